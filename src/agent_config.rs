@@ -0,0 +1,391 @@
+//! Module for generating the nomad agent configuration.
+//!
+//! The configuration is split like the HashiCorp packages do it: `nomad.hcl` holds the settings
+//! shared by every agent and `server.hcl` and `client.hcl` enable the server and the client.
+
+use std::fmt::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Roles an agent can be configured for.
+pub const AGENT_ROLES: &[&str] = &["server", "client", "dev"];
+/// Default datacenter of the agent.
+const DEFAULT_DATACENTER: &str = "dc1";
+/// Default region of the agent.
+const DEFAULT_REGION: &str = "global";
+/// Default bind address of servers and clients.
+const DEFAULT_BIND_ADDR: &str = "0.0.0.0";
+/// Default bind address of dev agents.
+const DEFAULT_DEV_BIND_ADDR: &str = "127.0.0.1";
+
+/// What an agent runs as.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AgentRole {
+    Server,
+    Client,
+    /// a single node that is both a server and a client
+    Dev,
+}
+
+impl FromStr for AgentRole {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "server" => Ok(Self::Server),
+            "client" => Ok(Self::Client),
+            "dev" => Ok(Self::Dev),
+            _ => anyhow::bail!(
+                "bad agent role {}, expected one of {:?}",
+                value,
+                AGENT_ROLES
+            ),
+        }
+    }
+}
+
+impl AgentRole {
+    /// Get the files of the other roles, which make the agent run as more than this role.
+    pub fn other_files(self) -> &'static [&'static str] {
+        match self {
+            Self::Server => &["client.hcl"],
+            Self::Client => &["server.hcl"],
+            Self::Dev => &[],
+        }
+    }
+}
+
+/// The nomad agent configuration.
+#[derive(Clone, Debug)]
+pub struct AgentConfig {
+    role: AgentRole,
+    datacenter: String,
+    region: String,
+    /// address the agent binds to, None for the default of the role
+    bind_addr: Option<String>,
+    /// the nomad data directory
+    data_dir: PathBuf,
+    /// number of servers to wait for before bootstrapping the cluster
+    bootstrap_expect: Option<u32>,
+    /// servers a client registers with
+    servers: Vec<String>,
+    /// addresses to join the cluster through
+    retry_join: Vec<String>,
+    /// plugin options, as `<plugin>.<key>=<value>`
+    plugin_options: Vec<String>,
+}
+
+impl AgentConfig {
+    /// Create a new AgentConfig.
+    pub fn new(role: AgentRole, data_dir: PathBuf) -> Self {
+        Self {
+            role,
+            datacenter: DEFAULT_DATACENTER.to_string(),
+            region: DEFAULT_REGION.to_string(),
+            bind_addr: None,
+            data_dir,
+            bootstrap_expect: None,
+            servers: Vec::new(),
+            retry_join: Vec::new(),
+            plugin_options: Vec::new(),
+        }
+    }
+
+    /// Set the datacenter and the region, keeping the defaults for None.
+    #[inline]
+    pub fn with_location(mut self, datacenter: Option<String>, region: Option<String>) -> Self {
+        if let Some(value) = datacenter {
+            self.datacenter = value;
+        }
+        if let Some(value) = region {
+            self.region = value;
+        }
+        self
+    }
+
+    /// Set the address the agent binds to.
+    #[inline]
+    pub fn with_bind_addr(mut self, bind_addr: Option<String>) -> Self {
+        self.bind_addr = bind_addr;
+        self
+    }
+
+    /// Set the number of servers to wait for before bootstrapping the cluster.
+    #[inline]
+    pub fn with_bootstrap_expect(mut self, bootstrap_expect: Option<u32>) -> Self {
+        self.bootstrap_expect = bootstrap_expect;
+        self
+    }
+
+    /// Set the servers a client registers with and the addresses to join the cluster through.
+    #[inline]
+    pub fn with_join(mut self, servers: Vec<String>, retry_join: Vec<String>) -> Self {
+        self.servers = servers;
+        self.retry_join = retry_join;
+        self
+    }
+
+    /// Set the plugin options, as `<plugin>.<key>=<value>`.
+    #[inline]
+    pub fn with_plugin_options(mut self, plugin_options: Vec<String>) -> Self {
+        self.plugin_options = plugin_options;
+        self
+    }
+
+    /// Render the configuration files, as pairs of file name and contents.
+    pub fn render(&self) -> anyhow::Result<Vec<(&'static str, String)>> {
+        match self.role {
+            AgentRole::Server if !self.servers.is_empty() => {
+                anyhow::bail!("servers only apply to clients, servers join with retry_join")
+            }
+            AgentRole::Client if self.bootstrap_expect.is_some() => {
+                anyhow::bail!("bootstrap_expect only applies to servers")
+            }
+            AgentRole::Dev if !self.servers.is_empty() || !self.retry_join.is_empty() => {
+                anyhow::bail!("a dev agent is a single node, it does not join other servers")
+            }
+            AgentRole::Dev if self.bootstrap_expect.unwrap_or(1) != 1 => {
+                anyhow::bail!("a dev agent is a single node, bootstrap_expect must be 1")
+            }
+            AgentRole::Client if self.servers.is_empty() && self.retry_join.is_empty() => {
+                log::warn!("no servers or retry_join given, the client can only find the servers through consul");
+            }
+            _ => {}
+        }
+
+        let bind_addr = self.bind_addr.as_deref().unwrap_or(match self.role {
+            AgentRole::Dev => DEFAULT_DEV_BIND_ADDR,
+            _ => DEFAULT_BIND_ADDR,
+        });
+        let mut nomad = Body::default();
+        nomad.attr("datacenter", hcl_string(self.datacenter.as_str()));
+        nomad.attr("region", hcl_string(self.region.as_str()));
+        nomad.attr(
+            "data_dir",
+            hcl_string(self.data_dir.display().to_string().as_str()),
+        );
+        nomad.attr("bind_addr", hcl_string(bind_addr));
+        for option in &self.plugin_options {
+            add_plugin_option(&mut nomad, option.as_str())?;
+        }
+        let mut files = vec![("nomad.hcl", nomad.render("init-config"))];
+
+        if self.role != AgentRole::Client {
+            let mut server = Body::default();
+            server.attr("enabled", "true".to_string());
+            server.attr(
+                "bootstrap_expect",
+                self.bootstrap_expect.unwrap_or(1).to_string(),
+            );
+            if !self.retry_join.is_empty() {
+                server.block("server_join", self.server_join());
+            }
+            let mut file = Body::default();
+            file.block("server", server);
+            files.push(("server.hcl", file.render("init-config")));
+        }
+
+        if self.role != AgentRole::Server {
+            let mut client = Body::default();
+            client.attr("enabled", "true".to_string());
+            if !self.servers.is_empty() {
+                client.attr("servers", hcl_list(&self.servers));
+            }
+            if !self.retry_join.is_empty() {
+                client.block("server_join", self.server_join());
+            }
+            let mut file = Body::default();
+            file.block("client", client);
+            files.push(("client.hcl", file.render("init-config")));
+        }
+
+        Ok(files)
+    }
+
+    /// Get the server_join block.
+    fn server_join(&self) -> Body {
+        let mut body = Body::default();
+        body.attr("retry_join", hcl_list(&self.retry_join));
+        body
+    }
+}
+
+/// Render the tls block enabling mutual TLS for http and rpc with the given files.
+pub fn render_tls(ca_file: &Path, cert_file: &Path, key_file: &Path) -> String {
+    let path = |value: &Path| hcl_string(value.display().to_string().as_str());
+    let mut tls = Body::default();
+    tls.attr("http", "true".to_string());
+    tls.attr("rpc", "true".to_string());
+    tls.attr("ca_file", path(ca_file));
+    tls.attr("cert_file", path(cert_file));
+    tls.attr("key_file", path(key_file));
+    tls.attr("verify_server_hostname", "true".to_string());
+    tls.attr("verify_https_client", "true".to_string());
+
+    let mut file = Body::default();
+    file.block("tls", tls);
+    file.render("tls cert create")
+}
+
+/// A body of an HCL file or block: attributes and blocks, in order.
+#[derive(Clone, Debug, Default)]
+struct Body {
+    entries: Vec<(String, Entry)>,
+}
+
+/// An entry of a body.
+#[derive(Clone, Debug)]
+enum Entry {
+    /// an attribute, with its rendered value
+    Attr(String),
+    Block(Body),
+}
+
+impl Body {
+    /// Add an attribute with a rendered value.
+    fn attr(&mut self, key: &str, value: String) {
+        self.entries.push((key.to_string(), Entry::Attr(value)));
+    }
+
+    /// Add a block, `header` being its type and labels.
+    fn block(&mut self, header: &str, body: Body) {
+        self.entries.push((header.to_string(), Entry::Block(body)));
+    }
+
+    /// Set an attribute in nested blocks, creating the blocks that are missing.
+    fn set(&mut self, path: &[&str], value: String) -> anyhow::Result<()> {
+        let (key, rest) = path
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("empty attribute path"))?;
+        let existing = self.entries.iter().position(|(name, _)| name == key);
+
+        if rest.is_empty() {
+            if existing.is_some() {
+                anyhow::bail!("{} is set twice", key);
+            }
+            self.attr(key, value);
+            return Ok(());
+        }
+
+        let index = match existing {
+            Some(value) => value,
+            None => {
+                self.block(key, Body::default());
+                self.entries.len() - 1
+            }
+        };
+        match &mut self.entries[index].1 {
+            Entry::Block(body) => body.set(rest, value),
+            Entry::Attr(_) => anyhow::bail!("{} is both an attribute and a block", key),
+        }
+    }
+
+    /// Render the body as a file generated by `command`.
+    fn render(&self, command: &str) -> String {
+        let mut out = format!("# Generated by nomadutil {}.\n\n", command);
+        self.write(&mut out, 0);
+        out
+    }
+
+    /// Write the body at `indent` levels, aligning the values of consecutive attributes.
+    fn write(&self, out: &mut String, indent: usize) {
+        let pad = "  ".repeat(indent);
+        for (index, (key, entry)) in self.entries.iter().enumerate() {
+            let after_block = index > 0 && matches!(self.entries[index - 1].1, Entry::Block(_));
+            match entry {
+                Entry::Attr(value) => {
+                    if after_block {
+                        out.push('\n');
+                    }
+                    let is_block = |(_, entry): &(String, Entry)| matches!(entry, Entry::Block(_));
+                    let start = self.entries[..index]
+                        .iter()
+                        .rposition(is_block)
+                        .map_or(0, |value| value + 1);
+                    let end = self.entries[index..]
+                        .iter()
+                        .position(is_block)
+                        .map_or(self.entries.len(), |value| index + value);
+                    let width = self.entries[start..end]
+                        .iter()
+                        .map(|(key, _)| key.len())
+                        .max()
+                        .unwrap_or_default();
+                    let _ = writeln!(out, "{}{:<width$} = {}", pad, key, value, width = width);
+                }
+                Entry::Block(body) => {
+                    if index > 0 {
+                        out.push('\n');
+                    }
+                    let _ = writeln!(out, "{}{} {{", pad, key);
+                    body.write(out, indent + 1);
+                    let _ = writeln!(out, "{}}}", pad);
+                }
+            }
+        }
+    }
+}
+
+/// Add a plugin option, `<plugin>.<key>=<value>`, to the body of nomad.hcl.
+///
+/// Dots in the key nest blocks, e.g. `docker.volumes.enabled=true`.
+fn add_plugin_option(body: &mut Body, option: &str) -> anyhow::Result<()> {
+    let bad = || {
+        anyhow::anyhow!(
+            "bad plugin option {:?}, expected <plugin>.<key>=<value>",
+            option
+        )
+    };
+    let (path, value) = option.split_once('=').ok_or_else(bad)?;
+    let mut segments = path.trim().split('.');
+    let plugin = segments
+        .next()
+        .filter(|value| !value.is_empty())
+        .ok_or_else(bad)?;
+    let keys: Vec<&str> = segments.collect();
+    if keys.is_empty() {
+        return Err(bad());
+    }
+    if let Some(key) = keys.iter().find(|key| {
+        key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    }) {
+        anyhow::bail!("bad key {:?} in plugin option {:?}", key, option);
+    }
+
+    let header = format!("plugin {}", hcl_string(plugin));
+    let mut path = vec![header.as_str(), "config"];
+    path.extend(keys);
+    body.set(path.as_slice(), hcl_value(value.trim()))
+        .map_err(|e| anyhow::anyhow!("bad plugin option {:?}: {}", option, e))
+}
+
+/// Render a value given on the command line: booleans and integers as they are, anything else
+/// as a string.
+fn hcl_value(value: &str) -> String {
+    if value == "true" || value == "false" || value.parse::<i64>().is_ok() {
+        value.to_string()
+    } else {
+        hcl_string(value)
+    }
+}
+
+/// Render a list of strings.
+fn hcl_list(values: &[String]) -> String {
+    let values: Vec<String> = values.iter().map(|value| hcl_string(value)).collect();
+    format!("[{}]", values.join(", "))
+}
+
+/// Render a string, escaping it and the template sequences of HCL.
+fn hcl_string(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace("${", "$${")
+        .replace("%{", "%%{");
+    format!("\"{}\"", escaped)
+}
@@ -1,16 +1,66 @@
 //! Module for getting the Nomad artifacts(zip, sums, sig).
 
-use crate::common::get_http_client;
+use crate::common::install_file;
+use crate::common::install_file_from;
+use crate::common::TempFile;
+use crate::http::is_transient_error;
+use crate::http::is_transient_status;
+use crate::http::HttpClient;
+
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
 
 use bytes::Bytes;
 
+use indicatif::ProgressBar;
+use indicatif::ProgressDrawTarget;
+use indicatif::ProgressStyle;
+
 use reqwest::header::ACCEPT;
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+
+use sha2::Digest;
+use sha2::Sha256;
+
+/// Size of the chunks in which the zip archive is downloaded and hashed.
+const CHUNK_SIZE: usize = 64 * 1024;
 
 /// Trait that defines common behaviour for remote artifacts that need to be downloaded.
 pub trait RemoteArtifact<T> {
-    /// Get the artifact.
-    fn get(version: &str) -> anyhow::Result<T>;
+    /// Get the artifact from the releases at `base_url`.
+    ///
+    /// Artifacts that are too large to hold in memory are staged in `dir`.
+    fn get(http: &HttpClient, base_url: &str, version: &str, dir: &Path) -> anyhow::Result<T>;
+    /// Get the file name of the artifact, as published in the releases.
+    fn file_name(version: &str) -> String;
+    /// Get the url of the artifact in the releases at `base_url`.
+    fn url(base_url: &str, version: &str) -> String {
+        format!(
+            "{}/nomad/{}/{}",
+            base_url,
+            version,
+            Self::file_name(version)
+        )
+    }
+}
+
+/// Trait that defines common behaviour for artifacts that are stored on disk.
+pub trait LocalArtifact<T> {
+    /// Load the artifact from a file.
+    fn load(path: &Path) -> anyhow::Result<T>;
+    /// Save the artifact to a file.
+    fn save(&self, path: &Path) -> anyhow::Result<()>;
 }
+
 /// Container for the Sha256Sums of the artifact.
 #[derive(Clone, Debug)]
 pub struct Sha256Sums {
@@ -24,23 +74,22 @@ pub struct Sha256SumsSig {
 }
 
 /// Container for the zip archive that contains the nomad binary.
-#[derive(Clone, Debug)]
+///
+/// The archive is kept on disk and only its digest is held in memory.
+#[derive(Debug)]
 pub struct NomadZip {
-    inner: Bytes,
+    path: PathBuf,
+    digest: Vec<u8>,
+    /// the file, if it was downloaded and should be removed when dropped
+    _temp: Option<TempFile>,
 }
 
 impl RemoteArtifact<Sha256Sums> for Sha256Sums {
-    fn get(version: &str) -> anyhow::Result<Self> {
-        let sums_res = get_http_client()
-            .get(
-                format!(
-                    "https://releases.hashicorp.com/nomad/{0}/nomad_{0}_SHA256SUMS",
-                    version
-                )
-                .as_str(),
-            )
-            .header(ACCEPT, "text/plain")
-            .send()?;
+    fn get(http: &HttpClient, base_url: &str, version: &str, _dir: &Path) -> anyhow::Result<Self> {
+        let url = Self::url(base_url, version);
+        let sums_res = http.send("getting the checksums", |client| {
+            client.get(url.as_str()).header(ACCEPT, "text/plain")
+        })?;
         if !sums_res.status().is_success() {
             anyhow::bail!(
                 "failed to get checksums for version {}: {}",
@@ -52,6 +101,22 @@ impl RemoteArtifact<Sha256Sums> for Sha256Sums {
 
         Ok(Self { inner: sums })
     }
+
+    fn file_name(version: &str) -> String {
+        format!("nomad_{}_SHA256SUMS", version)
+    }
+}
+
+impl LocalArtifact<Sha256Sums> for Sha256Sums {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            inner: fs::read_to_string(path)?,
+        })
+    }
+
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        install_file(path, self.inner.as_bytes(), 0o644, None)
+    }
 }
 
 impl Sha256Sums {
@@ -63,17 +128,13 @@ impl Sha256Sums {
 }
 
 impl RemoteArtifact<Sha256SumsSig> for Sha256SumsSig {
-    fn get(version: &str) -> anyhow::Result<Self> {
-        let sums_sig_res = get_http_client()
-            .get(
-                format!(
-                    "https://releases.hashicorp.com/nomad/{0}/nomad_{0}_SHA256SUMS.sig",
-                    version
-                )
-                .as_str(),
-            )
-            .header(ACCEPT, "application/octet-stream")
-            .send()?;
+    fn get(http: &HttpClient, base_url: &str, version: &str, _dir: &Path) -> anyhow::Result<Self> {
+        let url = Self::url(base_url, version);
+        let sums_sig_res = http.send("getting the checksums signature", |client| {
+            client
+                .get(url.as_str())
+                .header(ACCEPT, "application/octet-stream")
+        })?;
         if !sums_sig_res.status().is_success() {
             anyhow::bail!(
                 "failed to get checksums signature for version {}: {}",
@@ -85,6 +146,22 @@ impl RemoteArtifact<Sha256SumsSig> for Sha256SumsSig {
 
         Ok(Self { inner: sums_sig })
     }
+
+    fn file_name(version: &str) -> String {
+        format!("nomad_{}_SHA256SUMS.sig", version)
+    }
+}
+
+impl LocalArtifact<Sha256SumsSig> for Sha256SumsSig {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            inner: Bytes::from(fs::read(path)?),
+        })
+    }
+
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        install_file(path, self.inner.as_ref(), 0o644, None)
+    }
 }
 
 impl Sha256SumsSig {
@@ -95,36 +172,399 @@ impl Sha256SumsSig {
     }
 }
 
+/// Get the sha256 digest of a file, reading it in chunks.
+pub fn sha256_file(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
 impl RemoteArtifact<NomadZip> for NomadZip {
-    fn get(version: &str) -> anyhow::Result<Self> {
-        let zip_res = get_http_client()
-            .get(
-                format!(
-                    "https://releases.hashicorp.com/nomad/{0}/nomad_{0}_linux_{1}.zip",
-                    version,
-                    crate::ARCH
-                )
-                .as_str(),
+    fn get(http: &HttpClient, base_url: &str, version: &str, dir: &Path) -> anyhow::Result<Self> {
+        Self::download(http, base_url, version, dir, false)
+    }
+
+    fn file_name(version: &str) -> String {
+        format!("nomad_{}_linux_{}.zip", version, crate::ARCH)
+    }
+}
+
+impl LocalArtifact<NomadZip> for NomadZip {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            digest: sha256_file(path)?,
+            _temp: None,
+        })
+    }
+
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let dir = path
+            .parent()
+            .ok_or_else(|| anyhow::anyhow!("{} has no parent directory", path.display()))?;
+        let file_name = path
+            .file_name()
+            .and_then(|value| value.to_str())
+            .ok_or_else(|| anyhow::anyhow!("{} has no file name", path.display()))?;
+
+        // Avoid copying the archive if it is on the same file system.
+        let link = TempFile::new(dir, file_name);
+        if fs::hard_link(&self.path, link.path()).is_ok() {
+            fs::rename(link.path(), path)?;
+            return Ok(());
+        }
+        install_file_from(path, &self.path, 0o644, None)
+    }
+}
+
+impl NomadZip {
+    /// Download the zip archive from the releases at `base_url` into `dir`.
+    ///
+    /// The archive is streamed to disk and hashed as it arrives. A progress bar is shown when
+    /// stdout is a terminal. Transient failures are retried, resuming the download with a range
+    /// request.
+    ///
+    /// If `resumable` is set, a partial download is kept in `dir` when all attempts fail, and
+    /// picked up again by the next download of the same version.
+    pub fn download(
+        http: &HttpClient,
+        base_url: &str,
+        version: &str,
+        dir: &Path,
+        resumable: bool,
+    ) -> anyhow::Result<Self> {
+        let url = Self::url(base_url, version);
+        let (path, temp) = if resumable {
+            (
+                dir.join(format!(".{}.part", Self::file_name(version))),
+                None,
             )
-            .header(ACCEPT, "application/zip")
-            .send()?;
-        if !zip_res.status().is_success() {
-            anyhow::bail!(
+        } else {
+            let temp = TempFile::new(
+                dir,
+                format!("{}.download", Self::file_name(version)).as_str(),
+            );
+            (temp.path().to_path_buf(), Some(temp))
+        };
+
+        let mut download = ZipDownload {
+            path: path.as_path(),
+            hasher: Sha256::new(),
+            hashed: 0,
+            progress: None,
+        };
+        let mut attempt = 1;
+        loop {
+            match download.attempt(http, url.as_str(), version) {
+                Ok(()) => break,
+                Err(DownloadError::Transient(e)) if attempt < http.retry().attempts() => {
+                    http.retry()
+                        .wait(attempt, "downloading the nomad zip archive", &e);
+                }
+                Err(DownloadError::Transient(e)) | Err(DownloadError::Fatal(e)) => {
+                    if let Some(progress) = &download.progress {
+                        progress.abandon();
+                    }
+                    return Err(e);
+                }
+            }
+            attempt += 1;
+        }
+        if let Some(progress) = &download.progress {
+            progress.finish_and_clear();
+        }
+
+        Ok(Self {
+            digest: download.hasher.finalize().to_vec(),
+            _temp: Some(temp.unwrap_or_else(|| TempFile::at(path.clone()))),
+            path,
+        })
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn digest(&self) -> &[u8] {
+        self.digest.as_slice()
+    }
+}
+
+/// Why an attempt to download the zip archive failed.
+enum DownloadError {
+    /// the attempt may succeed if retried
+    Transient(anyhow::Error),
+    /// retrying will not help
+    Fatal(anyhow::Error),
+}
+
+/// State of a zip archive download that is kept across attempts.
+struct ZipDownload<'a> {
+    /// where the archive is downloaded to
+    path: &'a Path,
+    /// digest of the first `hashed` bytes of the file
+    hasher: Sha256,
+    hashed: u64,
+    progress: Option<ProgressBar>,
+}
+
+impl<'a> ZipDownload<'a> {
+    /// Make one attempt at downloading the rest of the archive.
+    fn attempt(
+        &mut self,
+        http: &HttpClient,
+        url: &str,
+        version: &str,
+    ) -> Result<(), DownloadError> {
+        let offset = fs::metadata(self.path)
+            .map(|value| value.len())
+            .unwrap_or(0);
+
+        let mut req = http
+            .client()
+            .map_err(DownloadError::Fatal)?
+            .get(url)
+            .header(ACCEPT, "application/zip");
+        if offset > 0 {
+            req = req.header(RANGE, format!("bytes={}-", offset));
+        }
+        let mut res = req.send().map_err(|e| {
+            if is_transient_error(&e) {
+                DownloadError::Transient(e.into())
+            } else {
+                DownloadError::Fatal(e.into())
+            }
+        })?;
+
+        let status = res.status();
+        let start = if status == StatusCode::PARTIAL_CONTENT {
+            log::info!("resuming the download at {} bytes", offset);
+            offset
+        } else if status == StatusCode::RANGE_NOT_SATISFIABLE {
+            // The partial file is not a prefix of the archive, start over.
+            let _ = fs::remove_file(self.path);
+            return Err(DownloadError::Transient(anyhow::anyhow!(
+                "the server rejected resuming at {} bytes",
+                offset
+            )));
+        } else if status.is_success() {
+            0
+        } else if is_transient_status(status) {
+            return Err(DownloadError::Transient(anyhow::anyhow!("{}", status)));
+        } else {
+            return Err(DownloadError::Fatal(anyhow::anyhow!(
                 "failed to get nomad zip archive for version {}: {}",
                 version,
-                zip_res.status()
-            );
+                status
+            )));
+        };
+
+        let fatal = |e: std::io::Error| DownloadError::Fatal(e.into());
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path)
+            .map_err(fatal)?;
+        file.set_len(start).map_err(fatal)?;
+        file.seek(SeekFrom::Start(start)).map_err(fatal)?;
+        if self.hashed != start {
+            self.rehash(start).map_err(fatal)?;
         }
-        let zip = zip_res.bytes()?;
 
-        Ok(Self { inner: zip })
+        let progress = self.progress.get_or_insert_with(|| {
+            if let Some(len) = res.content_length() {
+                let progress =
+                    ProgressBar::with_draw_target(start + len, ProgressDrawTarget::stdout());
+                progress.set_style(
+                    ProgressStyle::default_bar()
+                        .template("{bytes}/{total_bytes} [{bar:40}] {bytes_per_sec}, {eta} left")
+                        .progress_chars("=> "),
+                );
+                progress
+            } else {
+                let progress = ProgressBar::new_spinner();
+                progress.set_draw_target(ProgressDrawTarget::stdout());
+                progress.set_style(
+                    ProgressStyle::default_spinner().template("{spinner} {bytes} {bytes_per_sec}"),
+                );
+                progress
+            }
+        });
+        progress.set_position(start);
+
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            let n = res
+                .read(&mut buf)
+                .map_err(|e| DownloadError::Transient(e.into()))?;
+            if n == 0 {
+                break;
+            }
+            file.write_all(&buf[..n]).map_err(fatal)?;
+            self.hasher.update(&buf[..n]);
+            self.hashed += n as u64;
+            progress.inc(n as u64);
+        }
+        file.sync_all().map_err(fatal)?;
+
+        Ok(())
+    }
+
+    /// Hash the first `len` bytes of the file, e.g. a partial download left by an earlier run.
+    fn rehash(&mut self, len: u64) -> std::io::Result<()> {
+        self.hasher = Sha256::new();
+        self.hashed = 0;
+        let mut file = File::open(self.path)?.take(len);
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            self.hasher.update(&buf[..n]);
+            self.hashed += n as u64;
+        }
+        Ok(())
     }
 }
 
-impl NomadZip {
-    /// Get the inner value.
+/// Artifacts of a release that are available on disk, e.g. for air-gapped installs.
+#[derive(Clone, Debug)]
+pub struct LocalArtifacts {
+    version: String,
+    /// paths of the artifacts, keyed by their published file names
+    files: HashMap<String, PathBuf>,
+}
+
+impl LocalArtifacts {
+    /// Find the artifacts of a release in a directory.
+    ///
+    /// The files must have the same names as in the releases. If no version is given, it is
+    /// inferred from the name of the single zip archive for this platform in the directory.
+    pub fn from_dir(dir: &Path, version: Option<&str>) -> anyhow::Result<Self> {
+        let version = if let Some(value) = version {
+            value.to_string()
+        } else {
+            let suffix = format!("_linux_{}.zip", crate::ARCH);
+            let mut zips = Vec::new();
+            for entry in fs::read_dir(dir)? {
+                let name = entry?.file_name().to_string_lossy().to_string();
+                if name.starts_with("nomad_") && name.ends_with(suffix.as_str()) {
+                    zips.push(name);
+                }
+            }
+            match zips.as_slice() {
+                [zip] => infer_version(zip)?,
+                [] => anyhow::bail!(
+                    "no nomad zip archive for linux_{} in {}",
+                    crate::ARCH,
+                    dir.display()
+                ),
+                _ => anyhow::bail!(
+                    "multiple nomad zip archives in {}, the version must be given: {:?}",
+                    dir.display(),
+                    zips
+                ),
+            }
+        };
+
+        let mut files = HashMap::new();
+        for name in &[
+            NomadZip::file_name(version.as_str()),
+            Sha256Sums::file_name(version.as_str()),
+            Sha256SumsSig::file_name(version.as_str()),
+        ] {
+            let path = dir.join(name);
+            if path.is_file() {
+                files.insert(name.clone(), path);
+            }
+        }
+
+        Ok(Self { version, files })
+    }
+
+    /// Use explicitly given artifact files.
+    ///
+    /// If no version is given, it is inferred from the name of the zip archive.
+    pub fn from_files(
+        zip: &Path,
+        sums: Option<&Path>,
+        sig: Option<&Path>,
+        version: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let version = if let Some(value) = version {
+            value.to_string()
+        } else {
+            let name = zip
+                .file_name()
+                .and_then(|value| value.to_str())
+                .ok_or_else(|| anyhow::anyhow!("bad zip archive path {}", zip.display()))?;
+            infer_version(name)?
+        };
+
+        let mut files = HashMap::new();
+        files.insert(NomadZip::file_name(version.as_str()), zip.to_path_buf());
+        if let Some(value) = sums {
+            files.insert(Sha256Sums::file_name(version.as_str()), value.to_path_buf());
+        }
+        if let Some(value) = sig {
+            files.insert(
+                Sha256SumsSig::file_name(version.as_str()),
+                value.to_path_buf(),
+            );
+        }
+
+        Ok(Self { version, files })
+    }
+
+    #[allow(missing_docs, dead_code)]
     #[inline]
-    pub fn inner(&self) -> &[u8] {
-        self.inner.as_ref()
+    pub fn version(&self) -> &str {
+        self.version.as_str()
     }
+
+    /// Get the path of an artifact, if it was provided.
+    pub fn path<T: RemoteArtifact<T>>(&self) -> Option<&Path> {
+        self.files
+            .get(&T::file_name(self.version.as_str()))
+            .map(|value| value.as_path())
+    }
+
+    /// Load an artifact.
+    pub fn load<T: RemoteArtifact<T> + LocalArtifact<T>>(&self) -> anyhow::Result<T> {
+        let name = T::file_name(self.version.as_str());
+        let path = if let Some(value) = self.files.get(&name) {
+            value
+        } else {
+            anyhow::bail!("local artifact {} not provided", name);
+        };
+        let artifact = T::load(path)
+            .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+        log::info!("loaded {} from {}", name, path.display());
+        Ok(artifact)
+    }
+}
+
+/// Infer the version from the file name of an artifact, e.g. `nomad_1.0.1_linux_amd64.zip`.
+fn infer_version(file_name: &str) -> anyhow::Result<String> {
+    let version = file_name
+        .strip_prefix("nomad_")
+        .and_then(|value| value.split('_').next())
+        .filter(|value| !value.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("cannot infer the version from {}", file_name))?;
+    log::info!("inferred version {} from {}", version, file_name);
+    Ok(version.to_string())
 }
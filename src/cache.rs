@@ -0,0 +1,164 @@
+//! Module for the local cache of release artifacts.
+//!
+//! Artifacts are stored under `<root>/<version>/linux_<arch>/` with the same file names as in the
+//! releases. Cached artifacts are only trusted after they pass the same checks as downloaded ones.
+
+use crate::artifacts::*;
+use crate::common::compare_versions;
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Default directory for the cache.
+pub const DEFAULT_CACHE_DIR: &str = "/var/cache/nomadutil";
+
+/// The local cache of release artifacts.
+pub struct Cache {
+    root: PathBuf,
+}
+
+/// A cached version.
+pub struct CacheEntry {
+    version: String,
+    platforms: Vec<String>,
+    size: u64,
+}
+
+impl CacheEntry {
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn version(&self) -> &str {
+        self.version.as_str()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn platforms(&self) -> &[String] {
+        self.platforms.as_slice()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl Cache {
+    /// Create a new cache rooted at `root`.
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn root(&self) -> &Path {
+        self.root.as_path()
+    }
+
+    /// Get the directory holding the artifacts of a version for this platform.
+    pub fn dir(&self, version: &str) -> PathBuf {
+        self.root
+            .join(version)
+            .join(format!("linux_{}", crate::ARCH))
+    }
+
+    /// Load an artifact from the cache.
+    ///
+    /// Returns None if the artifact is not cached or cannot be read.
+    pub fn load<T: RemoteArtifact<T> + LocalArtifact<T>>(&self, version: &str) -> Option<T> {
+        let path = self.dir(version).join(T::file_name(version));
+        if !path.is_file() {
+            log::debug!("{} not cached", path.display());
+            return None;
+        }
+        match T::load(&path) {
+            Ok(value) => {
+                log::debug!("loaded {} from the cache", path.display());
+                Some(value)
+            }
+            Err(e) => {
+                log::warn!("failed to read {} from the cache: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Store an artifact in the cache.
+    ///
+    /// Failing to store an artifact is not fatal, the cache is only an optimisation.
+    pub fn store<T: RemoteArtifact<T> + LocalArtifact<T>>(&self, version: &str, artifact: &T) {
+        let dir = self.dir(version);
+        let path = dir.join(T::file_name(version));
+        let res = fs::create_dir_all(&dir)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| artifact.save(&path));
+        match res {
+            Ok(()) => log::debug!("stored {} in the cache", path.display()),
+            Err(e) => log::warn!("failed to store {} in the cache: {}", path.display(), e),
+        }
+    }
+
+    /// Get the cached versions, sorted from oldest to newest.
+    pub fn entries(&self) -> anyhow::Result<Vec<CacheEntry>> {
+        if !self.root.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut entries = Vec::new();
+        for version_entry in fs::read_dir(&self.root)? {
+            let version_entry = version_entry?;
+            if !version_entry.file_type()?.is_dir() {
+                continue;
+            }
+            let version = if let Some(value) = version_entry.file_name().to_str() {
+                value.to_string()
+            } else {
+                continue;
+            };
+
+            let mut platforms = Vec::new();
+            let mut size = 0;
+            for platform_entry in fs::read_dir(version_entry.path())? {
+                let platform_entry = platform_entry?;
+                if !platform_entry.file_type()?.is_dir() {
+                    continue;
+                }
+                platforms.push(platform_entry.file_name().to_string_lossy().to_string());
+                for file_entry in fs::read_dir(platform_entry.path())? {
+                    size += file_entry?.metadata()?.len();
+                }
+            }
+            platforms.sort();
+
+            entries.push(CacheEntry {
+                version,
+                platforms,
+                size,
+            });
+        }
+        entries.sort_by(|a, b| compare_versions(a.version(), b.version()));
+
+        Ok(entries)
+    }
+
+    /// Remove a version from the cache.
+    pub fn remove(&self, version: &str) -> anyhow::Result<()> {
+        let dir = self.root.join(version);
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        Ok(())
+    }
+
+    /// Remove everything from the cache.
+    pub fn clear(&self) -> anyhow::Result<()> {
+        for entry in self.entries()? {
+            self.remove(entry.version())?;
+        }
+        Ok(())
+    }
+}
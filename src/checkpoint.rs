@@ -1,8 +1,9 @@
 //! Module for interacting with APIs.
 
+use crate::http::HttpClient;
+
 use std::time::Duration;
 
-use reqwest::blocking::Client;
 use reqwest::header::ACCEPT;
 
 use serde::Deserialize;
@@ -116,14 +117,12 @@ impl CheckAlert {
     }
 }
 
-/// Make a check using the hashicorp checkpoint api.
-pub fn check(version: Option<&str>) -> anyhow::Result<CheckResponse> {
-    let client = Client::builder()
-        // https://github.com/hashicorp/go-checkpoint/blob/bbe6c410aa4be4194cb490a2bde8c3c33f295541/check.go#L101-L102
-        .timeout(Duration::from_secs(3))
-        .user_agent("github.com/cezarmathe/nomadutil")
-        .build()?;
-
+/// Make a check using the hashicorp checkpoint api at `base_url`.
+pub fn check(
+    http: &HttpClient,
+    base_url: &str,
+    version: Option<&str>,
+) -> anyhow::Result<CheckResponse> {
     let queries: Vec<(&str, &str)> = {
         let mut queries = Vec::new();
 
@@ -137,10 +136,17 @@ pub fn check(version: Option<&str>) -> anyhow::Result<CheckResponse> {
         queries
     };
 
-    Ok(client
-        .get("https://checkpoint-api.hashicorp.com/v1/check/nomad")
-        .header(ACCEPT, "application/json")
-        .query(queries.as_slice())
-        .send()?
-        .json()?)
+    let res = http.send("checkpoint", |client| {
+        client
+            .get(format!("{}/v1/check/nomad", base_url).as_str())
+            // https://github.com/hashicorp/go-checkpoint/blob/bbe6c410aa4be4194cb490a2bde8c3c33f295541/check.go#L101-L102
+            .timeout(Duration::from_secs(3))
+            .header(ACCEPT, "application/json")
+            .query(queries.as_slice())
+    })?;
+    if !res.status().is_success() {
+        anyhow::bail!("checkpoint failed: {}", res.status());
+    }
+
+    Ok(res.json()?)
 }
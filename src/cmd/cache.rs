@@ -0,0 +1,122 @@
+//! Manage the local cache of release artifacts.
+
+use crate::cache::Cache;
+use crate::cache::DEFAULT_CACHE_DIR;
+use crate::config::Config;
+
+use super::path_arg;
+use super::Command;
+
+use std::path::PathBuf;
+
+use clap::App;
+use clap::AppSettings;
+use clap::Arg;
+use clap::ArgMatches;
+use clap::SubCommand;
+
+/// What the cache command should do.
+enum CacheAction {
+    /// list the cached versions
+    List,
+    /// remove all but the newest versions
+    Prune { keep: usize },
+    /// remove everything
+    Clear,
+}
+
+/// Cache command.
+pub struct CacheCmd {
+    action: CacheAction,
+    /// directory of the artifact cache
+    cache_dir: PathBuf,
+}
+
+impl Command for CacheCmd {
+    const NAME: &'static str = "cache";
+
+    fn new(args: &ArgMatches, config: &Config) -> Self {
+        let action = match args.subcommand() {
+            ("prune", Some(args)) => CacheAction::Prune {
+                keep: args
+                    .value_of("keep")
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(0),
+            },
+            ("clear", _) => CacheAction::Clear,
+            _ => CacheAction::List,
+        };
+
+        Self {
+            action,
+            cache_dir: path_arg(args, "cache-dir", config.cache_dir(), DEFAULT_CACHE_DIR),
+        }
+    }
+
+    fn register(app: App<'static, 'static>) -> App<'static, 'static> {
+        let cache = SubCommand::with_name(Self::NAME)
+            .about("Manage the local cache of release artifacts.")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .arg(
+                Arg::with_name("cache-dir")
+                    .long("cache-dir")
+                    .takes_value(true)
+                    .help("Directory of the artifact cache. Defaults to /var/cache/nomadutil."),
+            )
+            .subcommand(SubCommand::with_name("list").about("List the cached versions."))
+            .subcommand(
+                SubCommand::with_name("prune")
+                    .about("Remove all but the newest cached versions.")
+                    .arg(
+                        Arg::with_name("keep")
+                            .long("keep")
+                            .takes_value(true)
+                            .required(true)
+                            .validator(|value| {
+                                value
+                                    .parse::<usize>()
+                                    .map(|_| ())
+                                    .map_err(|e| e.to_string())
+                            })
+                            .help("How many of the newest versions to keep."),
+                    ),
+            )
+            .subcommand(SubCommand::with_name("clear").about("Remove all cached versions."));
+        app.subcommand(cache)
+    }
+
+    fn run(&self) -> anyhow::Result<()> {
+        let cache = Cache::new(&self.cache_dir);
+        let entries = cache.entries()?;
+
+        match self.action {
+            CacheAction::List => {
+                if entries.is_empty() {
+                    log::info!("the cache at {} is empty", self.cache_dir.display());
+                }
+                for entry in entries {
+                    println!(
+                        "{} ({}) {:.1} MiB",
+                        entry.version(),
+                        entry.platforms().join(", "),
+                        entry.size() as f64 / (1024.0 * 1024.0)
+                    );
+                }
+            }
+            CacheAction::Prune { keep } => {
+                let remove = entries.len().saturating_sub(keep);
+                for entry in &entries[..remove] {
+                    cache.remove(entry.version())?;
+                    log::info!("removed version {} from the cache", entry.version());
+                }
+                log::info!("{} versions kept in the cache", entries.len() - remove);
+            }
+            CacheAction::Clear => {
+                cache.clear()?;
+                log::info!("removed {} versions from the cache", entries.len());
+            }
+        }
+
+        Ok(())
+    }
+}
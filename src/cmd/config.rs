@@ -0,0 +1,79 @@
+//! Inspect the Nomad agent configuration.
+
+use crate::config::Config;
+use crate::config_check::check_config;
+
+use super::path_arg;
+use super::Command;
+use super::DEFAULT_NOMAD_CONFIG_DIR;
+
+use std::path::PathBuf;
+
+use clap::App;
+use clap::AppSettings;
+use clap::Arg;
+use clap::ArgMatches;
+use clap::SubCommand;
+
+/// Config command.
+pub struct ConfigCmd {
+    /// the nomad configuration directory or file
+    config_dir: PathBuf,
+}
+
+impl Command for ConfigCmd {
+    const NAME: &'static str = "config";
+
+    fn new(args: &ArgMatches, config: &Config) -> Self {
+        // check is the only action for now.
+        let args = args.subcommand_matches("check").unwrap_or(args);
+        Self {
+            config_dir: path_arg(
+                args,
+                "config-dir",
+                config.config_dir(),
+                DEFAULT_NOMAD_CONFIG_DIR,
+            ),
+        }
+    }
+
+    fn register(app: App<'static, 'static>) -> App<'static, 'static> {
+        let config = SubCommand::with_name(Self::NAME)
+            .about("Inspect the nomad agent configuration.")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                SubCommand::with_name("check")
+                    .about("Check the syntax of the agent configuration files and look for common mistakes. Exits with 1 if the agent would not start.")
+                    .arg(
+                        Arg::with_name("config-dir")
+                            .long("config-dir")
+                            .takes_value(true)
+                            .help("The nomad configuration directory, or a single file. Defaults to /etc/nomad.d."),
+                    ),
+            );
+        app.subcommand(config)
+    }
+
+    fn run(&self) -> anyhow::Result<()> {
+        let findings = check_config(&self.config_dir)?;
+        for finding in &findings {
+            println!("{}", finding);
+        }
+
+        let errors = findings.iter().filter(|finding| finding.is_error()).count();
+        if errors > 0 {
+            anyhow::bail!(
+                "{} errors in the configuration at {}",
+                errors,
+                self.config_dir.display()
+            );
+        }
+        log::info!(
+            "the configuration at {} is valid, {} warnings",
+            self.config_dir.display(),
+            findings.len()
+        );
+
+        Ok(())
+    }
+}
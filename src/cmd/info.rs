@@ -1,5 +1,7 @@
 //! Get the version of nomadutil.
 
+use crate::config::Config;
+
 use super::Command;
 
 use clap::App;
@@ -12,7 +14,7 @@ pub struct InfoCmd {}
 impl Command for InfoCmd {
     const NAME: &'static str = "info";
 
-    fn new(_: &ArgMatches) -> Self {
+    fn new(_: &ArgMatches, _: &Config) -> Self {
         Self {}
     }
 
@@ -0,0 +1,190 @@
+//! Generate a baseline Nomad agent configuration.
+
+use crate::agent_config::AgentConfig;
+use crate::agent_config::AgentRole;
+use crate::agent_config::AGENT_ROLES;
+use crate::common::install_file;
+use crate::config::Config;
+
+use super::list_arg;
+use super::path_arg;
+use super::print_diff;
+use super::string_arg;
+use super::validate_positive;
+use super::Command;
+use super::DEFAULT_NOMAD_CONFIG_DIR;
+use super::DEFAULT_NOMAD_DATA_DIR;
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+
+use clap::App;
+use clap::Arg;
+use clap::ArgMatches;
+use clap::SubCommand;
+
+/// Init config command.
+pub struct InitConfigCmd {
+    /// role of the agent, None if not given
+    role: Option<String>,
+    /// the nomad configuration directory
+    config_dir: PathBuf,
+    /// the nomad data directory
+    data_dir: PathBuf,
+    datacenter: Option<String>,
+    region: Option<String>,
+    bind_addr: Option<String>,
+    bootstrap_expect: Option<u32>,
+    servers: Vec<String>,
+    retry_join: Vec<String>,
+    plugin_options: Vec<String>,
+    /// whether to overwrite files that differ
+    force: bool,
+}
+
+impl Command for InitConfigCmd {
+    const NAME: &'static str = "init-config";
+
+    fn new(args: &ArgMatches, config: &Config) -> Self {
+        Self {
+            role: string_arg(args, "role", config.role()),
+            config_dir: path_arg(
+                args,
+                "config-dir",
+                config.config_dir(),
+                DEFAULT_NOMAD_CONFIG_DIR,
+            ),
+            data_dir: path_arg(args, "data-dir", config.data_dir(), DEFAULT_NOMAD_DATA_DIR),
+            datacenter: string_arg(args, "datacenter", config.datacenter()),
+            region: string_arg(args, "region", config.region()),
+            bind_addr: string_arg(args, "bind-addr", config.bind_addr()),
+            bootstrap_expect: args
+                .value_of("bootstrap-expect")
+                .and_then(|value| value.parse().ok())
+                .or_else(|| config.bootstrap_expect()),
+            servers: list_arg(args, "servers", config.servers()),
+            retry_join: list_arg(args, "retry-join", config.retry_join()),
+            plugin_options: list_arg(args, "plugin-option", config.plugin_options()),
+            force: args.is_present("force"),
+        }
+    }
+
+    fn register(app: App<'static, 'static>) -> App<'static, 'static> {
+        let init_config = SubCommand::with_name(Self::NAME)
+            .about("Generate a baseline nomad agent configuration.")
+            .arg(Arg::with_name("role").long("role").takes_value(true).possible_values(AGENT_ROLES).help(
+                "What the agent runs as. server and client write server.hcl or client.hcl next to nomad.hcl, dev writes both for a single node cluster.",
+            ))
+            .arg(Arg::with_name("config-dir").long("config-dir").takes_value(true).help(
+                "The nomad configuration directory. Defaults to /etc/nomad.d.",
+            ))
+            .arg(Arg::with_name("data-dir").long("data-dir").takes_value(true).help(
+                "The nomad data directory. Defaults to /opt/nomad/data.",
+            ))
+            .arg(Arg::with_name("datacenter").long("datacenter").takes_value(true).help(
+                "Datacenter of the agent. Defaults to dc1.",
+            ))
+            .arg(Arg::with_name("region").long("region").takes_value(true).help(
+                "Region of the agent. Defaults to global.",
+            ))
+            .arg(Arg::with_name("bind-addr").long("bind-addr").takes_value(true).help(
+                "Address the agent binds to. Defaults to 0.0.0.0, or 127.0.0.1 for dev agents.",
+            ))
+            .arg(Arg::with_name("bootstrap-expect").long("bootstrap-expect").takes_value(true).validator(validate_positive).help(
+                "Number of servers to wait for before bootstrapping the cluster. Defaults to 1.",
+            ))
+            .arg(Arg::with_name("servers").long("servers").takes_value(true).multiple(true).use_delimiter(true).help(
+                "Comma separated servers a client registers with.",
+            ))
+            .arg(Arg::with_name("retry-join").long("retry-join").takes_value(true).multiple(true).use_delimiter(true).help(
+                "Comma separated addresses or cloud auto-join strings to join the cluster through.",
+            ))
+            .arg(Arg::with_name("plugin-option").long("plugin-option").takes_value(true).multiple(true).number_of_values(1).help(
+                "A plugin option, as <plugin>.<key>=<value>, e.g. docker.allow_privileged=true or docker.volumes.enabled=true. Can be used multiple times.",
+            ))
+            .arg(Arg::with_name("force").long("force").help(
+                "Overwrite configuration files that differ from the generated ones.",
+            ));
+        app.subcommand(init_config)
+    }
+
+    fn run(&self) -> anyhow::Result<()> {
+        let role: AgentRole = self
+            .role
+            .as_deref()
+            .ok_or_else(|| {
+                anyhow::anyhow!("no agent role given, use --role or role in the configuration")
+            })?
+            .parse()?;
+        let files = AgentConfig::new(role, self.data_dir.clone())
+            .with_location(self.datacenter.clone(), self.region.clone())
+            .with_bind_addr(self.bind_addr.clone())
+            .with_bootstrap_expect(self.bootstrap_expect)
+            .with_join(self.servers.clone(), self.retry_join.clone())
+            .with_plugin_options(self.plugin_options.clone())
+            .render()?;
+
+        // Check every file before writing any, so a refusal leaves the directory untouched.
+        let mut changes = Vec::new();
+        let mut refused = Vec::new();
+        for (name, contents) in files {
+            let path = self.config_dir.join(name);
+            let current = match fs::read_to_string(&path) {
+                Ok(value) => Some(value),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => anyhow::bail!("failed to read {}: {}", path.display(), e),
+            };
+            if current.as_deref() == Some(contents.as_str()) {
+                log::info!("{} is unchanged", path.display());
+                continue;
+            }
+            print_diff(&path, current.as_deref(), contents.as_str());
+            if current.is_some() && !self.force {
+                refused.push(path.display().to_string());
+            }
+            changes.push((path, contents, current.is_some()));
+        }
+        if !refused.is_empty() {
+            anyhow::bail!(
+                "{} already exist and differ, use --force to overwrite them",
+                refused.join(", ")
+            );
+        }
+
+        if !changes.is_empty() && !self.config_dir.is_dir() {
+            fs::create_dir_all(&self.config_dir)?;
+            log::info!("created {}", self.config_dir.display());
+        }
+        // New files take the owner and group of the directory, e.g. root and the agent's group
+        // after install --setup-host, so the agent can read them.
+        let metadata = fs::metadata(&self.config_dir)?;
+        for (path, contents, exists) in changes {
+            let owner = if exists {
+                None
+            } else {
+                Some((metadata.uid(), metadata.gid()))
+            };
+            install_file(&path, contents.as_bytes(), 0o640, owner)?;
+            if exists {
+                log::info!("replaced {}", path.display());
+            } else {
+                log::info!("created {}", path.display());
+            }
+        }
+
+        for name in role.other_files() {
+            let path = self.config_dir.join(name);
+            if path.exists() {
+                log::warn!(
+                    "{} exists and still configures the agent, remove it unless the agent should run as more than a {}",
+                    path.display(),
+                    self.role.as_deref().unwrap_or_default()
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,14 +1,52 @@
 //! Install Nomad.
 
-use crate::checkpoint::check;
+use crate::artifacts::*;
+use crate::cache::Cache;
+use crate::common::install_file;
+use crate::common::install_file_from;
 use crate::common::opt_string_to_opt_str;
+use crate::common::resolve_out;
+use crate::common::Endpoints;
+use crate::config::Config;
+use crate::config_check::check_config;
+use crate::constraint::VersionConstraint;
+use crate::host::HostSetup;
+use crate::host::DEFAULT_AGENT_USER;
+use crate::host::SETUP_ITEMS;
+use crate::layout::VersionedLayout;
+use crate::layout::DEFAULT_PREFIX;
 use crate::releases::*;
+use crate::state::Install;
+use crate::state::State;
+use crate::systemd::render_unit;
+use crate::systemd::unit_name;
+use crate::systemd::Systemctl;
+use crate::systemd::Systemd;
+use crate::systemd::UnitVars;
+use crate::systemd::DEFAULT_SERVICE_TEMPLATE;
 
+use super::cache_args;
+use super::cache_dir_arg;
+use super::endpoint_args;
+use super::endpoints_arg;
+use super::keyrings_arg;
+use super::negating_args;
+use super::path_arg;
+use super::print_diff;
+use super::require_fingerprint_arg;
+use super::resolve_version;
+use super::sig_args;
+use super::staging_dir;
+use super::state_file_arg;
+use super::string_arg;
+use super::switch_arg;
 use super::Command;
+use super::DEFAULT_NOMAD_CONFIG_DIR;
+use super::DEFAULT_NOMAD_DATA_DIR;
+use super::DEFAULT_NOMAD_OUT;
+use super::DEFAULT_NOMAD_SERVICE_OUT;
 
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::os::unix::fs::OpenOptionsExt;
+use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -17,11 +55,6 @@ use clap::Arg;
 use clap::ArgMatches;
 use clap::SubCommand;
 
-/// Default output dir for the nomad binary.
-const DEFAULT_NOMAD_OUT: &str = "/usr/local/bin";
-/// Default output dir for the nomad service file.
-const DEFAULT_NOMAD_SERVICE_OUT: &str = "/etc/systemd/system";
-
 /// Install command.
 pub struct InstallCmd {
     version: Option<String>,
@@ -37,32 +70,131 @@ pub struct InstallCmd {
     ignore_alerts: bool,
     /// whether to ignore if a version is outdated or not
     ignore_outdated: bool,
+    /// whether to use the versioned install layout
+    versioned: bool,
+    /// prefix for the versioned install layout
+    prefix: PathBuf,
+    /// directory of the artifact cache, if caching is enabled
+    cache_dir: Option<PathBuf>,
+    /// the remote endpoints
+    endpoints: Endpoints,
+    /// armoured keyrings to trust in addition to the HashiCorp keys
+    keyrings: Vec<PathBuf>,
+    /// fingerprint of the key that must have signed the shasums
+    require_fingerprint: Option<String>,
+    /// directory holding the release artifacts, for offline installs
+    from_dir: Option<PathBuf>,
+    /// the zip archive, for offline installs
+    zip: Option<PathBuf>,
+    /// the sha256sums file, for offline installs
+    sums: Option<PathBuf>,
+    /// the sha256sums signature, for offline installs
+    sig: Option<PathBuf>,
+    /// the install manifest
+    state_file: PathBuf,
+    /// only show what would be done
+    dry_run: bool,
+    /// template of the nomad service file, None for the built-in one
+    service_template: Option<PathBuf>,
+    /// variables for the service file template
+    unit: UnitVars,
+    /// whether to enable the nomad unit
+    enable: bool,
+    /// whether to start the nomad unit
+    start: bool,
+    /// how to set up the host, if it should be set up
+    host_setup: Option<HostSetup>,
+    /// how systemd is reached
+    systemd: Box<dyn Systemd>,
 }
 
 impl Command for InstallCmd {
     const NAME: &'static str = "install";
 
-    fn new(args: &ArgMatches) -> Self {
+    fn new(args: &ArgMatches, config: &Config) -> Self {
+        let setup_host = args.is_present("setup-host");
+        let config_dir = path_arg(
+            args,
+            "config-dir",
+            config.config_dir(),
+            DEFAULT_NOMAD_CONFIG_DIR,
+        );
+        let data_dir = path_arg(args, "data-dir", config.data_dir(), DEFAULT_NOMAD_DATA_DIR);
+        let user = string_arg(args, "service-user", config.service_user())
+            .or_else(|| setup_host.then(|| DEFAULT_AGENT_USER.to_string()));
+        let group = string_arg(args, "service-group", config.service_group());
+        let host_setup = if setup_host {
+            Some(HostSetup::new(
+                user.clone(),
+                group.clone(),
+                config_dir.clone(),
+                data_dir.clone(),
+                args.values_of("skip-setup")
+                    .map(|values| values.map(|value| value.to_string()).collect())
+                    .unwrap_or_default(),
+            ))
+        } else {
+            None
+        };
+
         Self {
-            version: if let Some(value) = args.value_of("version") {
-                Some(value.to_string())
-            } else {
-                None
-            },
-            check_integrity: !args.is_present("skip-sums"),
-            check_sig: !args.is_present("skip-sig"),
-            out: if let Some(value) = args.value_of("out") {
-                value.into()
-            } else {
-                PathBuf::from(DEFAULT_NOMAD_OUT)
-            },
-            service_out: if let Some(value) = args.value_of("service-out") {
-                value.into()
-            } else {
-                PathBuf::from(DEFAULT_NOMAD_SERVICE_OUT)
-            },
-            ignore_alerts: args.is_present("ignore-alerts"),
-            ignore_outdated: args.is_present("ignore-outdated"),
+            version: args
+                .value_of("version")
+                .or_else(|| config.version())
+                .map(|value| value.to_string()),
+            check_integrity: !switch_arg(args, "skip-sums", "check-sums", config.skip_sums()),
+            check_sig: !switch_arg(args, "skip-sig", "check-sig", config.skip_sig()),
+            out: path_arg(args, "out", config.out(), DEFAULT_NOMAD_OUT),
+            service_out: path_arg(
+                args,
+                "service-out",
+                config.service_out(),
+                DEFAULT_NOMAD_SERVICE_OUT,
+            ),
+            ignore_alerts: switch_arg(
+                args,
+                "ignore-alerts",
+                "no-ignore-alerts",
+                config.ignore_alerts(),
+            ),
+            ignore_outdated: switch_arg(
+                args,
+                "ignore-outdated",
+                "no-ignore-outdated",
+                config.ignore_outdated(),
+            ),
+            versioned: switch_arg(args, "versioned", "no-versioned", config.versioned()),
+            prefix: path_arg(args, "prefix", config.prefix(), DEFAULT_PREFIX),
+            cache_dir: cache_dir_arg(args, config),
+            endpoints: endpoints_arg(args, config),
+            keyrings: keyrings_arg(args, config),
+            require_fingerprint: require_fingerprint_arg(args, config),
+            from_dir: args.value_of("from-dir").map(PathBuf::from),
+            zip: args.value_of("zip").map(PathBuf::from),
+            sums: args.value_of("sums").map(PathBuf::from),
+            sig: args.value_of("sig").map(PathBuf::from),
+            state_file: state_file_arg(config),
+            dry_run: args.is_present("dry-run"),
+            service_template: args
+                .value_of("service-template")
+                .map(PathBuf::from)
+                .or_else(|| config.service_template().map(|value| value.to_path_buf())),
+            unit: UnitVars::new(config_dir, data_dir)
+                .with_user(user, group)
+                .with_environment_file(
+                    args.value_of("environment-file")
+                        .map(PathBuf::from)
+                        .or_else(|| config.environment_file().map(|value| value.to_path_buf())),
+                )
+                .with_agent_args(string_arg(args, "agent-args", config.agent_args()))
+                .with_limits(
+                    string_arg(args, "limit-nofile", config.limit_nofile()),
+                    string_arg(args, "limit-nproc", config.limit_nproc()),
+                ),
+            enable: args.is_present("enable"),
+            start: args.is_present("start"),
+            host_setup,
+            systemd: Box::new(Systemctl),
         }
     }
 
@@ -70,7 +202,7 @@ impl Command for InstallCmd {
         let install = SubCommand::with_name(Self::NAME)
             .about("Install Nomad.")
             .arg(Arg::with_name("version").long("version").takes_value(true).help(
-                "The version of Nomad to install. If omitted, the latest version shall be used.",
+                "The version of Nomad to install, or a constraint such as \"~> 1.6\", \">= 1.5, < 1.7\", \"1.6.x\" or \"latest-patch-of 1.5\". If omitted, the latest version shall be used.",
             ))
             .arg(Arg::with_name("skip-sums").long("skip-sums").help(
                 "Skip checking the sha256sums on the zip archive.",
@@ -89,134 +221,361 @@ impl Command for InstallCmd {
             ))
             .arg(Arg::with_name("ignore-outdated").long("ignore-outdated").help(
                 "Ignore whether a version is outdated.",
+            ))
+            .arg(Arg::with_name("versioned").long("versioned").help(
+                "Install into <prefix>/nomad/<version>/nomad and make the nomad binary a symlink to it.",
+            ))
+            .arg(Arg::with_name("prefix").long("prefix").takes_value(true).requires("versioned").help(
+                "Prefix for the versioned install layout. Defaults to /opt.",
+            ))
+            .args(&negating_args())
+            .args(&cache_args())
+            .args(&endpoint_args())
+            .args(&sig_args())
+            .arg(Arg::with_name("from-dir").long("from-dir").takes_value(true).conflicts_with_all(&["zip", "sums", "sig"]).help(
+                "Install offline from a directory holding the zip archive, sha256sums and signature, named as in the releases.",
+            ))
+            .arg(Arg::with_name("zip").long("zip").takes_value(true).help(
+                "Install offline from this zip archive.",
+            ))
+            .arg(Arg::with_name("sums").long("sums").takes_value(true).requires("zip").help(
+                "The sha256sums file to check the offline zip archive against.",
+            ))
+            .arg(Arg::with_name("sig").long("sig").takes_value(true).requires("sums").help(
+                "The signature of the offline sha256sums file.",
+            ))
+            .arg(Arg::with_name("service-template").long("service-template").takes_value(true).help(
+                "Template of the nomad systemd service file. {{ name }} is replaced by the variables binary, config_dir, data_dir, user, group, environment_file, agent_args, limit_nofile and limit_nproc; lines using an unset variable are left out.",
+            ))
+            .arg(Arg::with_name("config-dir").long("config-dir").takes_value(true).help(
+                "The nomad configuration directory, passed to the agent with -config. Defaults to /etc/nomad.d.",
+            ))
+            .arg(Arg::with_name("data-dir").long("data-dir").takes_value(true).help(
+                "The nomad data directory, for service file templates. Defaults to /opt/nomad/data.",
+            ))
+            .arg(Arg::with_name("service-user").long("service-user").takes_value(true).help(
+                "The user the nomad agent runs as.",
+            ))
+            .arg(Arg::with_name("service-group").long("service-group").takes_value(true).help(
+                "The group the nomad agent runs as.",
+            ))
+            .arg(Arg::with_name("environment-file").long("environment-file").takes_value(true).help(
+                "File with environment variables for the nomad agent, e.g. tokens.",
+            ))
+            .arg(Arg::with_name("agent-args").long("agent-args").takes_value(true).allow_hyphen_values(true).help(
+                "Extra arguments for nomad agent.",
+            ))
+            .arg(Arg::with_name("limit-nofile").long("limit-nofile").takes_value(true).help(
+                "LimitNOFILE of the nomad agent. Defaults to infinity.",
+            ))
+            .arg(Arg::with_name("limit-nproc").long("limit-nproc").takes_value(true).help(
+                "LimitNPROC of the nomad agent. Defaults to infinity.",
+            ))
+            .arg(Arg::with_name("enable").long("enable").help(
+                "Reload systemd and enable the nomad unit.",
+            ))
+            .arg(Arg::with_name("start").long("start").help(
+                "Reload systemd and start the nomad unit, or restart it if it is running. Fails with the last journal lines if it does not stay up.",
+            ))
+            .arg(Arg::with_name("setup-host").long("setup-host").help(
+                "Set up the host for the nomad agent: create its system user and group, unless --service-user is root, and create the configuration directory (mode 750, owned by root and the agent's group) and the data directory (mode 700, owned by the agent's user). The agent runs as nomad unless --service-user is given. Only what is missing or different is changed.",
+            ))
+            .arg(Arg::with_name("skip-setup").long("skip-setup").takes_value(true).multiple(true).number_of_values(1).possible_values(SETUP_ITEMS).requires("setup-host").help(
+                "Leave a part of the host setup alone. Can be given more than once.",
+            ))
+            .arg(Arg::with_name("dry-run").long("dry-run").help(
+                "Show the artifacts that would be fetched and the files that would be written, with a diff of the service file, without downloading the zip archive or writing anything.",
             ));
         app.subcommand(install)
     }
 
     fn run(&self) -> anyhow::Result<()> {
-        let version = opt_string_to_opt_str(&self.version);
-        let res = check(version)?;
-        let version: &str = if let Some(value) = version {
-            value
+        let mut state = State::load(&self.state_file)?;
+        let local = self.local_artifacts()?;
+        let version = if let Some(local) = &local {
+            log::info!("installing from local artifacts, not consulting checkpoint");
+            local.version().to_string()
         } else {
-            res.current_version()
+            resolve_version(
+                &self.endpoints,
+                opt_string_to_opt_str(&self.version),
+                self.ignore_outdated,
+                self.ignore_alerts,
+            )?
         };
-        if res.outdated() {
-            if self.ignore_outdated {
-                log::warn!(
-                    "checkpoint says version {} is outdated, newest is {}, ignoring",
-                    version,
-                    res.current_version()
-                );
-            } else {
-                anyhow::bail!(
-                    "checkpoint says version {} is outdated, newest is {}",
-                    version,
-                    res.current_version()
-                );
-            }
-        } else {
-            log::info!("{} is the latest release", version);
-        }
-        if !res.alerts().is_empty() {
-            if self.ignore_alerts {
-                log::warn!("alerts: {:?}; ignoring", res.alerts());
-            } else {
-                anyhow::bail!("alerts: {:?}", res.alerts());
-            }
+        let version = version.as_str();
+
+        if self.dry_run {
+            return self.plan(version, local.as_ref());
         }
 
         log::info!("attempting to install version {}", version);
 
-        let bin = get(version, Some(ReleaseGetOpts::from(self)))?;
+        let out = resolve_out(&self.out, "nomad")?;
+        let layout = VersionedLayout::new(&self.prefix);
+        let opts = ReleaseGetOpts::from(self).with_staging_dir(staging_dir(
+            &out,
+            self.versioned.then_some((&layout, version)),
+        )?);
+        let opts = if let Some(local) = local {
+            opts.with_local(local)
+        } else {
+            opts
+        };
+        let bin = get(version, Some(opts))?;
         log::info!("nomad binary ready for installation");
 
-        let out = {
-            let mut out = if !self.out.is_absolute() {
-                self.out.canonicalize()?
-            } else {
-                self.out.clone()
-            };
-            if out.is_dir() {
-                out.push("nomad");
-            }
-            out
+        let service_file_contents = self.service_file_contents(&out)?;
+        if let Some(host_setup) = &self.host_setup {
+            host_setup.apply()?;
+        }
+
+        let bin_out = if self.versioned {
+            layout.binary(version)
+        } else {
+            out.clone()
         };
 
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .mode(0o755)
-            .open(&out)?;
-        let written = file.write(bin.as_ref())?;
-        if written != bin.len() {
-            anyhow::bail!(
-                "nomad binary: written {} bytes instead, not {}",
-                written,
-                bin.len()
-            );
+        install_file_from(&bin_out, bin.path(), 0o755, None)?;
+        log::info!("nomad binary installed");
+
+        if self.versioned {
+            layout.activate(version, &out)?;
+            log::info!("{} now points to {}", out.display(), bin_out.display());
         }
 
-        log::info!("nomad binary installed");
+        let service_out = resolve_out(&self.service_out, "nomad.service")?;
+
+        install_file(&service_out, service_file_contents.as_bytes(), 0o644, None)?;
+
+        log::info!("nomad service file installed");
 
-        let service_file_contents = format!(
-            "[Unit]
-Description=Nomad
-Documentation=https://nomadproject.io/docs/
-Wants=network-online.target
-After=network-online.target
-
-[Service]
-ExecReload=/bin/kill -HUP $MAINPID
-ExecStart={} agent -config /etc/nomad.d
-KillMode=process
-KillSignal=SIGINT
-LimitNOFILE=infinity
-LimitNPROC=infinity
-Restart=on-failure
-RestartSec=2
-StartLimitBurst=3
-StartLimitIntervalSec=10
-TasksMax=infinity
-
-[Install]
-WantedBy=multi-user.target",
-            &out.display()
+        let mut files = vec![bin_out.clone(), out.clone(), service_out.clone()];
+        files.dedup();
+        if let Some(previous) = state.get(&out) {
+            files.extend(previous.files().iter().cloned());
+        }
+        state.record(
+            Install::new(out, version, &bin, self.versioned)?
+                .with_service_file(service_out.clone())
+                .with_files(files),
         );
+        state.save(&self.state_file)?;
+        log::info!("installation recorded in {}", self.state_file.display());
+
+        if self.enable || self.start {
+            let unit = unit_name(&service_out)?;
+            self.systemd.daemon_reload()?;
+            log::info!("reloaded the systemd unit files");
 
-        let service_out = {
-            let mut service_out = if !self.service_out.is_absolute() {
-                self.service_out.canonicalize()?
+            if self.enable {
+                self.systemd.unit_action("enable", unit)?;
+                log::info!("enabled {}", unit);
+            }
+            let unit_state = if self.start {
+                self.check_config(unit)?;
+                self.systemd.start_checked(unit)?
             } else {
-                self.service_out.clone()
+                self.systemd.state(unit)?
             };
-            if service_out.is_dir() {
-                service_out.push("nomad.service");
-            }
-            service_out
-        };
+            log::info!("{} is {}", unit, unit_state);
+        }
+
+        Ok(())
+    }
+}
 
-        let mut service_file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .mode(0o644)
-            .open(&service_out)?;
-        let service_written = service_file.write(service_file_contents.as_bytes())?;
-        if service_written != service_file_contents.len() {
+impl InstallCmd {
+    /// Render the nomad service file, for a nomad binary at `out`.
+    fn service_file_contents(&self, out: &Path) -> anyhow::Result<String> {
+        let vars = self.unit.clone().with_binary(out.to_path_buf());
+        if let Some(path) = &self.service_template {
+            let template = fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+            render_unit(template.as_str(), &vars)
+                .map_err(|e| anyhow::anyhow!("bad service template {}: {}", path.display(), e))
+        } else {
+            render_unit(DEFAULT_SERVICE_TEMPLATE, &vars)
+        }
+    }
+
+    /// Check the agent configuration, failing before `unit` is (re)started if it has errors.
+    fn check_config(&self, unit: &str) -> anyhow::Result<()> {
+        let config_dir = self.unit.config_dir();
+        let findings = check_config(config_dir)?;
+        for finding in &findings {
+            if finding.is_error() {
+                log::error!("{}", finding);
+            } else {
+                log::warn!("{}", finding);
+            }
+        }
+        if findings.iter().any(|finding| finding.is_error()) {
             anyhow::bail!(
-                "nomad service file: written {} bytes instead, not {}",
-                service_written,
-                service_file_contents.len()
+                "not starting {}, the configuration in {} has errors",
+                unit,
+                config_dir.display()
             );
         }
+        Ok(())
+    }
 
-        log::info!("nomad service file installed");
+    /// Print what installing `version` would fetch and write, without doing it.
+    fn plan(&self, version: &str, local: Option<&LocalArtifacts>) -> anyhow::Result<()> {
+        println!("nomad {} would be installed", version);
+
+        println!("artifacts:");
+        if let Some(local) = local {
+            let mut paths = vec![(NomadZip::file_name(version), local.path::<NomadZip>())];
+            if self.check_integrity {
+                paths.push((Sha256Sums::file_name(version), local.path::<Sha256Sums>()));
+                if self.check_sig {
+                    paths.push((
+                        Sha256SumsSig::file_name(version),
+                        local.path::<Sha256SumsSig>(),
+                    ));
+                }
+            }
+            for (name, path) in paths {
+                match path {
+                    Some(value) => println!("  {}", value.display()),
+                    None => println!("  {} (not provided)", name),
+                }
+            }
+        } else {
+            let releases_url = self.endpoints.releases_url();
+            let mut urls = Vec::new();
+            if self.check_integrity {
+                urls.push((
+                    Sha256Sums::url(releases_url, version),
+                    Sha256Sums::file_name(version),
+                ));
+                if self.check_sig {
+                    urls.push((
+                        Sha256SumsSig::url(releases_url, version),
+                        Sha256SumsSig::file_name(version),
+                    ));
+                }
+            }
+            urls.push((
+                NomadZip::url(releases_url, version),
+                NomadZip::file_name(version),
+            ));
+
+            // Cached artifacts are only used when integrity checks are enabled.
+            let cache = self
+                .cache_dir
+                .as_deref()
+                .filter(|_| self.check_integrity)
+                .map(Cache::new);
+            for (url, name) in urls {
+                let cached = cache
+                    .as_ref()
+                    .map(|cache| cache.dir(version).join(name))
+                    .filter(|path| path.is_file());
+                match cached {
+                    Some(path) => println!("  {} (cached at {})", url, path.display()),
+                    None => println!("  {}", url),
+                }
+            }
+        }
+
+        let out = resolve_out(&self.out, "nomad")?;
+        let service_out = resolve_out(&self.service_out, "nomad.service")?;
+        println!("files:");
+        if self.versioned {
+            let layout = VersionedLayout::new(&self.prefix);
+            let bin_out = layout.binary(version);
+            println!("  {:<7} {}", plan_action(&bin_out), bin_out.display());
+            println!(
+                "  {:<7} {} (symlink to {})",
+                plan_action(&out),
+                out.display(),
+                bin_out.display()
+            );
+        } else {
+            println!("  {:<7} {}", plan_action(&out), out.display());
+        }
+        println!(
+            "  {:<7} {}",
+            plan_action(&service_out),
+            service_out.display()
+        );
+        println!(
+            "  {:<7} {} (install manifest)",
+            plan_action(&self.state_file),
+            self.state_file.display()
+        );
+
+        if let Some(host_setup) = &self.host_setup {
+            let actions = host_setup.actions()?;
+            println!("host setup:");
+            if actions.is_empty() {
+                println!("  nothing to do, the host is already set up");
+            }
+            for action in actions {
+                println!("  {}", action);
+            }
+        }
+
+        if self.enable || self.start {
+            let unit = unit_name(&service_out)?;
+            println!("systemd:");
+            println!("  daemon-reload");
+            if self.enable {
+                println!("  enable {}", unit);
+            }
+            if self.start {
+                println!(
+                    "  check the configuration in {}",
+                    self.unit.config_dir().display()
+                );
+                println!("  start {}, or restart it if it is running", unit);
+            }
+        }
+
+        let contents = self.service_file_contents(&out)?;
+        let current = fs::read_to_string(&service_out).ok();
+        if current.as_deref() == Some(contents.as_str()) {
+            println!("service file {} is unchanged", service_out.display());
+        } else {
+            println!("service file diff:");
+            print_diff(&service_out, current.as_deref(), contents.as_str());
+        }
 
         Ok(())
     }
-}
 
-impl InstallCmd {
+    /// Get the local artifacts to install from, if this is an offline install.
+    fn local_artifacts(&self) -> anyhow::Result<Option<LocalArtifacts>> {
+        if self.from_dir.is_none() && self.zip.is_none() {
+            return Ok(None);
+        }
+
+        // Constraints cannot be resolved without the releases index.
+        let version = if let Some(value) = &self.version {
+            let constraint = VersionConstraint::parse(value)?;
+            if let Some(value) = constraint.exact() {
+                Some(value.to_string())
+            } else {
+                anyhow::bail!("offline installs need an exact version, not {}", constraint);
+            }
+        } else {
+            None
+        };
+        let version = version.as_deref();
+
+        let local = match (&self.from_dir, &self.zip) {
+            (Some(dir), _) => LocalArtifacts::from_dir(dir, version)?,
+            (None, Some(zip)) => {
+                LocalArtifacts::from_files(zip, self.sums.as_deref(), self.sig.as_deref(), version)?
+            }
+            (None, None) => return Ok(None),
+        };
+
+        Ok(Some(local))
+    }
+
     #[allow(missing_docs, dead_code)]
     #[inline]
     pub fn check_integrity(&self) -> bool {
@@ -252,4 +611,49 @@ impl InstallCmd {
     pub fn ignore_outdated(&self) -> bool {
         self.ignore_outdated
     }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn versioned(&self) -> bool {
+        self.versioned
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn prefix(&self) -> &Path {
+        self.prefix.as_path()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn cache_dir(&self) -> Option<&Path> {
+        self.cache_dir.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn keyrings(&self) -> &[PathBuf] {
+        self.keyrings.as_slice()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn require_fingerprint(&self) -> Option<&str> {
+        self.require_fingerprint.as_deref()
+    }
+}
+
+/// Describe what writing to `path` would do.
+fn plan_action(path: &Path) -> &'static str {
+    if fs::symlink_metadata(path).is_ok() {
+        "replace"
+    } else {
+        "create"
+    }
 }
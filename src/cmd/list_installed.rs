@@ -0,0 +1,77 @@
+//! List the installed Nomad versions.
+
+use crate::common::resolve_out;
+use crate::config::Config;
+use crate::layout::VersionedLayout;
+use crate::layout::DEFAULT_PREFIX;
+
+use super::path_arg;
+use super::Command;
+use super::DEFAULT_NOMAD_OUT;
+
+use std::path::PathBuf;
+
+use clap::App;
+use clap::Arg;
+use clap::ArgMatches;
+use clap::SubCommand;
+
+/// List installed command.
+pub struct ListInstalledCmd {
+    /// where the nomad binary symlink is placed
+    out: PathBuf,
+    /// prefix of the versioned install layout
+    prefix: PathBuf,
+}
+
+impl Command for ListInstalledCmd {
+    const NAME: &'static str = "list-installed";
+
+    fn new(args: &ArgMatches, config: &Config) -> Self {
+        Self {
+            out: path_arg(args, "out", config.out(), DEFAULT_NOMAD_OUT),
+            prefix: path_arg(args, "prefix", config.prefix(), DEFAULT_PREFIX),
+        }
+    }
+
+    fn register(app: App<'static, 'static>) -> App<'static, 'static> {
+        let list_installed = SubCommand::with_name(Self::NAME)
+            .about("List the versions of Nomad in the versioned install layout.")
+            .arg(
+                Arg::with_name("out")
+                    .short("o")
+                    .long("out")
+                    .takes_value(true)
+                    .help("Where the nomad binary symlink is placed."),
+            )
+            .arg(
+                Arg::with_name("prefix")
+                    .long("prefix")
+                    .takes_value(true)
+                    .help("Prefix of the versioned install layout. Defaults to /opt."),
+            );
+        app.subcommand(list_installed)
+    }
+
+    fn run(&self) -> anyhow::Result<()> {
+        let layout = VersionedLayout::new(&self.prefix);
+        let out = resolve_out(&self.out, "nomad")?;
+        let active = layout.active(&out)?;
+
+        let versions = layout.installed()?;
+        if versions.is_empty() {
+            log::info!("no versions installed in {}", self.prefix.display());
+            return Ok(());
+        }
+
+        for version in versions {
+            if active.as_deref() == Some(version.as_str()) {
+                println!("* {}", version);
+            } else {
+                println!("  {}", version);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,13 +1,73 @@
 //! Module for nomadutil commands.
 
+use crate::cache::DEFAULT_CACHE_DIR;
+use crate::checkpoint::check;
+use crate::common::Endpoints;
+use crate::common::DEFAULT_CHECKPOINT_URL;
+use crate::common::DEFAULT_RELEASES_URL;
+use crate::config::Config;
+use crate::constraint::VersionConstraint;
+use crate::http::HttpClient;
+use crate::http::HttpSettings;
+use crate::http::RetryPolicy;
+use crate::http::DEFAULT_ATTEMPTS;
+use crate::http::DEFAULT_CONNECT_TIMEOUT;
+use crate::http::DEFAULT_READ_TIMEOUT;
+use crate::layout::VersionedLayout;
+use crate::releases;
+use crate::state::DEFAULT_STATE_FILE;
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
 use clap::App;
+use clap::Arg;
 use clap::ArgMatches;
 
+use reqwest::Url;
+
+use similar::TextDiff;
+
+mod cache;
+mod config;
 mod info;
+mod init_config;
 mod install;
+mod list_installed;
+mod service;
+mod status;
+mod tls;
+mod uninstall;
+mod upgrade;
+mod use_version;
+mod verify;
+mod versions;
 
+pub use cache::CacheCmd;
+pub use config::ConfigCmd;
 pub use info::InfoCmd;
+pub use init_config::InitConfigCmd;
 pub use install::InstallCmd;
+pub use list_installed::ListInstalledCmd;
+pub use service::ServiceCmd;
+pub use status::StatusCmd;
+pub use tls::TlsCmd;
+pub use uninstall::UninstallCmd;
+pub use upgrade::UpgradeCmd;
+pub use use_version::UseCmd;
+pub use verify::VerifyCmd;
+pub use versions::VersionsCmd;
+
+/// Default output dir for the nomad binary.
+const DEFAULT_NOMAD_OUT: &str = "/usr/local/bin";
+/// Default output dir for the nomad service file.
+const DEFAULT_NOMAD_SERVICE_OUT: &str = "/etc/systemd/system";
+/// Default directory for the nomad agent configuration.
+const DEFAULT_NOMAD_CONFIG_DIR: &str = "/etc/nomad.d";
+/// Default directory for the nomad agent data.
+const DEFAULT_NOMAD_DATA_DIR: &str = "/opt/nomad/data";
 
 /// Register subcommands
 #[macro_export]
@@ -22,11 +82,11 @@ macro_rules! register_subcommands {
 /// Macro for matching on subcommands and running them.
 #[macro_export]
 macro_rules! match_subcommands {
-    ($matches: ident, commands: { $($cmdname:ty),* }) => {
+    ($matches: ident, $config: ident, commands: { $($cmdname:ty),* }) => {
         match $matches.subcommand() {
             $(
                 (<$cmdname>::NAME, Some(args)) => {
-                    let _cmd = <$cmdname>::new(args);
+                    let _cmd = <$cmdname>::new(args, &$config);
 
                     if let Err(e) = _cmd.run() {
                         log::error!("{} failed: {}", <$cmdname>::NAME, e);
@@ -49,9 +109,498 @@ pub trait Command {
     const NAME: &'static str;
 
     /// Create a new command.
-    fn new(args: &ArgMatches) -> Self;
+    ///
+    /// Flags take precedence over the settings in the configuration.
+    fn new(args: &ArgMatches, config: &Config) -> Self;
     //  Register this command in the clap app.
     fn register(app: App<'static, 'static>) -> App<'static, 'static>;
     /// Run this command.
     fn run(&self) -> anyhow::Result<()>;
 }
+
+/// Get a path from a flag, falling back to the configuration and then to a default.
+fn path_arg(args: &ArgMatches, name: &str, config: Option<&Path>, default: &str) -> PathBuf {
+    if let Some(value) = args.value_of(name) {
+        PathBuf::from(value)
+    } else if let Some(value) = config {
+        value.to_path_buf()
+    } else {
+        PathBuf::from(default)
+    }
+}
+
+/// Get a string from a flag, falling back to the configuration.
+fn string_arg(args: &ArgMatches, name: &str, config: Option<&str>) -> Option<String> {
+    args.value_of(name)
+        .or(config)
+        .map(|value| value.to_string())
+}
+
+/// Get a boolean from a pair of flags, falling back to the configuration.
+///
+/// `on` sets it and `off` clears it, so a setting from the configuration can be overridden
+/// either way.
+fn switch_arg(args: &ArgMatches, on: &str, off: &str, config: bool) -> bool {
+    if args.is_present(on) {
+        true
+    } else if args.is_present(off) {
+        false
+    } else {
+        config
+    }
+}
+
+/// Get the flags that turn off the boolean settings of the install and upgrade commands.
+fn negating_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("check-sums")
+            .long("check-sums")
+            .conflicts_with("skip-sums")
+            .help("Check the sha256sums on the zip archive, even if the configuration skips it."),
+        Arg::with_name("check-sig")
+            .long("check-sig")
+            .conflicts_with("skip-sig")
+            .help(
+                "Check the signature of the sha256sums file, even if the configuration skips it.",
+            ),
+        Arg::with_name("no-ignore-alerts")
+            .long("no-ignore-alerts")
+            .conflicts_with("ignore-alerts")
+            .help("Fail on alerts for a version, even if the configuration ignores them."),
+        Arg::with_name("no-ignore-outdated")
+            .long("no-ignore-outdated")
+            .conflicts_with("ignore-outdated")
+            .help("Fail if a version is outdated, even if the configuration ignores it."),
+        Arg::with_name("no-versioned")
+            .long("no-versioned")
+            .conflicts_with("versioned")
+            .help("Do not use the versioned install layout, even if the configuration does."),
+    ]
+}
+
+/// Get a list from a flag that can be given multiple times, falling back to the configuration.
+///
+/// Values given as flags replace the ones in the configuration.
+fn list_arg(args: &ArgMatches, name: &str, config: &[String]) -> Vec<String> {
+    if let Some(values) = args.values_of(name) {
+        values.map(|value| value.to_string()).collect()
+    } else {
+        config.to_vec()
+    }
+}
+
+/// Print a unified diff from the `current` contents of `path`, None if it does not exist, to
+/// `contents`.
+fn print_diff(path: &Path, current: Option<&str>, contents: &str) {
+    let diff = TextDiff::from_lines(current.unwrap_or_default(), contents);
+    let old_name = if current.is_some() {
+        path.display().to_string()
+    } else {
+        "/dev/null".to_string()
+    };
+    let new_name = path.display().to_string();
+    print!(
+        "{}",
+        diff.unified_diff()
+            .header(old_name.as_str(), new_name.as_str())
+    );
+}
+
+/// Get the directory to stage a release in before installing it at `out`.
+///
+/// This is the version directory in the versioned install layout, created if needed, and the
+/// directory of `out` otherwise, so the release is staged on the disk it is installed to.
+fn staging_dir(out: &Path, layout: Option<(&VersionedLayout, &str)>) -> anyhow::Result<PathBuf> {
+    if let Some((layout, version)) = layout {
+        let dir = layout.version_dir(version);
+        fs::create_dir_all(&dir)?;
+        return Ok(dir);
+    }
+    out.parent()
+        .map(|value| value.to_path_buf())
+        .ok_or_else(|| anyhow::anyhow!("{} has no parent directory", out.display()))
+}
+
+/// Get the path of the install manifest from the configuration.
+fn state_file_arg(config: &Config) -> PathBuf {
+    config
+        .state_file()
+        .map(|value| value.to_path_buf())
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_STATE_FILE))
+}
+
+/// Get the cache directory from the flags and the configuration.
+///
+/// Returns None if caching is disabled.
+fn cache_dir_arg(args: &ArgMatches, config: &Config) -> Option<PathBuf> {
+    if switch_arg(args, "no-cache", "cache", config.no_cache()) {
+        None
+    } else {
+        Some(path_arg(
+            args,
+            "cache-dir",
+            config.cache_dir(),
+            DEFAULT_CACHE_DIR,
+        ))
+    }
+}
+
+/// Get the cache arguments shared by the commands that download releases.
+fn cache_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("cache-dir")
+            .long("cache-dir")
+            .takes_value(true)
+            .help("Directory of the artifact cache. Defaults to /var/cache/nomadutil."),
+        Arg::with_name("no-cache")
+            .long("no-cache")
+            .conflicts_with("cache-dir")
+            .help("Do not use the artifact cache."),
+        Arg::with_name("cache")
+            .long("cache")
+            .conflicts_with("no-cache")
+            .help("Use the artifact cache, even if the configuration disables it."),
+    ]
+}
+
+/// Get the keyrings to trust from the flags and the configuration.
+///
+/// Keyrings given as flags replace the ones in the configuration.
+fn keyrings_arg(args: &ArgMatches, config: &Config) -> Vec<PathBuf> {
+    if let Some(values) = args.values_of("keyring") {
+        values.map(PathBuf::from).collect()
+    } else {
+        config.trusted_keys().to_vec()
+    }
+}
+
+/// Get the fingerprint of the key that must sign the sha256sums from the flags and the
+/// configuration.
+fn require_fingerprint_arg(args: &ArgMatches, config: &Config) -> Option<String> {
+    args.value_of("require-fingerprint")
+        .or_else(|| config.require_fingerprint())
+        .map(|value| value.to_string())
+}
+
+/// Get the signature arguments shared by the commands that verify releases.
+fn sig_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("keyring")
+            .long("keyring")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Armoured gpg keys to trust in addition to the HashiCorp keys, e.g. the key a mirror re-signs releases with. Can be used multiple times."),
+        Arg::with_name("require-fingerprint")
+            .long("require-fingerprint")
+            .takes_value(true)
+            .help("Fail unless the sha256sums are signed by the key with this fingerprint."),
+    ]
+}
+
+/// Get the endpoints from the flags and the configuration.
+///
+/// Exits if the http client cannot be configured, e.g. because a certificate file is missing.
+fn endpoints_arg(args: &ArgMatches, config: &Config) -> Endpoints {
+    let releases_url = args
+        .value_of("releases-url")
+        .or_else(|| config.releases_url())
+        .unwrap_or(DEFAULT_RELEASES_URL);
+    let checkpoint_url = if switch_arg(args, "no-checkpoint", "checkpoint", config.no_checkpoint())
+    {
+        None
+    } else {
+        Some(
+            args.value_of("checkpoint-url")
+                .or_else(|| config.checkpoint_url())
+                .unwrap_or(DEFAULT_CHECKPOINT_URL),
+        )
+    };
+
+    Endpoints::new(
+        releases_url.to_string(),
+        checkpoint_url.map(|value| value.to_string()),
+        HttpClient::new(&http_settings_arg(args, config)),
+    )
+}
+
+/// Get the http client settings from the flags and the configuration.
+fn http_settings_arg(args: &ArgMatches, config: &Config) -> HttpSettings {
+    let number = |name: &str| args.value_of(name).and_then(|value| value.parse().ok());
+    let string = |name: &str, config: Option<&str>| {
+        args.value_of(name)
+            .or(config)
+            .map(|value| value.to_string())
+    };
+    let path = |name: &str, config: Option<&Path>| {
+        args.value_of(name)
+            .map(PathBuf::from)
+            .or_else(|| config.map(|value| value.to_path_buf()))
+    };
+
+    let attempts = number("retries")
+        .map(|value| value as u32)
+        .or_else(|| config.retries())
+        .unwrap_or(DEFAULT_ATTEMPTS);
+    let connect_timeout = number("connect-timeout")
+        .or_else(|| config.connect_timeout())
+        .unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+    let read_timeout = number("read-timeout")
+        .or_else(|| config.read_timeout())
+        .unwrap_or(DEFAULT_READ_TIMEOUT);
+
+    HttpSettings::new(RetryPolicy::new(attempts))
+        .with_proxy(
+            string("proxy", config.proxy()),
+            string("no-proxy", config.no_proxy()),
+        )
+        .with_ca_cert(path("ca-cert", config.ca_cert()))
+        .with_client_cert(
+            path("client-cert", config.client_cert()),
+            path("client-key", config.client_key()),
+        )
+        .with_timeouts(
+            Duration::from_secs(connect_timeout),
+            Duration::from_secs(read_timeout),
+        )
+}
+
+/// Validate a flag that takes a positive number.
+fn validate_positive(value: String) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(value) if value > 0 && value <= u64::from(u32::MAX) => Ok(()),
+        _ => Err(format!("{} is not a positive number", value)),
+    }
+}
+
+/// Validate a flag that takes a url.
+fn validate_url(value: String) -> Result<(), String> {
+    Url::parse(value.as_str())
+        .map(|_| ())
+        .map_err(|e| format!("{} is not a url: {}", value, e))
+}
+
+/// Validate a flag that takes a PEM file.
+fn validate_pem_file(value: String) -> Result<(), String> {
+    let contents = fs::read_to_string(value.as_str())
+        .map_err(|e| format!("failed to read {}: {}", value, e))?;
+    if !contents.contains("-----BEGIN ") {
+        return Err(format!("{} is not a PEM file", value));
+    }
+    Ok(())
+}
+
+/// Get the http client arguments shared by the commands that talk to remote endpoints.
+fn http_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("retries")
+            .long("retries")
+            .takes_value(true)
+            .validator(validate_positive)
+            .help("How many times to attempt http requests that fail transiently. Defaults to 4."),
+        Arg::with_name("proxy")
+            .long("proxy")
+            .takes_value(true)
+            .validator(validate_url)
+            .help("Proxy for the http requests. Defaults to $HTTPS_PROXY or $HTTP_PROXY."),
+        Arg::with_name("no-proxy")
+            .long("no-proxy")
+            .takes_value(true)
+            .help("Comma separated hosts to reach without the proxy. Defaults to $NO_PROXY."),
+        Arg::with_name("ca-cert")
+            .long("ca-cert")
+            .takes_value(true)
+            .validator(validate_pem_file)
+            .help("PEM file with root certificates to trust in addition to the built-in ones."),
+        Arg::with_name("client-cert")
+            .long("client-cert")
+            .takes_value(true)
+            .validator(validate_pem_file)
+            .help("PEM file with a client certificate to present, and its key if --client-key is not used."),
+        Arg::with_name("client-key")
+            .long("client-key")
+            .takes_value(true)
+            .requires("client-cert")
+            .validator(validate_pem_file)
+            .help("PEM file with the key of the client certificate."),
+        Arg::with_name("connect-timeout")
+            .long("connect-timeout")
+            .takes_value(true)
+            .validator(validate_positive)
+            .help("Timeout for connecting, in seconds. Defaults to 10."),
+        Arg::with_name("read-timeout")
+            .long("read-timeout")
+            .takes_value(true)
+            .validator(validate_positive)
+            .help("Timeout for a single read, in seconds. Defaults to 30."),
+    ]
+}
+
+/// Get the endpoint arguments shared by the commands that talk to the releases.
+fn endpoint_args() -> Vec<Arg<'static, 'static>> {
+    let mut args = vec![
+        Arg::with_name("releases-url")
+            .long("releases-url")
+            .takes_value(true)
+            .help("Base url of the releases, e.g. a mirror. Defaults to https://releases.hashicorp.com."),
+        Arg::with_name("checkpoint-url")
+            .long("checkpoint-url")
+            .takes_value(true)
+            .help("Url of the checkpoint api. Defaults to https://checkpoint-api.hashicorp.com."),
+        Arg::with_name("no-checkpoint")
+            .long("no-checkpoint")
+            .conflicts_with("checkpoint-url")
+            .help("Do not consult the checkpoint api for the latest version and alerts."),
+        Arg::with_name("checkpoint")
+            .long("checkpoint")
+            .conflicts_with("no-checkpoint")
+            .help("Consult the checkpoint api, even if the configuration disables it."),
+    ];
+    args.extend(http_args());
+    args
+}
+
+/// Resolve the version to install using the checkpoint api.
+///
+/// If no version is provided, the latest version is used. Version constraints are first resolved
+/// against the releases index; since they intentionally track an older line, an outdated version
+/// is only a warning for them.
+///
+/// If checkpoint is disabled, the latest version is taken from the releases index and there are
+/// no outdated or alert checks.
+fn resolve_version(
+    endpoints: &Endpoints,
+    version: Option<&str>,
+    ignore_outdated: bool,
+    ignore_alerts: bool,
+) -> anyhow::Result<String> {
+    let (version, ignore_outdated) = match version.map(VersionConstraint::parse).transpose()? {
+        Some(constraint) => {
+            if let Some(value) = constraint.exact() {
+                (Some(value.to_string()), ignore_outdated)
+            } else {
+                (
+                    Some(
+                        releases::resolve(endpoints, &constraint)?
+                            .version()
+                            .to_string(),
+                    ),
+                    true,
+                )
+            }
+        }
+        None => (None, ignore_outdated),
+    };
+    let version = version.as_deref();
+
+    let checkpoint_url = if let Some(value) = endpoints.checkpoint_url() {
+        value
+    } else {
+        log::warn!("checkpoint is disabled, not checking for outdated versions and alerts");
+        return if let Some(value) = version {
+            Ok(value.to_string())
+        } else {
+            let release = releases::latest(endpoints)?;
+            log::info!("{} is the latest release", release.version());
+            Ok(release.version().to_string())
+        };
+    };
+
+    let res = check(endpoints.http(), checkpoint_url, version)?;
+    let version: &str = if let Some(value) = version {
+        value
+    } else {
+        res.current_version()
+    };
+    if res.outdated() {
+        if ignore_outdated {
+            log::warn!(
+                "checkpoint says version {} is outdated, newest is {}, ignoring",
+                version,
+                res.current_version()
+            );
+        } else {
+            anyhow::bail!(
+                "checkpoint says version {} is outdated, newest is {}",
+                version,
+                res.current_version()
+            );
+        }
+    } else {
+        log::info!("{} is the latest release", version);
+    }
+    if !res.alerts().is_empty() {
+        if ignore_alerts {
+            log::warn!("alerts: {:?}; ignoring", res.alerts());
+        } else {
+            anyhow::bail!("alerts: {:?}", res.alerts());
+        }
+    }
+
+    Ok(version.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::negating_args;
+    use super::switch_arg;
+    use super::validate_pem_file;
+    use super::validate_url;
+
+    use crate::common::scratch_dir;
+
+    use std::fs;
+    use std::path::Path;
+
+    use clap::App;
+    use clap::Arg;
+
+    /// Check whether the signature is skipped for the given flags and configuration.
+    fn skip_sig(flags: &[&str], config: bool) -> bool {
+        let matches = App::new("test")
+            .arg(Arg::with_name("skip-sig").long("skip-sig"))
+            .arg(Arg::with_name("skip-sums").long("skip-sums"))
+            .arg(Arg::with_name("ignore-alerts").long("ignore-alerts"))
+            .arg(Arg::with_name("ignore-outdated").long("ignore-outdated"))
+            .arg(Arg::with_name("versioned").long("versioned"))
+            .args(&negating_args())
+            .get_matches_from(std::iter::once("test").chain(flags.iter().copied()));
+        switch_arg(&matches, "skip-sig", "check-sig", config)
+    }
+
+    #[test]
+    fn flags_override_the_configuration() {
+        assert!(!skip_sig(&[], false));
+        assert!(skip_sig(&[], true));
+        assert!(skip_sig(&["--skip-sig"], false));
+        assert!(!skip_sig(&["--check-sig"], true));
+    }
+
+    #[test]
+    fn urls_are_validated() {
+        assert!(validate_url("http://127.0.0.1:4646".to_string()).is_ok());
+        assert!(validate_url("https://proxy.example.com:3128".to_string()).is_ok());
+        assert!(validate_url("127.0.0.1:4646".to_string()).is_err());
+        assert!(validate_url("".to_string()).is_err());
+    }
+
+    #[test]
+    fn pem_files_are_validated() {
+        let dir = scratch_dir("validate-pem");
+        let pem = dir.join("ca.pem");
+        fs::write(
+            &pem,
+            "-----BEGIN CERTIFICATE-----\n-----END CERTIFICATE-----\n",
+        )
+        .unwrap();
+        let text = dir.join("ca.txt");
+        fs::write(&text, "not a certificate").unwrap();
+
+        let path = |path: &Path| path.display().to_string();
+        assert!(validate_pem_file(path(&pem)).is_ok());
+        assert!(validate_pem_file(path(&text)).is_err());
+        assert!(validate_pem_file(path(&dir.join("missing.pem"))).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+//! Manage the Nomad systemd unit.
+
+use crate::common::resolve_out;
+use crate::config::Config;
+use crate::state::State;
+use crate::systemd::unit_name;
+use crate::systemd::Systemctl;
+use crate::systemd::Systemd;
+use crate::systemd::JOURNAL_LINES;
+
+use super::path_arg;
+use super::state_file_arg;
+use super::Command;
+use super::DEFAULT_NOMAD_OUT;
+use super::DEFAULT_NOMAD_SERVICE_OUT;
+
+use std::path::PathBuf;
+
+use clap::App;
+use clap::Arg;
+use clap::ArgMatches;
+use clap::SubCommand;
+
+/// Service command.
+pub struct ServiceCmd {
+    /// what to do with the unit
+    action: String,
+    /// where the nomad binary is installed
+    out: PathBuf,
+    /// where the nomad service file is installed
+    service_out: PathBuf,
+    /// the install manifest
+    state_file: PathBuf,
+    /// how systemd is reached
+    systemd: Box<dyn Systemd>,
+}
+
+impl Command for ServiceCmd {
+    const NAME: &'static str = "service";
+
+    fn new(args: &ArgMatches, config: &Config) -> Self {
+        Self {
+            action: args.value_of("action").unwrap_or_default().to_string(),
+            out: path_arg(args, "out", config.out(), DEFAULT_NOMAD_OUT),
+            service_out: path_arg(
+                args,
+                "service-out",
+                config.service_out(),
+                DEFAULT_NOMAD_SERVICE_OUT,
+            ),
+            state_file: state_file_arg(config),
+            systemd: Box::new(Systemctl),
+        }
+    }
+
+    fn register(app: App<'static, 'static>) -> App<'static, 'static> {
+        let service = SubCommand::with_name(Self::NAME)
+            .about("Manage the nomad systemd unit.")
+            .arg(
+                Arg::with_name("action")
+                    .required(true)
+                    .possible_values(&["start", "stop", "restart", "status", "enable", "disable"])
+                    .help("What to do with the unit."),
+            )
+            .arg(
+                Arg::with_name("out")
+                    .short("o")
+                    .long("out")
+                    .takes_value(true)
+                    .help("Where the nomad binary is placed, to find its unit in the install manifest."),
+            )
+            .arg(
+                Arg::with_name("service-out")
+                    .long("service-out")
+                    .takes_value(true)
+                    .help("Where the nomad systemd service file is placed, if it is not in the install manifest."),
+            );
+        app.subcommand(service)
+    }
+
+    fn run(&self) -> anyhow::Result<()> {
+        let state = State::load(&self.state_file)?;
+        let out = resolve_out(&self.out, "nomad")?;
+        let service_out =
+            if let Some(value) = state.get(&out).and_then(|install| install.service_file()) {
+                value.to_path_buf()
+            } else {
+                resolve_out(&self.service_out, "nomad.service")?
+            };
+        let unit = unit_name(&service_out)?;
+
+        let unit_state = match self.action.as_str() {
+            "status" => {
+                println!("{} is {}", unit, self.systemd.state(unit)?);
+                for line in self.systemd.journal(unit, JOURNAL_LINES)? {
+                    println!("{}", line);
+                }
+                return Ok(());
+            }
+            "start" | "restart" => {
+                let current = self.systemd.state(unit)?;
+                if self.action == "start" && current.is_active() {
+                    log::info!("{} is already running", unit);
+                    current
+                } else {
+                    self.systemd.start_checked(unit)?
+                }
+            }
+            action => {
+                self.systemd.unit_action(action, unit)?;
+                self.systemd.state(unit)?
+            }
+        };
+        log::info!("{} is {}", unit, unit_state);
+
+        Ok(())
+    }
+}
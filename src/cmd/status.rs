@@ -0,0 +1,118 @@
+//! Show the installations recorded in the install manifest.
+
+use crate::config::Config;
+use crate::state::Install;
+use crate::state::State;
+
+use super::state_file_arg;
+use super::Command;
+
+use std::path::PathBuf;
+
+use clap::App;
+use clap::Arg;
+use clap::ArgMatches;
+use clap::SubCommand;
+
+/// Status command.
+pub struct StatusCmd {
+    /// the install manifest
+    state_file: PathBuf,
+    /// whether to print the manifest as json
+    json: bool,
+}
+
+impl Command for StatusCmd {
+    const NAME: &'static str = "status";
+
+    fn new(args: &ArgMatches, config: &Config) -> Self {
+        Self {
+            state_file: state_file_arg(config),
+            json: args.is_present("json"),
+        }
+    }
+
+    fn register(app: App<'static, 'static>) -> App<'static, 'static> {
+        let status = SubCommand::with_name(Self::NAME)
+            .about("Show what nomadutil has installed, from the install manifest.")
+            .arg(
+                Arg::with_name("json")
+                    .long("json")
+                    .help("Print the install manifest as json."),
+            );
+        app.subcommand(status)
+    }
+
+    fn run(&self) -> anyhow::Result<()> {
+        let state = State::load(&self.state_file)?;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&state)?);
+            return Ok(());
+        }
+
+        if state.installs().is_empty() {
+            log::info!("no installations recorded in {}", self.state_file.display());
+            return Ok(());
+        }
+
+        for (index, install) in state.installs().iter().enumerate() {
+            if index > 0 {
+                println!();
+            }
+            print_install(install);
+        }
+
+        Ok(())
+    }
+}
+
+/// Print an installation.
+fn print_install(install: &Install) {
+    let binary = if !install.binary().exists() {
+        "missing".to_string()
+    } else {
+        match install.binary_matches(install.binary()) {
+            Ok(true) => "ok".to_string(),
+            Ok(false) => "modified since it was installed".to_string(),
+            Err(e) => format!("unreadable: {}", e),
+        }
+    };
+
+    println!("{}", install.binary().display());
+    println!("  version:       {}", install.version());
+    println!("  installed at:  {}", install.installed_at().to_rfc3339());
+    println!("  source:        {}", install.source().unwrap_or("unknown"));
+    println!(
+        "  zip sha256:    {}",
+        install.zip_sha256().unwrap_or("unknown")
+    );
+    println!("  binary sha256: {} ({})", install.binary_sha256(), binary);
+    println!(
+        "  sha256sums:    {}",
+        if install.checked_sums() {
+            "checked"
+        } else {
+            "not checked"
+        }
+    );
+    println!(
+        "  signed by:     {}",
+        install.signed_by().unwrap_or("not checked")
+    );
+    println!(
+        "  layout:        {}",
+        if install.versioned() {
+            "versioned"
+        } else {
+            "plain"
+        }
+    );
+    if let Some(value) = install.service_file() {
+        println!("  service file:  {}", value.display());
+    }
+    println!("  files:");
+    for file in install.files() {
+        println!("    {}", file.display());
+    }
+}
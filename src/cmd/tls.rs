@@ -0,0 +1,475 @@
+//! Generate the TLS certificates of a Nomad cluster.
+
+use crate::agent_config::render_tls;
+use crate::common::install_file;
+use crate::config::Config;
+use crate::tls::create_ca;
+use crate::tls::create_cert;
+use crate::tls::CertRole;
+use crate::tls::PemPair;
+use crate::tls::CA_FILE;
+use crate::tls::CA_KEY_FILE;
+use crate::tls::DEFAULT_CA_DAYS;
+use crate::tls::DEFAULT_CERT_DAYS;
+
+use super::path_arg;
+use super::print_diff;
+use super::string_arg;
+use super::validate_positive;
+use super::Command;
+use super::DEFAULT_NOMAD_CONFIG_DIR;
+
+use std::fs;
+use std::net::IpAddr;
+use std::os::unix::fs::chown;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+use clap::App;
+use clap::AppSettings;
+use clap::Arg;
+use clap::ArgGroup;
+use clap::ArgMatches;
+use clap::SubCommand;
+
+/// Default region of the certificates.
+const DEFAULT_REGION: &str = "global";
+/// Mode of the directory of the certificates.
+const TLS_DIR_MODE: u32 = 0o750;
+
+/// What the tls command should do.
+enum TlsAction {
+    /// create the CA
+    CreateCa,
+    /// create a certificate signed by the CA
+    CreateCert {
+        role: CertRole,
+        region: String,
+        /// the CA certificate, None for the one in the tls directory
+        ca: Option<PathBuf>,
+        /// the CA key, None for the one in the tls directory
+        ca_key: Option<PathBuf>,
+        /// extra DNS SANs
+        dns_names: Vec<String>,
+        /// extra IP SANs
+        ip_addresses: Vec<IpAddr>,
+        /// whether to write the matching tls block to the configuration directory
+        emit_hcl: bool,
+    },
+}
+
+/// Tls command.
+pub struct TlsCmd {
+    action: TlsAction,
+    /// the nomad configuration directory
+    config_dir: PathBuf,
+    /// directory of the certificates
+    tls_dir: PathBuf,
+    /// validity of the certificate, None for the default
+    days: Option<u32>,
+    /// whether to overwrite existing files
+    force: bool,
+}
+
+impl Command for TlsCmd {
+    const NAME: &'static str = "tls";
+
+    fn new(args: &ArgMatches, config: &Config) -> Self {
+        let (action, args) = match args.subcommand() {
+            ("cert", Some(args)) => {
+                let args = args.subcommand_matches("create").unwrap_or(args);
+                let role = if args.is_present("server") {
+                    CertRole::Server
+                } else if args.is_present("client") {
+                    CertRole::Client
+                } else {
+                    CertRole::Cli
+                };
+                let action = TlsAction::CreateCert {
+                    role,
+                    region: string_arg(args, "region", config.region())
+                        .unwrap_or_else(|| DEFAULT_REGION.to_string()),
+                    ca: args.value_of("ca").map(PathBuf::from),
+                    ca_key: args.value_of("ca-key").map(PathBuf::from),
+                    dns_names: args
+                        .values_of("additional-dnsname")
+                        .map(|values| values.map(|value| value.to_string()).collect())
+                        .unwrap_or_default(),
+                    ip_addresses: args
+                        .values_of("additional-ipaddress")
+                        .map(|values| values.filter_map(|value| value.parse().ok()).collect())
+                        .unwrap_or_default(),
+                    emit_hcl: args.is_present("emit-hcl"),
+                };
+                (action, args)
+            }
+            (_, ca) => {
+                let args = ca.unwrap_or(args);
+                (
+                    TlsAction::CreateCa,
+                    args.subcommand_matches("create").unwrap_or(args),
+                )
+            }
+        };
+
+        let config_dir = path_arg(
+            args,
+            "config-dir",
+            config.config_dir(),
+            DEFAULT_NOMAD_CONFIG_DIR,
+        );
+        let tls_dir = args
+            .value_of("tls-dir")
+            .map(PathBuf::from)
+            .or_else(|| config.tls_dir().map(|value| value.to_path_buf()))
+            .unwrap_or_else(|| config_dir.join("tls"));
+
+        Self {
+            action,
+            config_dir,
+            tls_dir,
+            days: args.value_of("days").and_then(|value| value.parse().ok()),
+            force: args.is_present("force"),
+        }
+    }
+
+    fn register(app: App<'static, 'static>) -> App<'static, 'static> {
+        let common_args = || {
+            vec![
+                Arg::with_name("config-dir")
+                    .long("config-dir")
+                    .takes_value(true)
+                    .help("The nomad configuration directory. Defaults to /etc/nomad.d."),
+                Arg::with_name("tls-dir")
+                    .long("tls-dir")
+                    .takes_value(true)
+                    .help("Where to place the certificates. Defaults to tls in the configuration directory."),
+                Arg::with_name("days")
+                    .long("days")
+                    .takes_value(true)
+                    .validator(validate_positive)
+                    .help("How many days the certificate is valid."),
+                Arg::with_name("force")
+                    .long("force")
+                    .help("Overwrite existing files."),
+            ]
+        };
+
+        let ca = SubCommand::with_name("ca")
+            .about("Manage the cluster CA.")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                SubCommand::with_name("create")
+                    .about("Create an ECDSA CA. It is valid for 5 years unless --days is given. The key is only readable by its owner.")
+                    .args(&common_args()),
+            );
+
+        let cert = SubCommand::with_name("cert")
+            .about("Manage the agent and cli certificates.")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                SubCommand::with_name("create")
+                    .about("Create a certificate signed by the CA, with the SANs nomad expects: <role>.<region>.nomad, localhost and, for agents, 127.0.0.1. It is valid for 1 year unless --days is given.")
+                    .args(&common_args())
+                    .arg(Arg::with_name("server").long("server").help("Create a server certificate."))
+                    .arg(Arg::with_name("client").long("client").help("Create a client certificate."))
+                    .arg(Arg::with_name("cli").long("cli").help("Create a certificate for the nomad cli."))
+                    .group(ArgGroup::with_name("role").args(&["server", "client", "cli"]).required(true))
+                    .arg(
+                        Arg::with_name("region")
+                            .long("region")
+                            .takes_value(true)
+                            .help("Region of the agent. Defaults to global."),
+                    )
+                    .arg(
+                        Arg::with_name("ca")
+                            .long("ca")
+                            .takes_value(true)
+                            .help("The CA certificate. Defaults to nomad-agent-ca.pem in the tls directory."),
+                    )
+                    .arg(
+                        Arg::with_name("ca-key")
+                            .long("ca-key")
+                            .takes_value(true)
+                            .help("The CA key. Defaults to nomad-agent-ca-key.pem in the tls directory."),
+                    )
+                    .arg(
+                        Arg::with_name("additional-dnsname")
+                            .long("additional-dnsname")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1)
+                            .help("An extra DNS name of the certificate. Can be used multiple times."),
+                    )
+                    .arg(
+                        Arg::with_name("additional-ipaddress")
+                            .long("additional-ipaddress")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1)
+                            .validator(|value| {
+                                value
+                                    .parse::<IpAddr>()
+                                    .map(|_| ())
+                                    .map_err(|e| e.to_string())
+                            })
+                            .help("An extra IP address of the certificate. Can be used multiple times."),
+                    )
+                    .arg(
+                        Arg::with_name("emit-hcl")
+                            .long("emit-hcl")
+                            .conflicts_with("cli")
+                            .help("Write the matching tls block to tls.hcl in the configuration directory."),
+                    ),
+            );
+
+        let tls = SubCommand::with_name(Self::NAME)
+            .about("Generate the TLS certificates of a nomad cluster.")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(ca)
+            .subcommand(cert);
+        app.subcommand(tls)
+    }
+
+    fn run(&self) -> anyhow::Result<()> {
+        let ca_file = self.tls_dir.join(CA_FILE);
+        let ca_key_file = self.tls_dir.join(CA_KEY_FILE);
+
+        match &self.action {
+            TlsAction::CreateCa => {
+                self.check_new(&[&ca_file, &ca_key_file])?;
+                if self.force && ca_file.exists() {
+                    log::warn!(
+                        "replacing the CA, the certificates it signed will no longer be trusted"
+                    );
+                }
+                let ca = create_ca(self.days.unwrap_or(DEFAULT_CA_DAYS))?;
+
+                self.ensure_tls_dir()?;
+                self.write(&ca_file, ca.cert(), 0o644)?;
+                self.write(&ca_key_file, ca.key(), 0o600)?;
+            }
+            TlsAction::CreateCert {
+                role,
+                region,
+                ca,
+                ca_key,
+                dns_names,
+                ip_addresses,
+                emit_hcl,
+            } => {
+                let (cert_name, key_name) = role.file_names(region);
+                let cert_file = self.tls_dir.join(cert_name);
+                let key_file = self.tls_dir.join(key_name);
+                self.check_new(&[&cert_file, &key_file])?;
+
+                let ca_file = ca.as_deref().unwrap_or(&ca_file);
+                let ca = PemPair::new(
+                    read_pem(ca_file)?,
+                    read_pem(ca_key.as_deref().unwrap_or(&ca_key_file))?,
+                );
+                let cert = create_cert(
+                    &ca,
+                    *role,
+                    region,
+                    dns_names,
+                    ip_addresses,
+                    self.days.unwrap_or(DEFAULT_CERT_DAYS),
+                )?;
+
+                // The agent reads its key through the group of the tls directory, e.g. the nomad
+                // group after install --setup-host.
+                let key_mode = if *role == CertRole::Cli { 0o600 } else { 0o640 };
+                self.ensure_tls_dir()?;
+                self.write(&cert_file, cert.cert(), 0o644)?;
+                self.write(&key_file, cert.key(), key_mode)?;
+
+                if *emit_hcl {
+                    self.emit_hcl(ca_file, &cert_file, &key_file)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TlsCmd {
+    /// Fail if any of `paths` exists, unless forced.
+    fn check_new(&self, paths: &[&Path]) -> anyhow::Result<()> {
+        if self.force {
+            return Ok(());
+        }
+        let existing: Vec<String> = paths
+            .iter()
+            .filter(|path| path.exists())
+            .map(|path| path.display().to_string())
+            .collect();
+        if !existing.is_empty() {
+            anyhow::bail!(
+                "{} already exist, use --force to overwrite them",
+                existing.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Create the tls directory if it is missing, owned like the configuration directory.
+    fn ensure_tls_dir(&self) -> anyhow::Result<()> {
+        if self.tls_dir.is_dir() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.tls_dir)?;
+        fs::set_permissions(&self.tls_dir, fs::Permissions::from_mode(TLS_DIR_MODE))?;
+        if let Ok(metadata) = fs::metadata(&self.config_dir) {
+            chown(&self.tls_dir, Some(metadata.uid()), Some(metadata.gid()))?;
+        }
+        log::info!("created {}", self.tls_dir.display());
+        Ok(())
+    }
+
+    /// Write a file in the tls directory, owned like the directory.
+    fn write(&self, path: &Path, contents: &str, mode: u32) -> anyhow::Result<()> {
+        let metadata = fs::metadata(&self.tls_dir)?;
+        install_file(
+            path,
+            contents.as_bytes(),
+            mode,
+            Some((metadata.uid(), metadata.gid())),
+        )?;
+        log::info!("wrote {}", path.display());
+        Ok(())
+    }
+
+    /// Write the tls block for the certificate to tls.hcl in the configuration directory.
+    fn emit_hcl(&self, ca_file: &Path, cert_file: &Path, key_file: &Path) -> anyhow::Result<()> {
+        let path = self.config_dir.join("tls.hcl");
+        let contents = render_tls(ca_file, cert_file, key_file);
+        let current = fs::read_to_string(&path).ok();
+        if current.as_deref() == Some(contents.as_str()) {
+            log::info!("{} is unchanged", path.display());
+            return Ok(());
+        }
+        print_diff(&path, current.as_deref(), contents.as_str());
+        if current.is_some() && !self.force {
+            anyhow::bail!(
+                "{} already exists and differs, use --force to overwrite it",
+                path.display()
+            );
+        }
+
+        let metadata = fs::metadata(&self.config_dir)?;
+        let owner = if current.is_some() {
+            None
+        } else {
+            Some((metadata.uid(), metadata.gid()))
+        };
+        install_file(&path, contents.as_bytes(), 0o640, owner)?;
+        log::info!("wrote {}", path.display());
+        Ok(())
+    }
+}
+
+/// Read a PEM file.
+fn read_pem(path: &Path) -> anyhow::Result<String> {
+    fs::read_to_string(path).map_err(|e| {
+        anyhow::anyhow!(
+            "failed to read {}: {}, is there a CA? create one with nomadutil tls ca create",
+            path.display(),
+            e
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+    use super::TlsCmd;
+
+    use crate::common::scratch_dir;
+    use crate::config::Config;
+
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    use clap::App;
+
+    /// Run `nomadutil tls <args>` with the configuration directory `dir`.
+    fn tls(dir: &Path, args: &[&str]) -> anyhow::Result<()> {
+        let config_dir = dir.display().to_string();
+        let matches = TlsCmd::register(App::new("nomadutil")).get_matches_from(
+            ["nomadutil", "tls"]
+                .iter()
+                .chain(args)
+                .chain(&["--config-dir", config_dir.as_str()]),
+        );
+        let args = matches.subcommand_matches(TlsCmd::NAME).unwrap();
+        TlsCmd::new(args, &Config::default()).run()
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn key_modes() {
+        let dir = scratch_dir("tls-modes");
+        let tls_dir = dir.join("tls");
+        tls(&dir, &["ca", "create"]).unwrap();
+        tls(&dir, &["cert", "create", "--server"]).unwrap();
+        tls(&dir, &["cert", "create", "--client"]).unwrap();
+        tls(&dir, &["cert", "create", "--cli"]).unwrap();
+
+        assert_eq!(mode(&tls_dir), 0o750);
+        assert_eq!(mode(&tls_dir.join("nomad-agent-ca.pem")), 0o644);
+        assert_eq!(mode(&tls_dir.join("nomad-agent-ca-key.pem")), 0o600);
+        for role in &["server", "client"] {
+            assert_eq!(
+                mode(&tls_dir.join(format!("global-{}-nomad.pem", role))),
+                0o644
+            );
+            assert_eq!(
+                mode(&tls_dir.join(format!("global-{}-nomad-key.pem", role))),
+                0o640
+            );
+        }
+        assert_eq!(mode(&tls_dir.join("global-cli-nomad.pem")), 0o644);
+        assert_eq!(mode(&tls_dir.join("global-cli-nomad-key.pem")), 0o600);
+    }
+
+    #[test]
+    fn refuses_to_overwrite() {
+        let dir = scratch_dir("tls-overwrite");
+        let ca_file = dir.join("tls/nomad-agent-ca.pem");
+        let cert_file = dir.join("tls/global-server-nomad.pem");
+        tls(&dir, &["ca", "create"]).unwrap();
+        tls(&dir, &["cert", "create", "--server"]).unwrap();
+        let ca = fs::read_to_string(&ca_file).unwrap();
+        let cert = fs::read_to_string(&cert_file).unwrap();
+
+        let e = tls(&dir, &["ca", "create"]).unwrap_err();
+        assert!(e.to_string().ends_with("use --force to overwrite them"));
+        let e = tls(&dir, &["cert", "create", "--server"]).unwrap_err();
+        assert!(e.to_string().ends_with("use --force to overwrite them"));
+        assert_eq!(fs::read_to_string(&ca_file).unwrap(), ca);
+        assert_eq!(fs::read_to_string(&cert_file).unwrap(), cert);
+
+        tls(&dir, &["cert", "create", "--server", "--force"]).unwrap();
+        assert_ne!(fs::read_to_string(&cert_file).unwrap(), cert);
+        tls(&dir, &["ca", "create", "--force"]).unwrap();
+        assert_ne!(fs::read_to_string(&ca_file).unwrap(), ca);
+    }
+
+    #[test]
+    fn cert_needs_a_ca() {
+        let dir = scratch_dir("tls-no-ca");
+        let e = tls(&dir, &["cert", "create", "--cli"]).unwrap_err();
+        assert!(e
+            .to_string()
+            .contains("create one with nomadutil tls ca create"));
+        assert!(!dir.join("tls/global-cli-nomad.pem").exists());
+    }
+}
@@ -0,0 +1,247 @@
+//! Uninstall Nomad.
+
+use crate::common::resolve_out;
+use crate::config::Config;
+use crate::state::State;
+use crate::systemd::unit_name;
+use crate::systemd::Systemctl;
+use crate::systemd::Systemd;
+
+use super::path_arg;
+use super::state_file_arg;
+use super::Command;
+use super::DEFAULT_NOMAD_CONFIG_DIR;
+use super::DEFAULT_NOMAD_DATA_DIR;
+use super::DEFAULT_NOMAD_OUT;
+use super::DEFAULT_NOMAD_SERVICE_OUT;
+
+use std::fs;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use clap::App;
+use clap::Arg;
+use clap::ArgMatches;
+use clap::SubCommand;
+
+/// Uninstall command.
+pub struct UninstallCmd {
+    /// where the nomad binary was installed
+    out: PathBuf,
+    /// where the nomad service file was installed
+    service_out: PathBuf,
+    /// whether to remove the configuration and data directories
+    purge: bool,
+    /// the nomad configuration directory
+    config_dir: PathBuf,
+    /// the nomad data directory
+    data_dir: PathBuf,
+    /// the install manifest
+    state_file: PathBuf,
+    /// how systemd is reached
+    systemd: Box<dyn Systemd>,
+}
+
+impl Command for UninstallCmd {
+    const NAME: &'static str = "uninstall";
+
+    fn new(args: &ArgMatches, config: &Config) -> Self {
+        Self {
+            out: path_arg(args, "out", config.out(), DEFAULT_NOMAD_OUT),
+            service_out: path_arg(
+                args,
+                "service-out",
+                config.service_out(),
+                DEFAULT_NOMAD_SERVICE_OUT,
+            ),
+            purge: args.is_present("purge"),
+            config_dir: path_arg(
+                args,
+                "config-dir",
+                config.config_dir(),
+                DEFAULT_NOMAD_CONFIG_DIR,
+            ),
+            data_dir: path_arg(args, "data-dir", config.data_dir(), DEFAULT_NOMAD_DATA_DIR),
+            state_file: state_file_arg(config),
+            systemd: Box::new(Systemctl),
+        }
+    }
+
+    fn register(app: App<'static, 'static>) -> App<'static, 'static> {
+        let uninstall = SubCommand::with_name(Self::NAME)
+            .about("Uninstall Nomad.")
+            .arg(
+                Arg::with_name("out")
+                    .short("o")
+                    .long("out")
+                    .takes_value(true)
+                    .help("Where the nomad binary was placed."),
+            )
+            .arg(
+                Arg::with_name("service-out")
+                    .long("service-out")
+                    .takes_value(true)
+                    .help("Where the nomad systemd service file was placed."),
+            )
+            .arg(
+                Arg::with_name("purge")
+                    .long("purge")
+                    .help("Also remove the nomad configuration and data directories."),
+            )
+            .arg(
+                Arg::with_name("config-dir")
+                    .long("config-dir")
+                    .takes_value(true)
+                    .requires("purge")
+                    .help("The nomad configuration directory to remove when purging."),
+            )
+            .arg(
+                Arg::with_name("data-dir")
+                    .long("data-dir")
+                    .takes_value(true)
+                    .requires("purge")
+                    .help("The nomad data directory to remove when purging."),
+            );
+        app.subcommand(uninstall)
+    }
+
+    fn run(&self) -> anyhow::Result<()> {
+        let mut state = State::load(&self.state_file)?;
+        let out = resolve_out(&self.out, "nomad")?;
+        let install = state.remove(&out);
+        if install.is_none() {
+            log::warn!(
+                "{} is not in the install manifest, removing the files given by the flags",
+                out.display()
+            );
+        }
+
+        let service_out =
+            if let Some(value) = install.as_ref().and_then(|value| value.service_file()) {
+                value.to_path_buf()
+            } else {
+                resolve_out(&self.service_out, "nomad.service")?
+            };
+        if service_out.exists() {
+            let unit = unit_name(&service_out)?;
+
+            let state = self.systemd.state(unit)?;
+            if state.is_active() {
+                self.systemd.unit_action("stop", unit)?;
+                log::info!("stopped {}", unit);
+            }
+            if state.is_enabled() {
+                self.systemd.unit_action("disable", unit)?;
+                log::info!("disabled {}", unit);
+            }
+
+            fs::remove_file(&service_out)?;
+            log::info!("removed nomad service file {}", service_out.display());
+
+            self.systemd.daemon_reload()?;
+        } else {
+            log::info!(
+                "nomad service file {} not found, skipping",
+                service_out.display()
+            );
+        }
+
+        let files = if let Some(value) = &install {
+            value.files().to_vec()
+        } else {
+            vec![out.clone()]
+        };
+        for file in files.iter().filter(|file| **file != service_out) {
+            if fs::symlink_metadata(file).is_ok() {
+                fs::remove_file(file)?;
+                log::info!("removed {}", file.display());
+            } else {
+                log::info!("{} not found, skipping", file.display());
+            }
+        }
+
+        if install.is_some() {
+            state.save(&self.state_file)?;
+            log::info!("installation removed from {}", self.state_file.display());
+        }
+
+        if self.purge {
+            check_purge_dir(&self.config_dir)?;
+            check_purge_dir(&self.data_dir)?;
+            purge_dir(&self.config_dir)?;
+            purge_dir(&self.data_dir)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Make sure a directory is safe to purge.
+///
+/// Refuses empty paths, the root directory, paths that climb with `..` and paths that have no
+/// component mentioning nomad, since the directories can come from flags or the configuration.
+fn check_purge_dir(dir: &Path) -> anyhow::Result<()> {
+    if dir.as_os_str().is_empty() {
+        anyhow::bail!("refusing to purge an empty path");
+    }
+    if dir
+        .components()
+        .any(|component| component == Component::ParentDir)
+    {
+        anyhow::bail!("refusing to purge {}: path contains ..", dir.display());
+    }
+    if dir
+        .components()
+        .all(|component| matches!(component, Component::RootDir | Component::CurDir))
+    {
+        anyhow::bail!("refusing to purge {}", dir.display());
+    }
+    let mentions_nomad = dir.components().any(|component| match component {
+        Component::Normal(value) => value.to_string_lossy().contains("nomad"),
+        _ => false,
+    });
+    if !mentions_nomad {
+        anyhow::bail!(
+            "refusing to purge {}: it does not look like a nomad directory",
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// Remove a directory and its contents, if it exists.
+fn purge_dir(dir: &Path) -> anyhow::Result<()> {
+    if !dir.exists() {
+        log::info!("{} not found, skipping", dir.display());
+        return Ok(());
+    }
+    log::warn!("purging {}", dir.display());
+    fs::remove_dir_all(dir)?;
+    log::info!("purged {}", dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_purge_dir;
+
+    use std::path::Path;
+
+    #[test]
+    fn purge_accepts_nomad_dirs() {
+        assert!(check_purge_dir(Path::new("/etc/nomad.d")).is_ok());
+        assert!(check_purge_dir(Path::new("/opt/nomad/data")).is_ok());
+        assert!(check_purge_dir(Path::new("/srv/nomad-data")).is_ok());
+    }
+
+    #[test]
+    fn purge_refuses_dangerous_dirs() {
+        assert!(check_purge_dir(Path::new("")).is_err());
+        assert!(check_purge_dir(Path::new("/")).is_err());
+        assert!(check_purge_dir(Path::new(".")).is_err());
+        assert!(check_purge_dir(Path::new("/etc")).is_err());
+        assert!(check_purge_dir(Path::new("/opt/data")).is_err());
+        assert!(check_purge_dir(Path::new("/opt/nomad/../..")).is_err());
+    }
+}
@@ -0,0 +1,553 @@
+//! Upgrade Nomad.
+
+use crate::common::backup_path;
+use crate::common::install_file_from;
+use crate::common::opt_string_to_opt_str;
+use crate::common::resolve_out;
+use crate::common::Endpoints;
+use crate::config::Config;
+use crate::layout::VersionedLayout;
+use crate::layout::DEFAULT_PREFIX;
+use crate::nomad::installed_version;
+use crate::nomad::HealthProbe;
+use crate::nomad::DEFAULT_NOMAD_ADDR;
+use crate::releases::*;
+use crate::state::Install;
+use crate::state::State;
+use crate::systemd::unit_name;
+use crate::systemd::Systemctl;
+use crate::systemd::Systemd;
+
+use super::cache_args;
+use super::cache_dir_arg;
+use super::endpoint_args;
+use super::endpoints_arg;
+use super::keyrings_arg;
+use super::negating_args;
+use super::path_arg;
+use super::require_fingerprint_arg;
+use super::resolve_version;
+use super::sig_args;
+use super::staging_dir;
+use super::state_file_arg;
+use super::switch_arg;
+use super::validate_pem_file;
+use super::validate_positive;
+use super::validate_url;
+use super::Command;
+use super::DEFAULT_NOMAD_OUT;
+use super::DEFAULT_NOMAD_SERVICE_OUT;
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::App;
+use clap::Arg;
+use clap::ArgMatches;
+use clap::SubCommand;
+
+/// Default number of seconds to wait for the agent to become healthy.
+const DEFAULT_HEALTH_TIMEOUT: u64 = 30;
+
+/// Upgrade command.
+pub struct UpgradeCmd {
+    version: Option<String>,
+    /// check the integrity of the zip archive
+    check_integrity: bool,
+    /// check the signature of the shasums
+    check_sig: bool,
+    /// where the nomad binary is installed
+    out: PathBuf,
+    /// where the nomad service file is installed
+    service_out: PathBuf,
+    /// whether to ignore alerts or not
+    ignore_alerts: bool,
+    /// whether to ignore if a version is outdated or not
+    ignore_outdated: bool,
+    /// whether to upgrade even if the version is already installed
+    force: bool,
+    /// address of the local nomad agent
+    nomad_addr: String,
+    /// CA certificate of the local nomad agent, None for $NOMAD_CACERT
+    nomad_cacert: Option<PathBuf>,
+    /// client certificate for the local nomad agent, None for $NOMAD_CLIENT_CERT
+    nomad_client_cert: Option<PathBuf>,
+    /// key of the client certificate, None for $NOMAD_CLIENT_KEY
+    nomad_client_key: Option<PathBuf>,
+    /// how long to wait for the agent to become healthy
+    health_timeout: Duration,
+    /// whether to use the versioned install layout
+    versioned: bool,
+    /// prefix for the versioned install layout
+    prefix: PathBuf,
+    /// directory of the artifact cache, if caching is enabled
+    cache_dir: Option<PathBuf>,
+    /// the remote endpoints
+    endpoints: Endpoints,
+    /// armoured keyrings to trust in addition to the HashiCorp keys
+    keyrings: Vec<PathBuf>,
+    /// fingerprint of the key that must have signed the shasums
+    require_fingerprint: Option<String>,
+    /// the install manifest
+    state_file: PathBuf,
+    /// how systemd is reached
+    systemd: Box<dyn Systemd>,
+}
+
+impl Command for UpgradeCmd {
+    const NAME: &'static str = "upgrade";
+
+    fn new(args: &ArgMatches, config: &Config) -> Self {
+        Self {
+            version: args
+                .value_of("version")
+                .or_else(|| config.version())
+                .map(|value| value.to_string()),
+            check_integrity: !switch_arg(args, "skip-sums", "check-sums", config.skip_sums()),
+            check_sig: !switch_arg(args, "skip-sig", "check-sig", config.skip_sig()),
+            out: path_arg(args, "out", config.out(), DEFAULT_NOMAD_OUT),
+            service_out: path_arg(
+                args,
+                "service-out",
+                config.service_out(),
+                DEFAULT_NOMAD_SERVICE_OUT,
+            ),
+            ignore_alerts: switch_arg(
+                args,
+                "ignore-alerts",
+                "no-ignore-alerts",
+                config.ignore_alerts(),
+            ),
+            ignore_outdated: switch_arg(
+                args,
+                "ignore-outdated",
+                "no-ignore-outdated",
+                config.ignore_outdated(),
+            ),
+            force: args.is_present("force"),
+            nomad_addr: nomad_addr_arg(args, config),
+            nomad_cacert: args.value_of("nomad-cacert").map(PathBuf::from),
+            nomad_client_cert: args.value_of("nomad-client-cert").map(PathBuf::from),
+            nomad_client_key: args.value_of("nomad-client-key").map(PathBuf::from),
+            health_timeout: Duration::from_secs(
+                args.value_of("health-timeout")
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(DEFAULT_HEALTH_TIMEOUT),
+            ),
+            versioned: switch_arg(args, "versioned", "no-versioned", config.versioned()),
+            prefix: path_arg(args, "prefix", config.prefix(), DEFAULT_PREFIX),
+            cache_dir: cache_dir_arg(args, config),
+            endpoints: endpoints_arg(args, config),
+            keyrings: keyrings_arg(args, config),
+            require_fingerprint: require_fingerprint_arg(args, config),
+            state_file: state_file_arg(config),
+            systemd: Box::new(Systemctl),
+        }
+    }
+
+    fn register(app: App<'static, 'static>) -> App<'static, 'static> {
+        let upgrade = SubCommand::with_name(Self::NAME)
+            .about("Upgrade Nomad, rolling back if the agent does not become healthy.")
+            .arg(Arg::with_name("version").long("version").takes_value(true).help(
+                "The version of Nomad to upgrade to, or a version constraint. If omitted, the latest version shall be used.",
+            ))
+            .arg(Arg::with_name("skip-sums").long("skip-sums").help(
+                "Skip checking the sha256sums on the zip archive.",
+            ))
+            .arg(Arg::with_name("skip-sig").long("skip-sig").help(
+                "Skip checking the signature of the sha256sums file. This has no effect if --skip-sums is used.",
+            ))
+            .arg(Arg::with_name("out").short("o").long("out").takes_value(true).help(
+                "Where the nomad binary is placed.",
+            ))
+            .arg(Arg::with_name("service-out").long("service-out").takes_value(true).help(
+                "Where the nomad systemd service file is placed.",
+            ))
+            .arg(Arg::with_name("ignore-alerts").long("ignore-alerts").help(
+                "Ignore alerts for a version, if there are any alerts.",
+            ))
+            .arg(Arg::with_name("ignore-outdated").long("ignore-outdated").help(
+                "Ignore whether a version is outdated.",
+            ))
+            .arg(Arg::with_name("force").long("force").help(
+                "Upgrade even if the version is already installed.",
+            ))
+            .arg(Arg::with_name("nomad-addr").long("nomad-addr").takes_value(true).validator(validate_url).help(
+                "Address of the local nomad agent. Defaults to $NOMAD_ADDR or http://127.0.0.1:4646.",
+            ))
+            .arg(Arg::with_name("nomad-cacert").long("nomad-cacert").takes_value(true).validator(validate_pem_file).help(
+                "CA certificate of the local nomad agent, if it has TLS enabled. Defaults to $NOMAD_CACERT.",
+            ))
+            .arg(Arg::with_name("nomad-client-cert").long("nomad-client-cert").takes_value(true).validator(validate_pem_file).help(
+                "Client certificate to present to the local nomad agent. Defaults to $NOMAD_CLIENT_CERT.",
+            ))
+            .arg(Arg::with_name("nomad-client-key").long("nomad-client-key").takes_value(true).validator(validate_pem_file).help(
+                "Key of the client certificate. Defaults to $NOMAD_CLIENT_KEY.",
+            ))
+            .arg(Arg::with_name("health-timeout").long("health-timeout").takes_value(true).validator(validate_positive).help(
+                "How many seconds to wait for the agent to become healthy before rolling back. Defaults to 30.",
+            ))
+            .arg(Arg::with_name("versioned").long("versioned").help(
+                "Upgrade within the versioned install layout, switching the nomad binary symlink.",
+            ))
+            .arg(Arg::with_name("prefix").long("prefix").takes_value(true).requires("versioned").help(
+                "Prefix for the versioned install layout. Defaults to /opt.",
+            ))
+            .args(&negating_args())
+            .args(&cache_args())
+            .args(&endpoint_args())
+            .args(&sig_args());
+        app.subcommand(upgrade)
+    }
+
+    fn run(&self) -> anyhow::Result<()> {
+        // Check the probe settings before anything is downloaded or replaced.
+        let probe = HealthProbe::new(
+            self.nomad_addr.as_str(),
+            self.nomad_cacert.clone(),
+            self.nomad_client_cert.clone(),
+            self.nomad_client_key.clone(),
+        )
+        .map_err(|e| anyhow::anyhow!("failed to configure the nomad health check: {}", e))?;
+
+        let mut state = State::load(&self.state_file)?;
+        let out = resolve_out(&self.out, "nomad")?;
+        let recorded = state.get(&out).cloned();
+        let installed = match &recorded {
+            Some(install) if install.binary_matches(&out)? => install.version().to_string(),
+            _ => {
+                if recorded.is_some() {
+                    log::warn!(
+                        "{} was modified since it was installed, asking it for its version",
+                        out.display()
+                    );
+                }
+                if let Some(value) = installed_version(&out)? {
+                    value
+                } else {
+                    anyhow::bail!("nomad is not installed at {}", out.display());
+                }
+            }
+        };
+        log::info!("installed version is {}", installed);
+        let versioned = self.versioned || recorded.as_ref().is_some_and(|value| value.versioned());
+
+        let version = resolve_version(
+            &self.endpoints,
+            opt_string_to_opt_str(&self.version),
+            self.ignore_outdated,
+            self.ignore_alerts,
+        )?;
+        if version == installed && !self.force {
+            log::info!("version {} is already installed", version);
+            return Ok(());
+        }
+
+        log::info!("attempting to upgrade from {} to {}", installed, version);
+
+        let layout = VersionedLayout::new(&self.prefix);
+        let bin = get(
+            version.as_str(),
+            Some(
+                ReleaseGetOpts::new(
+                    self.check_integrity,
+                    self.check_sig,
+                    self.cache_dir.clone(),
+                    self.endpoints.clone(),
+                )
+                .with_keys(self.keyrings.clone(), self.require_fingerprint.clone())
+                .with_staging_dir(staging_dir(
+                    &out,
+                    versioned.then_some((&layout, version.as_str())),
+                )?),
+            ),
+        )?;
+        log::info!("nomad binary ready for installation");
+
+        let backup = if versioned {
+            if layout.active(&out)?.as_deref() != Some(installed.as_str()) {
+                anyhow::bail!(
+                    "{} does not point to version {} in {}",
+                    out.display(),
+                    installed,
+                    self.prefix.display()
+                );
+            }
+
+            install_file_from(&layout.binary(version.as_str()), bin.path(), 0o755, None)?;
+            layout.activate(version.as_str(), &out)?;
+            None
+        } else {
+            let backup = backup_path(&out);
+            fs::copy(&out, &backup)?;
+            log::info!("previous nomad binary backed up to {}", backup.display());
+
+            install_file_from(&out, bin.path(), 0o755, None)?;
+            Some(backup)
+        };
+        log::info!("nomad binary upgraded");
+
+        let service_out =
+            if let Some(value) = recorded.as_ref().and_then(|value| value.service_file()) {
+                value.to_path_buf()
+            } else {
+                resolve_out(&self.service_out, "nomad.service")?
+            };
+        let unit = unit_name(&service_out)?;
+
+        restart_or_roll_back(
+            self.systemd.as_ref(),
+            unit,
+            &|| self.wait_healthy(&probe),
+            &mut || {
+                if let Some(backup) = &backup {
+                    install_file_from(&out, backup, 0o755, None)
+                } else {
+                    layout.activate(installed.as_str(), &out)
+                }
+            },
+            version.as_str(),
+            installed.as_str(),
+        )?;
+
+        log::info!("nomad agent is healthy on version {}", version);
+
+        let mut files = vec![out.clone(), service_out.clone()];
+        if versioned {
+            files.push(layout.binary(version.as_str()));
+        }
+        if let Some(previous) = &recorded {
+            files.extend(previous.files().iter().cloned());
+        }
+        state.record(
+            Install::new(out, version.as_str(), &bin, versioned)?
+                .with_service_file(service_out)
+                .with_files(files),
+        );
+        state.save(&self.state_file)?;
+        log::info!("upgrade recorded in {}", self.state_file.display());
+
+        Ok(())
+    }
+}
+
+impl UpgradeCmd {
+    /// Wait for the agent to become healthy.
+    fn wait_healthy(&self, probe: &HealthProbe) -> anyhow::Result<()> {
+        if !probe.wait_healthy(self.health_timeout) {
+            anyhow::bail!(
+                "agent at {} did not become healthy within {}s",
+                probe.addr(),
+                self.health_timeout.as_secs()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Restart the nomad unit on the new version, rolling back if the agent does not become healthy.
+///
+/// `roll_back` puts the previous binary back in place, after which the unit is restarted again.
+fn restart_or_roll_back(
+    systemd: &dyn Systemd,
+    unit: &str,
+    wait_healthy: &dyn Fn() -> anyhow::Result<()>,
+    roll_back: &mut dyn FnMut() -> anyhow::Result<()>,
+    version: &str,
+    installed: &str,
+) -> anyhow::Result<()> {
+    let e = match restart_and_wait(systemd, unit, wait_healthy) {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    log::error!("upgrade to {} failed: {}; rolling back", version, e);
+
+    roll_back().map_err(|e| {
+        anyhow::anyhow!(
+            "upgrade to {} failed and rolling back to {} failed: {}",
+            version,
+            installed,
+            e
+        )
+    })?;
+    if let Err(e) = restart_and_wait(systemd, unit, wait_healthy) {
+        anyhow::bail!(
+            "upgrade to {} failed, rolled back to {}, but the agent did not recover: {}",
+            version,
+            installed,
+            e
+        );
+    }
+
+    anyhow::bail!(
+        "upgrade to {} failed, rolled back to {}",
+        version,
+        installed
+    );
+}
+
+/// Restart the nomad unit and wait for the agent to become healthy.
+///
+/// Failures include the last journal lines of the unit.
+fn restart_and_wait(
+    systemd: &dyn Systemd,
+    unit: &str,
+    wait_healthy: &dyn Fn() -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    systemd
+        .unit_action("restart", unit)
+        .map_err(|e| systemd.with_journal(unit, e))?;
+    log::info!(
+        "restarted {}, waiting for the agent to become healthy",
+        unit
+    );
+    wait_healthy().map_err(|e| systemd.with_journal(unit, e))
+}
+
+/// Get the address of the local nomad agent from the flags, the configuration and `NOMAD_ADDR`.
+fn nomad_addr_arg(args: &ArgMatches, config: &Config) -> String {
+    if let Some(value) = args.value_of("nomad-addr").or_else(|| config.nomad_addr()) {
+        value.to_string()
+    } else if let Ok(value) = std::env::var("NOMAD_ADDR") {
+        value
+    } else {
+        DEFAULT_NOMAD_ADDR.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::restart_or_roll_back;
+    use super::Command;
+    use super::UpgradeCmd;
+
+    use crate::systemd::FakeSystemd;
+
+    use clap::App;
+
+    use std::cell::Cell;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    const UNIT: &str = "nomad.service";
+
+    /// Health checks that return the results in order.
+    struct Health {
+        results: RefCell<VecDeque<bool>>,
+    }
+
+    impl Health {
+        fn new(results: &[bool]) -> Self {
+            Self {
+                results: RefCell::new(results.iter().copied().collect()),
+            }
+        }
+
+        fn wait(&self) -> anyhow::Result<()> {
+            if self.results.borrow_mut().pop_front().unwrap_or(false) {
+                Ok(())
+            } else {
+                anyhow::bail!("agent did not become healthy")
+            }
+        }
+    }
+
+    /// Run restart_or_roll_back, returning its result and how often it rolled back.
+    fn upgrade(
+        systemd: &FakeSystemd,
+        health: &Health,
+        roll_back_fails: bool,
+    ) -> (anyhow::Result<()>, usize) {
+        let rollbacks = Cell::new(0);
+        let res = restart_or_roll_back(
+            systemd,
+            UNIT,
+            &|| health.wait(),
+            &mut || {
+                rollbacks.set(rollbacks.get() + 1);
+                if roll_back_fails {
+                    anyhow::bail!("no backup");
+                }
+                Ok(())
+            },
+            "1.0.2",
+            "1.0.1",
+        );
+        (res, rollbacks.get())
+    }
+
+    #[test]
+    fn healthy_upgrade() {
+        let systemd = FakeSystemd::new(&[]);
+        let (res, rollbacks) = upgrade(&systemd, &Health::new(&[true]), false);
+        assert!(res.is_ok());
+        assert_eq!(rollbacks, 0);
+        assert_eq!(systemd.actions(), vec!["restart nomad.service"]);
+    }
+
+    #[test]
+    fn unhealthy_upgrade_rolls_back() {
+        let systemd = FakeSystemd::new(&[]).with_journal_lines(&["nomad: panic"]);
+        let (res, rollbacks) = upgrade(&systemd, &Health::new(&[false, true]), false);
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "upgrade to 1.0.2 failed, rolled back to 1.0.1"
+        );
+        assert_eq!(rollbacks, 1);
+        assert_eq!(
+            systemd.actions(),
+            vec!["restart nomad.service", "restart nomad.service"]
+        );
+    }
+
+    #[test]
+    fn failed_restart_rolls_back() {
+        let systemd = FakeSystemd::new(&[]).failing("restart nomad.service");
+        let (res, rollbacks) = upgrade(&systemd, &Health::new(&[true, true]), false);
+        let e = res.unwrap_err().to_string();
+        assert!(e.starts_with("upgrade to 1.0.2 failed, rolled back to 1.0.1, but the agent did not recover: systemctl restart nomad.service exited"));
+        assert_eq!(rollbacks, 1);
+    }
+
+    #[test]
+    fn rollback_then_restart_fails() {
+        let systemd = FakeSystemd::new(&[]).with_journal_lines(&["nomad: panic"]);
+        let (res, rollbacks) = upgrade(&systemd, &Health::new(&[false, false]), false);
+        let e = res.unwrap_err().to_string();
+        assert!(e.starts_with(
+            "upgrade to 1.0.2 failed, rolled back to 1.0.1, but the agent did not recover: agent did not become healthy"
+        ));
+        assert!(e.ends_with("last journal lines of nomad.service:\nnomad: panic"));
+        assert_eq!(rollbacks, 1);
+        assert_eq!(systemd.actions().len(), 2);
+    }
+
+    #[test]
+    fn failed_rollback_is_not_restarted() {
+        let systemd = FakeSystemd::new(&[]);
+        let (res, rollbacks) = upgrade(&systemd, &Health::new(&[false]), true);
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "upgrade to 1.0.2 failed and rolling back to 1.0.1 failed: no backup"
+        );
+        assert_eq!(rollbacks, 1);
+        assert_eq!(systemd.actions(), vec!["restart nomad.service"]);
+    }
+
+    #[test]
+    fn health_timeout_is_validated() {
+        let parse = |value: &str| {
+            UpgradeCmd::register(App::new("nomadutil")).get_matches_from_safe([
+                "nomadutil",
+                "upgrade",
+                "--health-timeout",
+                value,
+            ])
+        };
+        assert!(parse("60").is_ok());
+        assert!(parse("0").is_err());
+        assert!(parse("-1").is_err());
+        assert!(parse("a minute").is_err());
+    }
+}
@@ -0,0 +1,93 @@
+//! Switch the active Nomad version.
+
+use crate::common::resolve_out;
+use crate::config::Config;
+use crate::layout::check_version;
+use crate::layout::VersionedLayout;
+use crate::layout::DEFAULT_PREFIX;
+use crate::state::State;
+
+use super::path_arg;
+use super::state_file_arg;
+use super::Command;
+use super::DEFAULT_NOMAD_OUT;
+
+use std::path::PathBuf;
+
+use clap::App;
+use clap::Arg;
+use clap::ArgMatches;
+use clap::SubCommand;
+
+/// Use command.
+pub struct UseCmd {
+    /// the version to switch to
+    version: String,
+    /// where the nomad binary symlink is placed
+    out: PathBuf,
+    /// prefix of the versioned install layout
+    prefix: PathBuf,
+    /// the install manifest
+    state_file: PathBuf,
+}
+
+impl Command for UseCmd {
+    const NAME: &'static str = "use";
+
+    fn new(args: &ArgMatches, config: &Config) -> Self {
+        Self {
+            version: args.value_of("version").unwrap_or_default().to_string(),
+            out: path_arg(args, "out", config.out(), DEFAULT_NOMAD_OUT),
+            prefix: path_arg(args, "prefix", config.prefix(), DEFAULT_PREFIX),
+            state_file: state_file_arg(config),
+        }
+    }
+
+    fn register(app: App<'static, 'static>) -> App<'static, 'static> {
+        let use_version = SubCommand::with_name(Self::NAME)
+            .about("Switch to an already installed version of Nomad. A nomad binary at the symlink path that is not in the versioned layout is backed up to <out>.bak.")
+            .arg(
+                Arg::with_name("version")
+                    .required(true)
+                    .help("The version of Nomad to switch to."),
+            )
+            .arg(
+                Arg::with_name("out")
+                    .short("o")
+                    .long("out")
+                    .takes_value(true)
+                    .help("Where the nomad binary symlink is placed."),
+            )
+            .arg(
+                Arg::with_name("prefix")
+                    .long("prefix")
+                    .takes_value(true)
+                    .help("Prefix of the versioned install layout. Defaults to /opt."),
+            );
+        app.subcommand(use_version)
+    }
+
+    fn run(&self) -> anyhow::Result<()> {
+        check_version(self.version.as_str())?;
+        let layout = VersionedLayout::new(&self.prefix);
+        let out = resolve_out(&self.out, "nomad")?;
+
+        if layout.active(&out)?.as_deref() == Some(self.version.as_str()) {
+            log::info!("version {} is already in use", self.version);
+            return Ok(());
+        }
+
+        let mut state = State::load(&self.state_file)?;
+        layout.activate(self.version.as_str(), &out)?;
+        log::info!("{} now points to version {}", out.display(), self.version);
+
+        if let Some(mut install) = state.get(&out).cloned() {
+            install.switch(self.version.as_str(), &layout.binary(self.version.as_str()))?;
+            state.record(install);
+            state.save(&self.state_file)?;
+        }
+        log::info!("restart the nomad service for the change to take effect");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+//! Verify an installed Nomad.
+
+use crate::artifacts::sha256_file;
+use crate::common::resolve_out;
+use crate::common::Endpoints;
+use crate::config::Config;
+use crate::nomad::installed_version;
+use crate::releases::*;
+use crate::state::State;
+
+use super::cache_args;
+use super::cache_dir_arg;
+use super::endpoint_args;
+use super::endpoints_arg;
+use super::keyrings_arg;
+use super::path_arg;
+use super::require_fingerprint_arg;
+use super::sig_args;
+use super::staging_dir;
+use super::state_file_arg;
+use super::Command;
+use super::DEFAULT_NOMAD_OUT;
+
+use std::path::PathBuf;
+
+use clap::App;
+use clap::Arg;
+use clap::ArgMatches;
+use clap::SubCommand;
+
+/// Verify command.
+pub struct VerifyCmd {
+    /// the nomad binary to verify
+    path: PathBuf,
+    /// directory of the artifact cache, if caching is enabled
+    cache_dir: Option<PathBuf>,
+    /// the remote endpoints
+    endpoints: Endpoints,
+    /// armoured keyrings to trust in addition to the HashiCorp keys
+    keyrings: Vec<PathBuf>,
+    /// fingerprint of the key that must have signed the shasums
+    require_fingerprint: Option<String>,
+    /// the install manifest
+    state_file: PathBuf,
+}
+
+impl Command for VerifyCmd {
+    const NAME: &'static str = "verify";
+
+    fn new(args: &ArgMatches, config: &Config) -> Self {
+        Self {
+            path: path_arg(args, "path", config.out(), DEFAULT_NOMAD_OUT),
+            cache_dir: cache_dir_arg(args, config),
+            endpoints: endpoints_arg(args, config),
+            keyrings: keyrings_arg(args, config),
+            require_fingerprint: require_fingerprint_arg(args, config),
+            state_file: state_file_arg(config),
+        }
+    }
+
+    fn register(app: App<'static, 'static>) -> App<'static, 'static> {
+        let verify = SubCommand::with_name(Self::NAME)
+            .about(
+                "Verify that an installed nomad binary matches the signed release of its version.",
+            )
+            .arg(
+                Arg::with_name("path").index(1).help(
+                    "The nomad binary, or the directory holding it. Defaults to /usr/local/bin.",
+                ),
+            )
+            .args(&cache_args())
+            .args(&endpoint_args())
+            .args(&sig_args());
+        app.subcommand(verify)
+    }
+
+    fn run(&self) -> anyhow::Result<()> {
+        let state = State::load(&self.state_file)?;
+        let path = resolve_out(&self.path, "nomad")?;
+        if !path.exists() {
+            anyhow::bail!("nomad is not installed at {}", path.display());
+        }
+
+        // Prefer the manifest, so a tampered binary is never run to ask for its version.
+        let version = if let Some(install) = state.get(&path) {
+            if !install.binary_matches(&path)? {
+                anyhow::bail!(
+                    "{} does not match the binary recorded at install time, sha256 {}",
+                    path.display(),
+                    install.binary_sha256()
+                );
+            }
+            log::info!(
+                "{} matches the install manifest, version {}",
+                path.display(),
+                install.version()
+            );
+            install.version().to_string()
+        } else {
+            log::warn!(
+                "{} is not in the install manifest, asking it for its version",
+                path.display()
+            );
+            let version = installed_version(&path)?
+                .ok_or_else(|| anyhow::anyhow!("nomad is not installed at {}", path.display()))?;
+            log::info!("{} reports version {}", path.display(), version);
+            version
+        };
+
+        // The signed sha256sums only cover the zip archive, so the binary is compared against
+        // the one unpacked from the verified archive.
+        let release = get(
+            version.as_str(),
+            Some(
+                ReleaseGetOpts::new(true, true, self.cache_dir.clone(), self.endpoints.clone())
+                    .with_keys(self.keyrings.clone(), self.require_fingerprint.clone())
+                    .with_staging_dir(staging_dir(&path, None)?),
+            ),
+        )?;
+
+        let expected = sha256_file(release.path())?;
+        let actual = sha256_file(&path)?;
+        if actual != expected {
+            anyhow::bail!(
+                "{} does not match the nomad {} release: sha256 is {}, expected {}",
+                path.display(),
+                version,
+                hex::encode(actual),
+                hex::encode(expected)
+            );
+        }
+
+        log::info!(
+            "{} matches the signed nomad {} release",
+            path.display(),
+            version
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+//! List the available Nomad versions.
+
+use crate::common::resolve_out;
+use crate::common::Endpoints;
+use crate::config::Config;
+use crate::nomad::installed_version;
+use crate::releases::*;
+
+use super::endpoints_arg;
+use super::http_args;
+use super::path_arg;
+use super::Command;
+use super::DEFAULT_NOMAD_OUT;
+
+use std::path::PathBuf;
+
+use clap::App;
+use clap::Arg;
+use clap::ArgMatches;
+use clap::SubCommand;
+
+use serde::Serialize;
+
+/// Versions command.
+pub struct VersionsCmd {
+    /// include prereleases
+    prereleases: bool,
+    /// include enterprise releases
+    enterprise: bool,
+    /// print the versions as json
+    json: bool,
+    /// where the nomad binary is installed
+    out: PathBuf,
+    /// the remote endpoints
+    endpoints: Endpoints,
+}
+
+/// A version as printed by the versions command.
+#[derive(Serialize)]
+struct VersionEntry<'a> {
+    version: &'a str,
+    prerelease: bool,
+    enterprise: bool,
+    installed: bool,
+    url: &'a str,
+}
+
+impl Command for VersionsCmd {
+    const NAME: &'static str = "versions";
+
+    fn new(args: &ArgMatches, config: &Config) -> Self {
+        Self {
+            prereleases: args.is_present("prereleases"),
+            enterprise: args.is_present("enterprise"),
+            json: args.is_present("json"),
+            out: path_arg(args, "out", config.out(), DEFAULT_NOMAD_OUT),
+            endpoints: endpoints_arg(args, config),
+        }
+    }
+
+    fn register(app: App<'static, 'static>) -> App<'static, 'static> {
+        let versions = SubCommand::with_name(Self::NAME)
+            .about("List the available versions of Nomad.")
+            .arg(
+                Arg::with_name("prereleases")
+                    .long("prereleases")
+                    .help("Include prereleases (betas, release candidates)."),
+            )
+            .arg(
+                Arg::with_name("enterprise")
+                    .long("enterprise")
+                    .help("Include enterprise releases."),
+            )
+            .arg(
+                Arg::with_name("json")
+                    .long("json")
+                    .help("Print the versions as json."),
+            )
+            .arg(
+                Arg::with_name("out")
+                    .short("o")
+                    .long("out")
+                    .takes_value(true)
+                    .help("Where the nomad binary is placed, used to mark the installed version."),
+            )
+            .arg(
+                Arg::with_name("releases-url")
+                    .long("releases-url")
+                    .takes_value(true)
+                    .help("Base url of the releases, e.g. a mirror. Defaults to https://releases.hashicorp.com."),
+            )
+            .args(&http_args());
+        app.subcommand(versions)
+    }
+
+    fn run(&self) -> anyhow::Result<()> {
+        let installed = installed_version(&resolve_out(&self.out, "nomad")?)?;
+
+        let releases = list(Some(ReleaseListOpts::new(
+            self.prereleases,
+            self.enterprise,
+            &self.endpoints,
+        )))?;
+        let entries: Vec<VersionEntry> = releases
+            .iter()
+            .filter_map(|release| {
+                let build = release.build("linux", crate::ARCH)?;
+                Some(VersionEntry {
+                    version: release.version(),
+                    prerelease: release.is_prerelease(),
+                    enterprise: release.is_enterprise(),
+                    installed: installed.as_deref() == Some(release.version()),
+                    url: build.url(),
+                })
+            })
+            .collect();
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&entries)?);
+            return Ok(());
+        }
+
+        for entry in entries {
+            let marker = if entry.installed { "*" } else { " " };
+            let mut tags = Vec::new();
+            if entry.prerelease {
+                tags.push("prerelease");
+            }
+            if entry.enterprise {
+                tags.push("enterprise");
+            }
+            if tags.is_empty() {
+                println!("{} {}", marker, entry.version);
+            } else {
+                println!("{} {} ({})", marker, entry.version, tags.join(", "));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,21 +1,249 @@
 //! Module for common stuff.
 
-use reqwest::blocking::Client;
+use crate::http::HttpClient;
 
-/// Get an http client.
-pub fn get_http_client() -> Client {
-    Client::builder()
-        .user_agent("github.com/cezarmathe/nomadutil")
-        .build()
-        .expect("failed to create the http client")
+use std::cmp::Ordering;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Read;
+use std::os::unix::fs::chown;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+use semver::Version;
+
+/// Default base url of the HashiCorp releases.
+pub const DEFAULT_RELEASES_URL: &str = "https://releases.hashicorp.com";
+/// Default url of the HashiCorp checkpoint api.
+pub const DEFAULT_CHECKPOINT_URL: &str = "https://checkpoint-api.hashicorp.com";
+/// User agent of the http requests.
+pub const USER_AGENT: &str = "github.com/cezarmathe/nomadutil";
+
+/// The remote endpoints nomadutil talks to.
+#[derive(Clone, Debug)]
+pub struct Endpoints {
+    /// base url of the releases, e.g. a mirror
+    releases_url: String,
+    /// url of the checkpoint api, None if checkpoint is disabled
+    checkpoint_url: Option<String>,
+    /// the client used to talk to the endpoints
+    http: HttpClient,
+}
+
+impl Default for Endpoints {
+    #[allow(missing_docs)]
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_RELEASES_URL.to_string(),
+            Some(DEFAULT_CHECKPOINT_URL.to_string()),
+            HttpClient::default(),
+        )
+    }
+}
+
+impl Endpoints {
+    /// Create new Endpoints.
+    #[inline]
+    pub fn new(releases_url: String, checkpoint_url: Option<String>, http: HttpClient) -> Self {
+        Self {
+            releases_url: releases_url.trim_end_matches('/').to_string(),
+            checkpoint_url: checkpoint_url.map(|value| value.trim_end_matches('/').to_string()),
+            http,
+        }
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn releases_url(&self) -> &str {
+        self.releases_url.as_str()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn checkpoint_url(&self) -> Option<&str> {
+        self.checkpoint_url.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn http(&self) -> &HttpClient {
+        &self.http
+    }
 }
 
 /// Convert an Option<String> to an Option<&str>
 #[inline]
 pub fn opt_string_to_opt_str(src: &Option<String>) -> Option<&str> {
-    if let Some(value) = src {
-        Some(value.as_str())
+    src.as_deref()
+}
+
+/// Compare two versions semantically, falling back to comparing them as strings.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    match (Version::parse(a), Version::parse(b)) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+/// Resolve an output path.
+///
+/// Relative paths are canonicalized and, if the path points to a directory, `file_name` is
+/// appended to it.
+pub fn resolve_out(path: &Path, file_name: &str) -> anyhow::Result<PathBuf> {
+    let mut out = if !path.is_absolute() {
+        path.canonicalize()?
+    } else {
+        path.to_path_buf()
+    };
+    if out.is_dir() {
+        out.push(file_name);
+    }
+    Ok(out)
+}
+
+/// Get the path the previous nomad binary at `path` is backed up to, `<path>.bak`.
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.to_path_buf().into_os_string();
+    backup.push(".bak");
+    PathBuf::from(backup)
+}
+
+/// Atomically install a file.
+///
+/// The contents are written to a temporary file in the same directory as `path`, synced to disk,
+/// given `mode` and `owner` and then renamed over `path`. If `owner` is None, the owner of the
+/// file being replaced is kept.
+///
+/// Readers of `path` (including a running nomad agent) only ever see the old or the new file.
+pub fn install_file(
+    path: &Path,
+    contents: &[u8],
+    mode: u32,
+    owner: Option<(u32, u32)>,
+) -> anyhow::Result<()> {
+    install_from_reader(path, &mut &contents[..], mode, owner)
+}
+
+/// Atomically install a file with the contents of another file.
+///
+/// The contents are streamed, so this works for files of any size. See install_file.
+pub fn install_file_from(
+    path: &Path,
+    src: &Path,
+    mode: u32,
+    owner: Option<(u32, u32)>,
+) -> anyhow::Result<()> {
+    let mut src = File::open(src)?;
+    install_from_reader(path, &mut src, mode, owner)
+}
+
+/// Atomically install a file with the contents of a reader.
+fn install_from_reader(
+    path: &Path,
+    contents: &mut dyn Read,
+    mode: u32,
+    owner: Option<(u32, u32)>,
+) -> anyhow::Result<()> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("{} has no parent directory", path.display()))?;
+    let file_name = path
+        .file_name()
+        .and_then(|value| value.to_str())
+        .ok_or_else(|| anyhow::anyhow!("{} has no file name", path.display()))?;
+    let tmp = dir.join(format!(".{}.{}.tmp", file_name, std::process::id()));
+
+    let owner = if owner.is_some() {
+        owner
+    } else if let Ok(metadata) = fs::metadata(path) {
+        Some((metadata.uid(), metadata.gid()))
     } else {
         None
+    };
+
+    let res = write_tmp(&tmp, contents, mode, owner).and_then(|_| {
+        fs::rename(&tmp, path)?;
+        Ok(())
+    });
+    if let Err(e) = res {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+
+    // Sync the directory so the rename itself survives a crash.
+    File::open(dir)?.sync_all()?;
+
+    Ok(())
+}
+
+/// Write the temporary file used by install_file.
+fn write_tmp(
+    tmp: &Path,
+    contents: &mut dyn Read,
+    mode: u32,
+    owner: Option<(u32, u32)>,
+) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(tmp)?;
+    io::copy(contents, &mut file)?;
+    file.sync_all()?;
+
+    if let Some((uid, gid)) = owner {
+        chown(tmp, Some(uid), Some(gid))?;
+    }
+    // The mode passed to open is subject to the umask, so set it explicitly.
+    fs::set_permissions(tmp, fs::Permissions::from_mode(mode))?;
+
+    Ok(())
+}
+
+/// A file that is removed when dropped.
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Reserve a temporary file named after `name` in `dir`.
+    ///
+    /// The file itself is not created.
+    pub fn new(dir: &Path, name: &str) -> Self {
+        Self {
+            path: dir.join(format!(".{}.{}.part", name, std::process::id())),
+        }
     }
+
+    /// Take over an existing file, removing it when dropped.
+    pub fn at(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Get a fresh scratch directory for a test.
+#[cfg(test)]
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("nomadutil-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
 }
@@ -0,0 +1,633 @@
+//! Module for the nomadutil configuration.
+//!
+//! Settings are read, from lowest to highest precedence, from the system configuration file
+//! (`/etc/nomadutil.toml`), the user configuration file
+//! (`$XDG_CONFIG_HOME/nomadutil/config.toml`) and `NOMADUTIL_*` environment variables. Command
+//! line flags take precedence over all of them.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+/// Path of the system configuration file.
+const SYSTEM_CONFIG: &str = "/etc/nomadutil.toml";
+
+/// Prefix of the environment variables that override configuration settings.
+const ENV_PREFIX: &str = "NOMADUTIL_";
+
+/// The nomadutil configuration.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// version or version constraint to install
+    version: Option<String>,
+    /// where to install the nomad binary
+    out: Option<PathBuf>,
+    /// where to install the nomad service file
+    service_out: Option<PathBuf>,
+    /// skip checking the sha256sums on the zip archive
+    skip_sums: Option<bool>,
+    /// skip checking the signature of the sha256sums file
+    skip_sig: Option<bool>,
+    /// ignore alerts for a version
+    ignore_alerts: Option<bool>,
+    /// ignore whether a version is outdated
+    ignore_outdated: Option<bool>,
+    /// use the versioned install layout
+    versioned: Option<bool>,
+    /// prefix for the versioned install layout
+    prefix: Option<PathBuf>,
+    /// the nomad configuration directory
+    config_dir: Option<PathBuf>,
+    /// the nomad data directory
+    data_dir: Option<PathBuf>,
+    /// address of the local nomad agent
+    nomad_addr: Option<String>,
+    /// directory of the artifact cache
+    cache_dir: Option<PathBuf>,
+    /// do not use the artifact cache
+    no_cache: Option<bool>,
+    /// base url of the releases
+    releases_url: Option<String>,
+    /// url of the checkpoint api
+    checkpoint_url: Option<String>,
+    /// do not consult the checkpoint api
+    no_checkpoint: Option<bool>,
+    /// number of attempts for http requests
+    retries: Option<u32>,
+    /// proxy for the http requests
+    proxy: Option<String>,
+    /// hosts to reach without the proxy
+    no_proxy: Option<String>,
+    /// PEM file with extra root certificates
+    ca_cert: Option<PathBuf>,
+    /// PEM file with the client certificate
+    client_cert: Option<PathBuf>,
+    /// PEM file with the key of the client certificate
+    client_key: Option<PathBuf>,
+    /// connect timeout for http requests, in seconds
+    connect_timeout: Option<u64>,
+    /// read timeout for http requests, in seconds
+    read_timeout: Option<u64>,
+    /// armoured gpg keys to trust in addition to the HashiCorp keys
+    trusted_keys: Option<Vec<PathBuf>>,
+    /// fingerprint of the key that must sign the sha256sums
+    require_fingerprint: Option<String>,
+    /// the install manifest
+    state_file: Option<PathBuf>,
+    /// template of the nomad service file
+    service_template: Option<PathBuf>,
+    /// user the nomad agent runs as
+    service_user: Option<String>,
+    /// group the nomad agent runs as
+    service_group: Option<String>,
+    /// environment file of the nomad agent
+    environment_file: Option<PathBuf>,
+    /// extra arguments for nomad agent
+    agent_args: Option<String>,
+    /// LimitNOFILE of the nomad agent
+    limit_nofile: Option<String>,
+    /// LimitNPROC of the nomad agent
+    limit_nproc: Option<String>,
+    /// role of the nomad agent: server, client or dev
+    role: Option<String>,
+    /// datacenter of the nomad agent
+    datacenter: Option<String>,
+    /// region of the nomad agent
+    region: Option<String>,
+    /// address the nomad agent binds to
+    bind_addr: Option<String>,
+    /// number of servers to wait for before bootstrapping the cluster
+    bootstrap_expect: Option<u32>,
+    /// servers a nomad client registers with
+    servers: Option<Vec<String>>,
+    /// addresses to join the cluster through
+    retry_join: Option<Vec<String>>,
+    /// plugin options, as <plugin>.<key>=<value>
+    plugin_options: Option<Vec<String>>,
+    /// directory of the nomad TLS certificates
+    tls_dir: Option<PathBuf>,
+}
+
+impl Config {
+    /// Load the configuration from the configuration files and the environment.
+    pub fn load() -> anyhow::Result<Self> {
+        let mut config = Self::default();
+
+        for path in config_files() {
+            if !path.is_file() {
+                log::trace!("config file {} not found", path.display());
+                continue;
+            }
+            log::debug!("loading config file {}", path.display());
+            config = config.merge(Self::from_file(&path)?);
+        }
+
+        Ok(config.merge(Self::from_env()?))
+    }
+
+    /// Read the configuration from a toml file.
+    fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        toml::from_str(contents.as_str())
+            .map_err(|e| anyhow::anyhow!("bad config file {}: {}", path.display(), e))
+    }
+
+    /// Read the configuration from the environment.
+    fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            version: env_var("VERSION"),
+            out: env_var("OUT").map(PathBuf::from),
+            service_out: env_var("SERVICE_OUT").map(PathBuf::from),
+            skip_sums: env_bool("SKIP_SUMS")?,
+            skip_sig: env_bool("SKIP_SIG")?,
+            ignore_alerts: env_bool("IGNORE_ALERTS")?,
+            ignore_outdated: env_bool("IGNORE_OUTDATED")?,
+            versioned: env_bool("VERSIONED")?,
+            prefix: env_var("PREFIX").map(PathBuf::from),
+            config_dir: env_var("CONFIG_DIR").map(PathBuf::from),
+            data_dir: env_var("DATA_DIR").map(PathBuf::from),
+            nomad_addr: env_var("NOMAD_ADDR"),
+            cache_dir: env_var("CACHE_DIR").map(PathBuf::from),
+            no_cache: env_bool("NO_CACHE")?,
+            releases_url: env_var("RELEASES_URL"),
+            checkpoint_url: env_var("CHECKPOINT_URL"),
+            no_checkpoint: env_bool("NO_CHECKPOINT")?,
+            retries: env_parse("RETRIES")?,
+            proxy: env_var("PROXY"),
+            no_proxy: env_var("NO_PROXY"),
+            ca_cert: env_var("CA_CERT").map(PathBuf::from),
+            client_cert: env_var("CLIENT_CERT").map(PathBuf::from),
+            client_key: env_var("CLIENT_KEY").map(PathBuf::from),
+            connect_timeout: env_parse("CONNECT_TIMEOUT")?,
+            read_timeout: env_parse("READ_TIMEOUT")?,
+            trusted_keys: env_var("TRUSTED_KEYS").map(|value| env::split_paths(&value).collect()),
+            require_fingerprint: env_var("REQUIRE_FINGERPRINT"),
+            state_file: env_var("STATE_FILE").map(PathBuf::from),
+            service_template: env_var("SERVICE_TEMPLATE").map(PathBuf::from),
+            service_user: env_var("SERVICE_USER"),
+            service_group: env_var("SERVICE_GROUP"),
+            environment_file: env_var("ENVIRONMENT_FILE").map(PathBuf::from),
+            agent_args: env_var("AGENT_ARGS"),
+            limit_nofile: env_var("LIMIT_NOFILE"),
+            limit_nproc: env_var("LIMIT_NPROC"),
+            role: env_var("ROLE"),
+            datacenter: env_var("DATACENTER"),
+            region: env_var("REGION"),
+            bind_addr: env_var("BIND_ADDR"),
+            bootstrap_expect: env_parse("BOOTSTRAP_EXPECT")?,
+            servers: env_list("SERVERS"),
+            retry_join: env_list("RETRY_JOIN"),
+            plugin_options: env_list("PLUGIN_OPTIONS"),
+            tls_dir: env_var("TLS_DIR").map(PathBuf::from),
+        })
+    }
+
+    /// Merge another configuration on top of this one.
+    ///
+    /// Settings present in `other` take precedence.
+    fn merge(self, other: Self) -> Self {
+        Self {
+            version: other.version.or(self.version),
+            out: other.out.or(self.out),
+            service_out: other.service_out.or(self.service_out),
+            skip_sums: other.skip_sums.or(self.skip_sums),
+            skip_sig: other.skip_sig.or(self.skip_sig),
+            ignore_alerts: other.ignore_alerts.or(self.ignore_alerts),
+            ignore_outdated: other.ignore_outdated.or(self.ignore_outdated),
+            versioned: other.versioned.or(self.versioned),
+            prefix: other.prefix.or(self.prefix),
+            config_dir: other.config_dir.or(self.config_dir),
+            data_dir: other.data_dir.or(self.data_dir),
+            nomad_addr: other.nomad_addr.or(self.nomad_addr),
+            cache_dir: other.cache_dir.or(self.cache_dir),
+            no_cache: other.no_cache.or(self.no_cache),
+            releases_url: other.releases_url.or(self.releases_url),
+            checkpoint_url: other.checkpoint_url.or(self.checkpoint_url),
+            no_checkpoint: other.no_checkpoint.or(self.no_checkpoint),
+            retries: other.retries.or(self.retries),
+            proxy: other.proxy.or(self.proxy),
+            no_proxy: other.no_proxy.or(self.no_proxy),
+            ca_cert: other.ca_cert.or(self.ca_cert),
+            client_cert: other.client_cert.or(self.client_cert),
+            client_key: other.client_key.or(self.client_key),
+            connect_timeout: other.connect_timeout.or(self.connect_timeout),
+            read_timeout: other.read_timeout.or(self.read_timeout),
+            trusted_keys: other.trusted_keys.or(self.trusted_keys),
+            require_fingerprint: other.require_fingerprint.or(self.require_fingerprint),
+            state_file: other.state_file.or(self.state_file),
+            service_template: other.service_template.or(self.service_template),
+            service_user: other.service_user.or(self.service_user),
+            service_group: other.service_group.or(self.service_group),
+            environment_file: other.environment_file.or(self.environment_file),
+            agent_args: other.agent_args.or(self.agent_args),
+            limit_nofile: other.limit_nofile.or(self.limit_nofile),
+            limit_nproc: other.limit_nproc.or(self.limit_nproc),
+            role: other.role.or(self.role),
+            datacenter: other.datacenter.or(self.datacenter),
+            region: other.region.or(self.region),
+            bind_addr: other.bind_addr.or(self.bind_addr),
+            bootstrap_expect: other.bootstrap_expect.or(self.bootstrap_expect),
+            servers: other.servers.or(self.servers),
+            retry_join: other.retry_join.or(self.retry_join),
+            plugin_options: other.plugin_options.or(self.plugin_options),
+            tls_dir: other.tls_dir.or(self.tls_dir),
+        }
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn out(&self) -> Option<&Path> {
+        self.out.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn service_out(&self) -> Option<&Path> {
+        self.service_out.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn skip_sums(&self) -> bool {
+        self.skip_sums.unwrap_or(false)
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn skip_sig(&self) -> bool {
+        self.skip_sig.unwrap_or(false)
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn ignore_alerts(&self) -> bool {
+        self.ignore_alerts.unwrap_or(false)
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn ignore_outdated(&self) -> bool {
+        self.ignore_outdated.unwrap_or(false)
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn versioned(&self) -> bool {
+        self.versioned.unwrap_or(false)
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn prefix(&self) -> Option<&Path> {
+        self.prefix.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn config_dir(&self) -> Option<&Path> {
+        self.config_dir.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn data_dir(&self) -> Option<&Path> {
+        self.data_dir.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn nomad_addr(&self) -> Option<&str> {
+        self.nomad_addr.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn cache_dir(&self) -> Option<&Path> {
+        self.cache_dir.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn no_cache(&self) -> bool {
+        self.no_cache.unwrap_or(false)
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn releases_url(&self) -> Option<&str> {
+        self.releases_url.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn checkpoint_url(&self) -> Option<&str> {
+        self.checkpoint_url.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn no_checkpoint(&self) -> bool {
+        self.no_checkpoint.unwrap_or(false)
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn retries(&self) -> Option<u32> {
+        self.retries
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn proxy(&self) -> Option<&str> {
+        self.proxy.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn no_proxy(&self) -> Option<&str> {
+        self.no_proxy.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn ca_cert(&self) -> Option<&Path> {
+        self.ca_cert.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn client_cert(&self) -> Option<&Path> {
+        self.client_cert.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn client_key(&self) -> Option<&Path> {
+        self.client_key.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn connect_timeout(&self) -> Option<u64> {
+        self.connect_timeout
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn read_timeout(&self) -> Option<u64> {
+        self.read_timeout
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn trusted_keys(&self) -> &[PathBuf] {
+        self.trusted_keys.as_deref().unwrap_or_default()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn require_fingerprint(&self) -> Option<&str> {
+        self.require_fingerprint.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn state_file(&self) -> Option<&Path> {
+        self.state_file.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn service_template(&self) -> Option<&Path> {
+        self.service_template.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn service_user(&self) -> Option<&str> {
+        self.service_user.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn service_group(&self) -> Option<&str> {
+        self.service_group.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn environment_file(&self) -> Option<&Path> {
+        self.environment_file.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn agent_args(&self) -> Option<&str> {
+        self.agent_args.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn limit_nofile(&self) -> Option<&str> {
+        self.limit_nofile.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn limit_nproc(&self) -> Option<&str> {
+        self.limit_nproc.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn role(&self) -> Option<&str> {
+        self.role.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn datacenter(&self) -> Option<&str> {
+        self.datacenter.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn region(&self) -> Option<&str> {
+        self.region.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn bind_addr(&self) -> Option<&str> {
+        self.bind_addr.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn bootstrap_expect(&self) -> Option<u32> {
+        self.bootstrap_expect
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn servers(&self) -> &[String] {
+        self.servers.as_deref().unwrap_or_default()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn retry_join(&self) -> &[String] {
+        self.retry_join.as_deref().unwrap_or_default()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn plugin_options(&self) -> &[String] {
+        self.plugin_options.as_deref().unwrap_or_default()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn tls_dir(&self) -> Option<&Path> {
+        self.tls_dir.as_deref()
+    }
+}
+
+/// Get the configuration files, from lowest to highest precedence.
+fn config_files() -> Vec<PathBuf> {
+    let mut files = vec![PathBuf::from(SYSTEM_CONFIG)];
+
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(value) if !value.is_empty() => Some(PathBuf::from(value)),
+        _ => env::var_os("HOME").map(|value| PathBuf::from(value).join(".config")),
+    };
+    if let Some(value) = config_home {
+        files.push(value.join("nomadutil").join("config.toml"));
+    }
+
+    files
+}
+
+/// Get a non-empty `NOMADUTIL_*` environment variable.
+fn env_var(name: &str) -> Option<String> {
+    env::var(format!("{}{}", ENV_PREFIX, name))
+        .ok()
+        .filter(|value| !value.is_empty())
+}
+
+/// Get a comma separated `NOMADUTIL_*` environment variable.
+fn env_list(name: &str) -> Option<Vec<String>> {
+    env_var(name).map(|value| {
+        value
+            .split(',')
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .collect()
+    })
+}
+
+/// Get a boolean `NOMADUTIL_*` environment variable.
+fn env_bool(name: &str) -> anyhow::Result<Option<bool>> {
+    match env_var(name).as_deref() {
+        None => Ok(None),
+        Some("1") | Some("true") | Some("yes") => Ok(Some(true)),
+        Some("0") | Some("false") | Some("no") => Ok(Some(false)),
+        Some(value) => anyhow::bail!("bad value for {}{}: {}", ENV_PREFIX, name, value),
+    }
+}
+
+/// Get a `NOMADUTIL_*` environment variable and parse it.
+fn env_parse<T: std::str::FromStr>(name: &str) -> anyhow::Result<Option<T>> {
+    match env_var(name) {
+        None => Ok(None),
+        Some(value) => match value.parse() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => anyhow::bail!("bad value for {}{}: {}", ENV_PREFIX, name, value),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::env_bool;
+    use super::Config;
+
+    use std::env;
+    use std::path::Path;
+
+    fn parse(contents: &str) -> Config {
+        toml::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn merge_prefers_other() {
+        let system = parse(
+            r#"
+            skip_sig = true
+            versioned = true
+            out = "/usr/local/bin"
+            trusted_keys = ["/etc/nomadutil/mirror.asc"]
+            "#,
+        );
+        let user = parse(
+            r#"
+            skip_sig = false
+            out = "/opt/bin"
+            "#,
+        );
+        let config = system.merge(user);
+
+        assert!(!config.skip_sig());
+        assert!(config.versioned());
+        assert_eq!(config.out(), Some(Path::new("/opt/bin")));
+        assert_eq!(
+            config.trusted_keys(),
+            &[Path::new("/etc/nomadutil/mirror.asc").to_path_buf()]
+        );
+    }
+
+    #[test]
+    fn merge_keeps_unset() {
+        let config = parse("retries = 2").merge(Config::default());
+        assert_eq!(config.retries(), Some(2));
+        assert!(!config.skip_sums());
+        assert_eq!(config.version(), None);
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!(toml::from_str::<Config>("skip_signature = true").is_err());
+    }
+
+    #[test]
+    fn env_bools() {
+        for (value, expected) in &[
+            ("1", true),
+            ("true", true),
+            ("yes", true),
+            ("0", false),
+            ("false", false),
+            ("no", false),
+        ] {
+            env::set_var("NOMADUTIL_TEST_ENV_BOOL", value);
+            assert_eq!(env_bool("TEST_ENV_BOOL").unwrap(), Some(*expected));
+        }
+
+        env::set_var("NOMADUTIL_TEST_ENV_BOOL", "");
+        assert_eq!(env_bool("TEST_ENV_BOOL").unwrap(), None);
+        env::set_var("NOMADUTIL_TEST_ENV_BOOL", "maybe");
+        assert!(env_bool("TEST_ENV_BOOL").is_err());
+        env::remove_var("NOMADUTIL_TEST_ENV_BOOL");
+        assert_eq!(env_bool("TEST_ENV_BOOL").unwrap(), None);
+    }
+}
@@ -0,0 +1,617 @@
+//! Module for checking the nomad agent configuration.
+//!
+//! The agent loads every `.hcl` and `.json` file in its `-config` directory and merges them, so
+//! the syntax is checked per file and the common mistakes on the merged configuration. Nomad
+//! refuses unknown top-level keys, a relative `data_dir` and a missing one, which only shows up
+//! as a crash-looping unit otherwise.
+
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::path::PathBuf;
+
+use hcl::edit::expr::Expression;
+use hcl::edit::parser::parse_body;
+use hcl::edit::structure::Body;
+use hcl::edit::structure::Structure;
+use hcl::edit::Span;
+
+/// Top-level attributes and blocks of the nomad agent configuration.
+const TOP_LEVEL_KEYS: &[&str] = &[
+    "acl",
+    "addresses",
+    "advertise",
+    "audit",
+    "autopilot",
+    "bind_addr",
+    "client",
+    "consul",
+    "data_dir",
+    "datacenter",
+    "disable_anonymous_signature",
+    "disable_update_check",
+    "enable_debug",
+    "enable_syslog",
+    "http_api_response_headers",
+    "keyring",
+    "leave_on_interrupt",
+    "leave_on_terminate",
+    "limits",
+    "log_file",
+    "log_include_location",
+    "log_json",
+    "log_level",
+    "log_rotate_bytes",
+    "log_rotate_duration",
+    "log_rotate_max_files",
+    "name",
+    "plugin",
+    "plugin_dir",
+    "ports",
+    "region",
+    "reporting",
+    "sentinel",
+    "server",
+    "syslog_facility",
+    "telemetry",
+    "tls",
+    "ui",
+    "vault",
+];
+
+/// How bad a finding is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    /// the agent will not start
+    Error,
+    /// the agent starts, but probably not as intended
+    Warning,
+}
+
+/// A problem found in the configuration.
+#[derive(Clone, Debug)]
+pub struct Finding {
+    severity: Severity,
+    file: PathBuf,
+    /// line and column, if known
+    position: Option<(usize, usize)>,
+    message: String,
+}
+
+impl Finding {
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some((line, column)) = self.position {
+            write!(f, ":{}:{}", line, column)?;
+        }
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, ": {}: {}", severity, self.message)
+    }
+}
+
+/// Where something was found.
+#[derive(Clone, Debug)]
+struct Location {
+    file: PathBuf,
+    position: Option<(usize, usize)>,
+}
+
+impl Location {
+    /// Make a finding at this location.
+    fn finding(&self, severity: Severity, message: String) -> Finding {
+        Finding {
+            severity,
+            file: self.file.clone(),
+            position: self.position,
+            message,
+        }
+    }
+}
+
+/// What the merged configuration sets up.
+#[derive(Default)]
+struct Agent {
+    /// the data_dir, None if it is not a plain string
+    data_dir: Option<(Location, Option<String>)>,
+    /// the first server block that enables the server
+    server: Option<Location>,
+    bootstrap_expect: bool,
+    /// the first client block that enables the client
+    client: Option<Location>,
+    client_servers: bool,
+}
+
+/// Check the agent configuration at `path`, a directory or a single file.
+///
+/// Fails only if the configuration cannot be read; problems in it are returned as findings.
+pub fn check_config(path: &Path) -> anyhow::Result<Vec<Finding>> {
+    let files = config_files(path)?;
+    let mut findings = Vec::new();
+    if files.is_empty() {
+        findings.push(Finding {
+            severity: Severity::Error,
+            file: path.to_path_buf(),
+            position: None,
+            message: "no .hcl or .json configuration files".to_string(),
+        });
+        return Ok(findings);
+    }
+
+    let mut agent = Agent::default();
+    for file in files {
+        let input = fs::read_to_string(&file)
+            .map_err(|e| anyhow::anyhow!("failed to read {}: {}", file.display(), e))?;
+        if file.extension().is_some_and(|value| value == "json") {
+            check_json(&file, input.as_str(), &mut agent, &mut findings);
+        } else {
+            check_hcl(&file, input.as_str(), &mut agent, &mut findings);
+        }
+    }
+
+    match &agent.data_dir {
+        Some((location, Some(value))) if !Path::new(value).is_absolute() => {
+            findings.push(location.finding(
+                Severity::Error,
+                format!("data_dir {:?} is not an absolute path", value),
+            ))
+        }
+        None if agent.server.is_some() || agent.client.is_some() => findings.push(Finding {
+            severity: Severity::Error,
+            file: path.to_path_buf(),
+            position: None,
+            message: "no data_dir set".to_string(),
+        }),
+        _ => {}
+    }
+    if let Some(location) = &agent.server {
+        if !agent.bootstrap_expect {
+            findings.push(location.finding(
+                Severity::Warning,
+                "server without bootstrap_expect, it will not bootstrap a cluster".to_string(),
+            ));
+        }
+    }
+    if let Some(location) = &agent.client {
+        if !agent.client_servers {
+            findings.push(location.finding(
+                Severity::Warning,
+                "client without servers or server_join, it can only find the servers through consul"
+                    .to_string(),
+            ));
+        }
+    }
+
+    Ok(findings)
+}
+
+/// Get the configuration files the agent loads from `path`, in the order it loads them.
+fn config_files(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let entries = fs::read_dir(path)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .map(|value| value.to_string_lossy().to_string())
+            .unwrap_or_default();
+        // Nomad skips editor backups and temporary files.
+        if name.ends_with('~') || name.starts_with(".#") || name.starts_with('#') {
+            continue;
+        }
+        let extension = path.extension().and_then(|value| value.to_str());
+        if path.is_file() && (extension == Some("hcl") || extension == Some("json")) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Check an HCL configuration file.
+fn check_hcl(file: &Path, input: &str, agent: &mut Agent, findings: &mut Vec<Finding>) {
+    let body = match parse_body(input) {
+        Ok(value) => value,
+        Err(e) => {
+            findings.push(Finding {
+                severity: Severity::Error,
+                file: file.to_path_buf(),
+                position: Some((e.location().line(), e.location().column())),
+                message: e.message().to_string(),
+            });
+            return;
+        }
+    };
+    let location = |span: Option<Range<usize>>| Location {
+        file: file.to_path_buf(),
+        position: span.map(|value| position(input, value.start)),
+    };
+
+    for structure in body.iter() {
+        let (key, span) = match structure {
+            Structure::Attribute(attr) => (attr.key.as_str(), attr.key.span()),
+            Structure::Block(block) => (block.ident.as_str(), block.ident.span()),
+        };
+        if !TOP_LEVEL_KEYS.contains(&key) {
+            let kind = if structure.is_block() {
+                "block"
+            } else {
+                "attribute"
+            };
+            findings.push(location(span).finding(
+                Severity::Error,
+                format!("unknown top-level {} {}", kind, key),
+            ));
+            continue;
+        }
+
+        match structure {
+            Structure::Attribute(attr) if key == "data_dir" => {
+                let value = match &attr.value {
+                    Expression::String(value) => Some(value.value().to_string()),
+                    _ => None,
+                };
+                agent.data_dir = Some((location(span), value));
+            }
+            Structure::Block(block) if key == "server" => {
+                if hcl_enabled(&block.body) && agent.server.is_none() {
+                    agent.server = Some(location(span));
+                }
+                agent.bootstrap_expect |= block.body.has_attribute("bootstrap_expect");
+            }
+            Structure::Block(block) if key == "client" => {
+                if hcl_enabled(&block.body) && agent.client.is_none() {
+                    agent.client = Some(location(span));
+                }
+                agent.client_servers |=
+                    block.body.has_attribute("servers") || block.body.has_blocks("server_join");
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Check whether a block body has `enabled = true`.
+fn hcl_enabled(body: &Body) -> bool {
+    matches!(
+        body.get_attribute("enabled").map(|attr| &attr.value),
+        Some(Expression::Bool(value)) if *value.value()
+    )
+}
+
+/// Check a JSON configuration file.
+///
+/// Positions are only known for syntax errors.
+fn check_json(file: &Path, input: &str, agent: &mut Agent, findings: &mut Vec<Finding>) {
+    let location = Location {
+        file: file.to_path_buf(),
+        position: None,
+    };
+    let value: serde_json::Value = match serde_json::from_str(input) {
+        Ok(value) => value,
+        Err(e) => {
+            findings.push(Finding {
+                severity: Severity::Error,
+                file: file.to_path_buf(),
+                position: Some((e.line(), e.column())),
+                message: e
+                    .to_string()
+                    .trim_end_matches(&format!(" at line {} column {}", e.line(), e.column()))
+                    .to_string(),
+            });
+            return;
+        }
+    };
+    let object = match value.as_object() {
+        Some(value) => value,
+        None => {
+            findings.push(location.finding(
+                Severity::Error,
+                "the configuration is not a json object".to_string(),
+            ));
+            return;
+        }
+    };
+
+    for (key, value) in object {
+        if !TOP_LEVEL_KEYS.contains(&key.as_str()) {
+            findings
+                .push(location.finding(Severity::Error, format!("unknown top-level key {}", key)));
+            continue;
+        }
+
+        // Blocks are objects, or arrays of objects when repeated.
+        let blocks: Vec<&serde_json::Value> = match value {
+            serde_json::Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        let enabled = |block: &&serde_json::Value| {
+            block.get("enabled") == Some(&serde_json::Value::Bool(true))
+        };
+        match key.as_str() {
+            "data_dir" => {
+                agent.data_dir = Some((
+                    location.clone(),
+                    value.as_str().map(|value| value.to_string()),
+                ))
+            }
+            "server" => {
+                if blocks.iter().any(enabled) && agent.server.is_none() {
+                    agent.server = Some(location.clone());
+                }
+                agent.bootstrap_expect |= blocks
+                    .iter()
+                    .any(|block| block.get("bootstrap_expect").is_some());
+            }
+            "client" => {
+                if blocks.iter().any(enabled) && agent.client.is_none() {
+                    agent.client = Some(location.clone());
+                }
+                agent.client_servers |= blocks.iter().any(|block| {
+                    block.get("servers").is_some() || block.get("server_join").is_some()
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Get the line and column, both starting at 1, of a byte offset in `input`.
+fn position(input: &str, offset: usize) -> (usize, usize) {
+    let before = &input[..offset.min(input.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before[before.rfind('\n').map_or(0, |value| value + 1)..]
+        .chars()
+        .count()
+        + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_config;
+
+    use crate::common::scratch_dir;
+
+    use std::fs;
+    use std::path::Path;
+
+    /// Check the configuration `files` written to a fresh directory named after `name`.
+    ///
+    /// Returns the findings with the directory stripped from their paths.
+    fn check(name: &str, files: &[(&str, &str)]) -> Vec<String> {
+        let dir = scratch_dir(name);
+        for (file, contents) in files {
+            fs::write(dir.join(file), contents).unwrap();
+        }
+        let prefix = format!("{}/", dir.display());
+        let findings = check_config(&dir)
+            .unwrap()
+            .iter()
+            .map(|finding| finding.to_string().replacen(prefix.as_str(), "", 1))
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        findings
+    }
+
+    /// Check whether any of the findings of `files` is an error.
+    fn has_errors(name: &str, files: &[(&str, &str)]) -> bool {
+        let dir = scratch_dir(name);
+        for (file, contents) in files {
+            fs::write(dir.join(file), contents).unwrap();
+        }
+        let errors = check_config(&dir)
+            .unwrap()
+            .iter()
+            .any(|finding| finding.is_error());
+        fs::remove_dir_all(&dir).unwrap();
+        errors
+    }
+
+    const SERVER_HCL: &str = r#"data_dir = "/opt/nomad/data"
+
+server {
+  enabled          = true
+  bootstrap_expect = 3
+}
+"#;
+
+    const SERVER_JSON: &str = r#"{
+  "data_dir": "/opt/nomad/data",
+  "server": {
+    "enabled": true,
+    "bootstrap_expect": 3
+  }
+}
+"#;
+
+    #[test]
+    fn valid_configuration() {
+        assert!(check("check-valid-hcl", &[("nomad.hcl", SERVER_HCL)]).is_empty());
+        assert!(check("check-valid-json", &[("nomad.json", SERVER_JSON)]).is_empty());
+    }
+
+    #[test]
+    fn no_configuration_files() {
+        let findings = check("check-no-files", &[("nomad.conf", SERVER_HCL)]);
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].ends_with(": error: no .hcl or .json configuration files"));
+    }
+
+    #[test]
+    fn hcl_syntax_error() {
+        let findings = check(
+            "check-syntax-hcl",
+            &[(
+                "nomad.hcl",
+                "data_dir = \"/opt/nomad\"\n\nserver {\n  enabled = \n}\n",
+            )],
+        );
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].starts_with("nomad.hcl:4:13: error: invalid expression"));
+    }
+
+    #[test]
+    fn json_syntax_error() {
+        let findings = check(
+            "check-syntax-json",
+            &[("nomad.json", "{\n  \"data_dir\": \"/opt/nomad\",\n}\n")],
+        );
+        assert_eq!(findings, ["nomad.json:3:1: error: trailing comma"]);
+    }
+
+    #[test]
+    fn unknown_top_level_keys() {
+        let findings = check(
+            "check-unknown-hcl",
+            &[(
+                "nomad.hcl",
+                "data_dir = \"/opt/nomad\"\nsever {\n  enabled = true\n}\ndatacentre = \"dc1\"\n",
+            )],
+        );
+        assert_eq!(
+            findings,
+            [
+                "nomad.hcl:2:1: error: unknown top-level block sever",
+                "nomad.hcl:5:1: error: unknown top-level attribute datacentre",
+            ]
+        );
+
+        let findings = check(
+            "check-unknown-json",
+            &[(
+                "nomad.json",
+                r#"{"data_dir": "/opt/nomad", "sever": {"enabled": true}}"#,
+            )],
+        );
+        assert_eq!(findings, ["nomad.json: error: unknown top-level key sever"]);
+    }
+
+    #[test]
+    fn server_without_bootstrap_expect() {
+        let hcl = "data_dir = \"/opt/nomad\"\n\nserver {\n  enabled = true\n}\n";
+        let findings = check("check-server-hcl", &[("nomad.hcl", hcl)]);
+        assert_eq!(
+            findings,
+            ["nomad.hcl:3:1: warning: server without bootstrap_expect, it will not bootstrap a cluster"]
+        );
+
+        let json = r#"{"data_dir": "/opt/nomad", "server": {"enabled": true}}"#;
+        let findings = check("check-server-json", &[("nomad.json", json)]);
+        assert_eq!(
+            findings,
+            ["nomad.json: warning: server without bootstrap_expect, it will not bootstrap a cluster"]
+        );
+
+        // The attribute may be set in another file.
+        let findings = check(
+            "check-server-merged",
+            &[
+                ("a.hcl", hcl),
+                ("b.hcl", "server {\n  bootstrap_expect = 1\n}\n"),
+            ],
+        );
+        assert!(findings.is_empty());
+    }
+
+    #[test]
+    fn client_without_servers() {
+        let hcl = "data_dir = \"/opt/nomad\"\n\nclient {\n  enabled = true\n}\n";
+        let message = "warning: client without servers or server_join, it can only find the servers through consul";
+        let findings = check("check-client-hcl", &[("nomad.hcl", hcl)]);
+        assert_eq!(findings, [format!("nomad.hcl:3:1: {}", message)]);
+
+        let json = r#"{"data_dir": "/opt/nomad", "client": {"enabled": true}}"#;
+        let findings = check("check-client-json", &[("nomad.json", json)]);
+        assert_eq!(findings, [format!("nomad.json: {}", message)]);
+
+        let hcl = "data_dir = \"/opt/nomad\"\n\nclient {\n  enabled = true\n  server_join {\n    retry_join = [\"10.0.0.1\"]\n  }\n}\n";
+        assert!(check("check-client-join", &[("nomad.hcl", hcl)]).is_empty());
+        let json =
+            r#"{"data_dir": "/opt/nomad", "client": {"enabled": true, "servers": ["10.0.0.1"]}}"#;
+        assert!(check("check-client-servers", &[("nomad.json", json)]).is_empty());
+    }
+
+    #[test]
+    fn data_dir() {
+        let hcl = SERVER_HCL.replace("/opt/nomad/data", "nomad/data");
+        let findings = check("check-data-dir-hcl", &[("nomad.hcl", hcl.as_str())]);
+        assert_eq!(
+            findings,
+            ["nomad.hcl:1:1: error: data_dir \"nomad/data\" is not an absolute path"]
+        );
+
+        let json = SERVER_JSON.replace("/opt/nomad/data", "nomad/data");
+        let findings = check("check-data-dir-json", &[("nomad.json", json.as_str())]);
+        assert_eq!(
+            findings,
+            ["nomad.json: error: data_dir \"nomad/data\" is not an absolute path"]
+        );
+
+        let findings = check(
+            "check-no-data-dir",
+            &[(
+                "nomad.hcl",
+                SERVER_HCL
+                    .lines()
+                    .skip(1)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+                    .as_str(),
+            )],
+        );
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].ends_with(": error: no data_dir set"));
+    }
+
+    #[test]
+    fn errors_and_warnings() {
+        let warning = "data_dir = \"/opt/nomad\"\nserver {\n  enabled = true\n}\n";
+        assert!(!has_errors("check-warning", &[("nomad.hcl", warning)]));
+        assert!(has_errors(
+            "check-error-hcl",
+            &[("nomad.hcl", "sever {}\n")]
+        ));
+        assert!(has_errors("check-error-json", &[("nomad.json", "[]")]));
+    }
+
+    #[test]
+    fn skips_backups() {
+        let findings = check(
+            "check-backups",
+            &[
+                ("nomad.hcl", SERVER_HCL),
+                ("nomad.hcl~", "sever {}\n"),
+                ("#nomad.hcl#", "sever {}\n"),
+            ],
+        );
+        assert!(findings.is_empty());
+    }
+
+    #[test]
+    fn single_file() {
+        let dir = scratch_dir("check-single-file");
+        let file = dir.join("nomad.hcl");
+        fs::write(&file, "sever {}\n").unwrap();
+        fs::write(dir.join("other.hcl"), "datacentre = 1\n").unwrap();
+        let findings = check_config(Path::new(&file)).unwrap();
+        assert_eq!(findings.len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,348 @@
+//! Module for version constraints.
+//!
+//! Supported forms:
+//!
+//! - exact versions: `1.6.1`
+//! - comparisons, separated by commas: `>= 1.5, < 1.7`, `!= 1.6.2`
+//! - pessimistic constraints: `~> 1.6` (>= 1.6, < 2.0), `~> 1.6.1` (>= 1.6.1, < 1.7.0)
+//! - wildcards: `1.6.x`, `1.6.*`, `1.x`
+//! - `latest-patch-of 1.5` (>= 1.5.0, < 1.6.0)
+//!
+//! Prereleases only match constraints that name a prerelease themselves, so `~> 1.6` does not
+//! pick up `1.7.0-beta1`.
+
+use std::fmt;
+
+use semver::Version;
+
+/// A comparison operator.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+/// A single comparison against a version.
+#[derive(Clone, Debug)]
+struct Comparison {
+    op: Op,
+    version: Version,
+}
+
+impl Comparison {
+    fn matches(&self, version: &Version) -> bool {
+        match self.op {
+            Op::Eq => version == &self.version,
+            Op::Ne => version != &self.version,
+            Op::Gt => version > &self.version,
+            Op::Ge => version >= &self.version,
+            Op::Lt => version < &self.version,
+            Op::Le => version <= &self.version,
+        }
+    }
+}
+
+/// A version constraint.
+#[derive(Clone, Debug)]
+pub struct VersionConstraint {
+    raw: String,
+    comparisons: Vec<Comparison>,
+}
+
+impl VersionConstraint {
+    /// Parse a version constraint.
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        let raw = raw.trim();
+        let mut comparisons = Vec::new();
+
+        if let Some(value) = raw.strip_prefix("latest-patch-of") {
+            let (major, minor) = parse_partial(value.trim(), raw)?;
+            let minor = minor.ok_or_else(|| {
+                anyhow::anyhow!("bad version constraint {}: expected a minor version", raw)
+            })?;
+            comparisons.push(Comparison {
+                op: Op::Ge,
+                version: Version::new(major, minor, 0),
+            });
+            comparisons.push(Comparison {
+                op: Op::Lt,
+                version: Version::new(major, minor + 1, 0),
+            });
+        } else {
+            for part in raw.split(',') {
+                comparisons.extend(parse_part(part.trim(), raw)?);
+            }
+        }
+
+        if comparisons.is_empty() {
+            anyhow::bail!("empty version constraint");
+        }
+
+        Ok(Self {
+            raw: raw.to_string(),
+            comparisons,
+        })
+    }
+
+    /// Get the version this constraint pins, if it is a single exact version.
+    pub fn exact(&self) -> Option<&Version> {
+        match self.comparisons.as_slice() {
+            [Comparison {
+                op: Op::Eq,
+                version,
+            }] => Some(version),
+            _ => None,
+        }
+    }
+
+    /// Check whether a version satisfies this constraint.
+    pub fn matches(&self, version: &Version) -> bool {
+        if !version.pre.is_empty()
+            && self
+                .comparisons
+                .iter()
+                .all(|comparison| comparison.version.pre.is_empty())
+        {
+            return false;
+        }
+        self.comparisons
+            .iter()
+            .all(|comparison| comparison.matches(version))
+    }
+}
+
+impl fmt::Display for VersionConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.raw)
+    }
+}
+
+/// Parse one comma-separated part of a constraint.
+fn parse_part(part: &str, raw: &str) -> anyhow::Result<Vec<Comparison>> {
+    let ops: [(&str, Option<Op>); 8] = [
+        ("~>", None),
+        (">=", Some(Op::Ge)),
+        ("<=", Some(Op::Le)),
+        ("!=", Some(Op::Ne)),
+        (">", Some(Op::Gt)),
+        ("<", Some(Op::Lt)),
+        ("=", Some(Op::Eq)),
+        ("", Some(Op::Eq)),
+    ];
+    let (prefix, op) = ops
+        .iter()
+        .find(|(prefix, _)| part.starts_with(prefix))
+        .expect("the empty prefix always matches");
+    let value = part[prefix.len()..].trim().trim_start_matches('v');
+    if value.is_empty() {
+        anyhow::bail!("bad version constraint {}: missing version", raw);
+    }
+
+    // Full versions are compared as they are, including prereleases.
+    if let Ok(version) = Version::parse(value) {
+        return Ok(match op {
+            Some(op) => vec![Comparison { op: *op, version }],
+            // ~> 1.6.1 allows patch releases of 1.6.
+            None => vec![
+                Comparison {
+                    op: Op::Ge,
+                    version: version.clone(),
+                },
+                Comparison {
+                    op: Op::Lt,
+                    version: Version::new(version.major, version.minor + 1, 0),
+                },
+            ],
+        });
+    }
+
+    let (major, minor) = parse_partial(value, raw)?;
+    let lower = Version::new(major, minor.unwrap_or(0), 0);
+    let upper = match minor {
+        Some(minor) => Version::new(major, minor + 1, 0),
+        None => Version::new(major + 1, 0, 0),
+    };
+    let range = |lower: Version, upper: Version| {
+        vec![
+            Comparison {
+                op: Op::Ge,
+                version: lower,
+            },
+            Comparison {
+                op: Op::Lt,
+                version: upper,
+            },
+        ]
+    };
+
+    Ok(match op {
+        // ~> 1.6 allows minor releases of 1.
+        None => range(lower, Version::new(major + 1, 0, 0)),
+        // 1.6, 1.6.x and = 1.6.x match the whole 1.6 line.
+        Some(Op::Eq) => range(lower, upper),
+        Some(Op::Ge) => vec![Comparison {
+            op: Op::Ge,
+            version: lower,
+        }],
+        Some(Op::Lt) => vec![Comparison {
+            op: Op::Lt,
+            version: lower,
+        }],
+        Some(Op::Gt) => vec![Comparison {
+            op: Op::Ge,
+            version: upper,
+        }],
+        Some(Op::Le) => vec![Comparison {
+            op: Op::Lt,
+            version: upper,
+        }],
+        Some(Op::Ne) => anyhow::bail!("bad version constraint {}: != requires a full version", raw),
+    })
+}
+
+/// Parse a partial version such as `1`, `1.6`, `1.6.x` or `1.*`.
+///
+/// Returns the major and minor components.
+fn parse_partial(value: &str, raw: &str) -> anyhow::Result<(u64, Option<u64>)> {
+    let bad = || anyhow::anyhow!("bad version constraint {}: bad version {}", raw, value);
+
+    let mut parts = value.split('.');
+    let mut numbers: Vec<u64> = Vec::with_capacity(3);
+    for part in &mut parts {
+        if part == "x" || part == "X" || part == "*" {
+            break;
+        }
+        numbers.push(part.parse().map_err(|_| bad())?);
+    }
+    // Nothing may follow a wildcard except more wildcards.
+    if parts.any(|part| part != "x" && part != "X" && part != "*") {
+        return Err(bad());
+    }
+
+    match numbers.as_slice() {
+        [major] => Ok((*major, None)),
+        [major, minor] => Ok((*major, Some(*minor))),
+        _ => Err(bad()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VersionConstraint;
+
+    use semver::Version;
+
+    /// Check which of `versions` match `constraint`.
+    fn matching(constraint: &str, versions: &[&str]) -> Vec<String> {
+        let constraint = VersionConstraint::parse(constraint).unwrap();
+        versions
+            .iter()
+            .filter(|version| constraint.matches(&Version::parse(version).unwrap()))
+            .map(|version| version.to_string())
+            .collect()
+    }
+
+    const VERSIONS: &[&str] = &[
+        "1.4.3", "1.5.0", "1.5.9", "1.6.0", "1.6.1", "1.6.2", "1.7.0", "2.0.0",
+    ];
+
+    #[test]
+    fn pessimistic_minor() {
+        assert_eq!(
+            matching("~> 1.6", VERSIONS),
+            vec!["1.6.0", "1.6.1", "1.6.2", "1.7.0"]
+        );
+    }
+
+    #[test]
+    fn pessimistic_patch() {
+        assert_eq!(matching("~> 1.6.1", VERSIONS), vec!["1.6.1", "1.6.2"]);
+    }
+
+    #[test]
+    fn wildcard() {
+        assert_eq!(matching("1.6.x", VERSIONS), vec!["1.6.0", "1.6.1", "1.6.2"]);
+        assert_eq!(matching("1.6.*", VERSIONS), matching("1.6.x", VERSIONS));
+        assert_eq!(
+            matching("1.x", VERSIONS),
+            vec!["1.4.3", "1.5.0", "1.5.9", "1.6.0", "1.6.1", "1.6.2", "1.7.0"]
+        );
+    }
+
+    #[test]
+    fn bare_minor() {
+        assert_eq!(matching("1.6", VERSIONS), vec!["1.6.0", "1.6.1", "1.6.2"]);
+        assert!(VersionConstraint::parse("1.6").unwrap().exact().is_none());
+    }
+
+    #[test]
+    fn exact() {
+        let constraint = VersionConstraint::parse("v1.6.1").unwrap();
+        assert_eq!(constraint.exact(), Some(&Version::new(1, 6, 1)));
+        assert_eq!(matching("1.6.1", VERSIONS), vec!["1.6.1"]);
+    }
+
+    #[test]
+    fn latest_patch_of() {
+        assert_eq!(
+            matching("latest-patch-of 1.5", VERSIONS),
+            vec!["1.5.0", "1.5.9"]
+        );
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(
+            matching(">= 1.5, < 1.6.2", VERSIONS),
+            vec!["1.5.0", "1.5.9", "1.6.0", "1.6.1"]
+        );
+        assert_eq!(
+            matching(">= 1.5, < 1.7, != 1.6.1", VERSIONS),
+            vec!["1.5.0", "1.5.9", "1.6.0", "1.6.2"]
+        );
+        assert_eq!(matching("> 1.6", VERSIONS), vec!["1.7.0", "2.0.0"]);
+        assert_eq!(
+            matching("<= 1.5", VERSIONS),
+            vec!["1.4.3", "1.5.0", "1.5.9"]
+        );
+    }
+
+    #[test]
+    fn prereleases_are_excluded() {
+        let versions = &["1.6.2", "1.7.0-beta1", "1.7.0-rc1", "1.7.0"];
+        assert_eq!(matching("~> 1.6", versions), vec!["1.6.2", "1.7.0"]);
+        assert_eq!(matching(">= 1.6, < 1.7.0", versions), vec!["1.6.2"]);
+        assert_eq!(
+            matching(">= 1.7.0-beta1", versions),
+            vec!["1.7.0-beta1", "1.7.0-rc1", "1.7.0"]
+        );
+    }
+
+    #[test]
+    fn rejected() {
+        for raw in &[
+            "",
+            " ",
+            ",",
+            ">=",
+            "~>",
+            "latest-patch-of",
+            "latest-patch-of 1",
+            "1.x.2",
+            "one.two",
+            "1.6.2.1",
+            "!= 1.6",
+            ">= 1.5,",
+        ] {
+            assert!(
+                VersionConstraint::parse(raw).is_err(),
+                "{:?} should be rejected",
+                raw
+            );
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Module for unpacking the nomad binary from a release zip archive.
+//!
+//! The archive is unpacked defensively, since it is not checked at all when integrity checks are
+//! skipped: it must hold a single regular file named `nomad`, the declared and the actual
+//! uncompressed size are bounded, the compression ratio must be plausible and the result must be
+//! an ELF executable for this platform.
+
+use crate::common::TempFile;
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use zip::ZipArchive;
+
+/// Maximum uncompressed size of the nomad binary.
+const MAX_SIZE: u64 = 512 * 1024 * 1024;
+/// Maximum ratio between the uncompressed and the compressed size of the nomad binary.
+const MAX_RATIO: u64 = 20;
+/// Mask of the file type bits in a unix mode.
+const S_IFMT: u32 = 0o170000;
+/// File type bits of a symlink.
+const S_IFLNK: u32 = 0o120000;
+
+/// ELF machine of this platform.
+#[cfg(target_arch = "x86_64")]
+const ELF_MACHINE: u16 = 62;
+
+/// ELF machine of this platform.
+#[cfg(target_arch = "aarch64")]
+const ELF_MACHINE: u16 = 183;
+
+/// Why the nomad binary could not be unpacked.
+#[derive(Debug)]
+pub enum ExtractError {
+    /// the archive has no entries
+    Empty,
+    /// the archive has other entries than the nomad binary
+    UnexpectedEntries(Vec<String>),
+    /// an entry name has path components
+    PathComponents(String),
+    /// an entry is a symlink
+    Symlink(String),
+    /// an entry is not a regular file
+    NotAFile(String),
+    /// the declared or actual uncompressed size is over the limit
+    TooLarge(u64),
+    /// the compression ratio is implausible
+    Ratio { size: u64, compressed_size: u64 },
+    /// the uncompressed size does not match the declared one
+    SizeMismatch { declared: u64, actual: u64 },
+    /// the binary is not an ELF executable
+    NotElf(String),
+    /// the binary is an ELF executable for another platform
+    WrongArch(u16),
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty zip archive"),
+            Self::UnexpectedEntries(names) => {
+                write!(f, "zip archive has unexpected entries: {:?}", names)
+            }
+            Self::PathComponents(name) => {
+                write!(f, "zip archive entry {:?} has path components", name)
+            }
+            Self::Symlink(name) => write!(f, "zip archive entry {:?} is a symlink", name),
+            Self::NotAFile(name) => write!(f, "zip archive entry {:?} is not a file", name),
+            Self::TooLarge(size) => write!(
+                f,
+                "nomad binary is larger than {} bytes: {} bytes",
+                MAX_SIZE, size
+            ),
+            Self::Ratio {
+                size,
+                compressed_size,
+            } => write!(
+                f,
+                "nomad binary compresses implausibly well, {} bytes from {} bytes",
+                size, compressed_size
+            ),
+            Self::SizeMismatch { declared, actual } => write!(
+                f,
+                "nomad binary is {} bytes, but the zip archive declares {} bytes",
+                actual, declared
+            ),
+            Self::NotElf(reason) => write!(f, "nomad binary is not an ELF executable: {}", reason),
+            Self::WrongArch(machine) => write!(
+                f,
+                "nomad binary is for ELF machine {}, not for {} ({})",
+                machine,
+                crate::ARCH,
+                ELF_MACHINE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExtractError {}
+
+/// Unpack the nomad binary from the zip archive at `zip` into a temporary file in `dir`.
+pub fn extract_nomad(zip: &Path, dir: &Path) -> anyhow::Result<TempFile> {
+    let mut zip = ZipArchive::new(File::open(zip)?)?;
+
+    if zip.is_empty() {
+        return Err(ExtractError::Empty.into());
+    }
+    if let Some(name) = zip
+        .file_names()
+        .find(|name| name.contains('/') || name.contains('\\'))
+    {
+        return Err(ExtractError::PathComponents(name.to_string()).into());
+    }
+    if zip.len() != 1 || zip.file_names().any(|name| name != "nomad") {
+        return Err(
+            ExtractError::UnexpectedEntries(zip.file_names().map(String::from).collect()).into(),
+        );
+    }
+
+    let zip_file = zip.by_index(0)?;
+    let name = zip_file.name().to_string();
+    if let Some(mode) = zip_file.unix_mode() {
+        if mode & S_IFMT == S_IFLNK {
+            return Err(ExtractError::Symlink(name).into());
+        }
+    }
+    if zip_file.is_dir() {
+        return Err(ExtractError::NotAFile(name).into());
+    }
+
+    let declared = zip_file.size();
+    let compressed_size = zip_file.compressed_size();
+    if declared > MAX_SIZE {
+        return Err(ExtractError::TooLarge(declared).into());
+    }
+    if declared > compressed_size.saturating_mul(MAX_RATIO) {
+        return Err(ExtractError::Ratio {
+            size: declared,
+            compressed_size,
+        }
+        .into());
+    }
+
+    // The declared size cannot be trusted, so never read more than the limit.
+    let bin = TempFile::new(dir, "nomad");
+    let mut file = File::create(bin.path())?;
+    let actual = io::copy(&mut zip_file.take(MAX_SIZE + 1), &mut file)?;
+    if actual > MAX_SIZE {
+        return Err(ExtractError::TooLarge(actual).into());
+    }
+    if actual != declared {
+        return Err(ExtractError::SizeMismatch { declared, actual }.into());
+    }
+    file.sync_all()?;
+
+    check_elf(bin.path())?;
+
+    Ok(bin)
+}
+
+/// Check that the file at `path` is an ELF executable for this platform.
+fn check_elf(path: &Path) -> anyhow::Result<()> {
+    let mut header = [0; 20];
+    File::open(path)?
+        .read_exact(&mut header)
+        .map_err(|_| ExtractError::NotElf("too short".to_string()))?;
+
+    if header[..4] != *b"\x7fELF" {
+        return Err(ExtractError::NotElf("bad magic".to_string()).into());
+    }
+    if header[4] != 2 {
+        return Err(ExtractError::NotElf("not 64-bit".to_string()).into());
+    }
+    if header[5] != 1 {
+        return Err(ExtractError::NotElf("not little-endian".to_string()).into());
+    }
+    let kind = u16::from_le_bytes([header[16], header[17]]);
+    if kind != 2 && kind != 3 {
+        return Err(ExtractError::NotElf(format!("not an executable, type {}", kind)).into());
+    }
+    let machine = u16::from_le_bytes([header[18], header[19]]);
+    if machine != ELF_MACHINE {
+        return Err(ExtractError::WrongArch(machine).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::extract_nomad;
+    use super::ExtractError;
+    use super::ELF_MACHINE;
+
+    use crate::common::scratch_dir;
+
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    use zip::write::FileOptions;
+    use zip::CompressionMethod;
+    use zip::ZipWriter;
+
+    /// Offset of the uncompressed size in a central directory header.
+    const CENTRAL_SIZE: usize = 24;
+    /// Offset of the external attributes in a central directory header.
+    const CENTRAL_ATTRS: usize = 38;
+
+    /// The start of a 64-bit little-endian ELF executable for `machine`.
+    fn elf(machine: u16) -> Vec<u8> {
+        let mut header = b"\x7fELF\x02\x01\x01".to_vec();
+        header.resize(16, 0);
+        header.extend(&2u16.to_le_bytes());
+        header.extend(&machine.to_le_bytes());
+        header.resize(64, 0);
+        header
+    }
+
+    /// Write a zip archive with the stored `entries` to `dir`.
+    fn write_zip(dir: &Path, entries: &[(&str, &[u8])]) -> PathBuf {
+        let path = dir.join("nomad.zip");
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        for (name, data) in entries {
+            let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+        path
+    }
+
+    /// Overwrite a field of the first central directory header of the zip archive at `path`.
+    fn patch_central(path: &Path, offset: usize, value: u32) {
+        let mut data = fs::read(path).unwrap();
+        let start = data
+            .windows(4)
+            .position(|window| window == b"PK\x01\x02")
+            .unwrap();
+        data[start + offset..start + offset + 4].copy_from_slice(&value.to_le_bytes());
+        fs::write(path, data).unwrap();
+    }
+
+    fn extract_err(zip: &Path, dir: &Path) -> ExtractError {
+        extract_nomad(zip, dir)
+            .err()
+            .unwrap()
+            .downcast::<ExtractError>()
+            .unwrap()
+    }
+
+    #[test]
+    fn extracts_nomad() {
+        let dir = scratch_dir("extract-ok");
+        let zip = write_zip(&dir, &[("nomad", &elf(ELF_MACHINE))]);
+        let bin = extract_nomad(&zip, &dir).unwrap();
+        assert_eq!(fs::read(bin.path()).unwrap(), elf(ELF_MACHINE));
+    }
+
+    #[test]
+    fn empty_archive() {
+        let dir = scratch_dir("extract-empty");
+        let zip = write_zip(&dir, &[]);
+        assert!(matches!(extract_err(&zip, &dir), ExtractError::Empty));
+    }
+
+    #[test]
+    fn unexpected_entries() {
+        let dir = scratch_dir("extract-entries");
+        let zip = write_zip(&dir, &[("nomad", &elf(ELF_MACHINE)), ("README", b"")]);
+        match extract_err(&zip, &dir) {
+            ExtractError::UnexpectedEntries(mut names) => {
+                names.sort();
+                assert_eq!(names, ["README", "nomad"]);
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+
+        let zip = write_zip(&dir, &[("consul", &elf(ELF_MACHINE))]);
+        assert!(matches!(
+            extract_err(&zip, &dir),
+            ExtractError::UnexpectedEntries(_)
+        ));
+    }
+
+    #[test]
+    fn path_components() {
+        let dir = scratch_dir("extract-path");
+        for name in &["bin/nomad", "../nomad", "..\\nomad"] {
+            let zip = write_zip(&dir, &[(name, &elf(ELF_MACHINE))]);
+            assert!(matches!(
+                extract_err(&zip, &dir),
+                ExtractError::PathComponents(value) if value == *name
+            ));
+        }
+    }
+
+    #[test]
+    fn symlink() {
+        let dir = scratch_dir("extract-symlink");
+        let zip = write_zip(&dir, &[("nomad", b"/usr/bin/nomad")]);
+        patch_central(&zip, CENTRAL_ATTRS, 0o120_777 << 16);
+        assert!(matches!(
+            extract_err(&zip, &dir),
+            ExtractError::Symlink(name) if name == "nomad"
+        ));
+    }
+
+    #[test]
+    fn declared_size_too_large() {
+        let dir = scratch_dir("extract-large");
+        let zip = write_zip(&dir, &[("nomad", &elf(ELF_MACHINE))]);
+        patch_central(&zip, CENTRAL_SIZE, u32::MAX);
+        assert!(matches!(
+            extract_err(&zip, &dir),
+            ExtractError::TooLarge(size) if size == u64::from(u32::MAX)
+        ));
+    }
+
+    #[test]
+    fn implausible_ratio() {
+        let dir = scratch_dir("extract-ratio");
+        let path = dir.join("nomad.zip");
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        zip.start_file("nomad", options).unwrap();
+        zip.write_all(&vec![0; 1024 * 1024]).unwrap();
+        zip.finish().unwrap();
+        assert!(matches!(
+            extract_err(&path, &dir),
+            ExtractError::Ratio { size, .. } if size == 1024 * 1024
+        ));
+    }
+
+    #[test]
+    fn size_mismatch() {
+        let dir = scratch_dir("extract-mismatch");
+        let data = elf(ELF_MACHINE);
+        let zip = write_zip(&dir, &[("nomad", &data)]);
+        patch_central(&zip, CENTRAL_SIZE, data.len() as u32 + 1);
+        assert!(matches!(
+            extract_err(&zip, &dir),
+            ExtractError::SizeMismatch { declared, actual }
+                if declared == data.len() as u64 + 1 && actual == data.len() as u64
+        ));
+    }
+
+    #[test]
+    fn not_elf() {
+        let dir = scratch_dir("extract-not-elf");
+        let zip = write_zip(&dir, &[("nomad", b"#!/bin/sh\necho nomad\n")]);
+        assert!(matches!(
+            extract_err(&zip, &dir),
+            ExtractError::NotElf(reason) if reason == "bad magic"
+        ));
+
+        let zip = write_zip(&dir, &[("nomad", b"\x7fELF")]);
+        assert!(matches!(
+            extract_err(&zip, &dir),
+            ExtractError::NotElf(reason) if reason == "too short"
+        ));
+    }
+
+    #[test]
+    fn wrong_arch() {
+        let dir = scratch_dir("extract-arch");
+        // 32-bit ARM
+        let zip = write_zip(&dir, &[("nomad", &elf(40))]);
+        assert!(matches!(
+            extract_err(&zip, &dir),
+            ExtractError::WrongArch(40)
+        ));
+    }
+}
@@ -0,0 +1,276 @@
+//! Module for preparing a host to run the nomad agent.
+//!
+//! The setup is computed as a list of actions from what is already on the host, so it can be
+//! shown without being applied, and a host that is already set up needs no actions.
+
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::chown;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Default user and group of the nomad agent when setting up a host.
+pub const DEFAULT_AGENT_USER: &str = "nomad";
+/// Mode of the nomad configuration directory.
+const CONFIG_DIR_MODE: u32 = 0o750;
+/// Mode of the nomad data directory.
+const DATA_DIR_MODE: u32 = 0o700;
+
+/// Parts of the host setup that can be skipped.
+pub const SETUP_ITEMS: &[&str] = &["user", "config-dir", "data-dir"];
+
+/// An action needed to set up the host.
+#[derive(Clone, Debug)]
+pub enum HostAction {
+    /// create a system group
+    CreateGroup(String),
+    /// create a system user with a primary group and a home directory
+    CreateUser {
+        name: String,
+        group: String,
+        home: PathBuf,
+    },
+    /// create a directory
+    CreateDir(PathBuf),
+    /// change the mode of a path
+    Chmod(PathBuf, u32),
+    /// change the owner of a path
+    Chown(PathBuf, String, String),
+}
+
+impl fmt::Display for HostAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CreateGroup(name) => write!(f, "create system group {}", name),
+            Self::CreateUser { name, group, home } => write!(
+                f,
+                "create system user {} in group {} with home {}",
+                name,
+                group,
+                home.display()
+            ),
+            Self::CreateDir(path) => write!(f, "create directory {}", path.display()),
+            Self::Chmod(path, mode) => {
+                write!(f, "set the mode of {} to {:o}", path.display(), mode)
+            }
+            Self::Chown(path, user, group) => {
+                write!(
+                    f,
+                    "set the owner of {} to {}:{}",
+                    path.display(),
+                    user,
+                    group
+                )
+            }
+        }
+    }
+}
+
+/// The setup of a host for the nomad agent.
+pub struct HostSetup {
+    /// user the agent runs as, None or root for root
+    user: Option<String>,
+    /// group the agent runs as, defaults to the user
+    group: Option<String>,
+    config_dir: PathBuf,
+    data_dir: PathBuf,
+    /// items of SETUP_ITEMS to skip
+    skip: Vec<String>,
+}
+
+impl HostSetup {
+    /// Create a new HostSetup.
+    pub fn new(
+        user: Option<String>,
+        group: Option<String>,
+        config_dir: PathBuf,
+        data_dir: PathBuf,
+        skip: Vec<String>,
+    ) -> Self {
+        Self {
+            user,
+            group,
+            config_dir,
+            data_dir,
+            skip,
+        }
+    }
+
+    /// Get the user and group the agent runs as.
+    fn owner(&self) -> (String, String) {
+        let user = self.user.clone().unwrap_or_else(|| "root".to_string());
+        let group = self.group.clone().unwrap_or_else(|| user.clone());
+        (user, group)
+    }
+
+    /// Whether an item of the setup is skipped.
+    fn skips(&self, item: &str) -> bool {
+        self.skip.iter().any(|value| value == item)
+    }
+
+    /// Get the actions needed to set up the host.
+    pub fn actions(&self) -> anyhow::Result<Vec<HostAction>> {
+        let (user, group) = self.owner();
+        let mut actions = Vec::new();
+
+        if !self.skips("user") && user != "root" {
+            if lookup_id("group", group.as_str())?.is_none() {
+                actions.push(HostAction::CreateGroup(group.clone()));
+            }
+            if lookup_id("passwd", user.as_str())?.is_none() {
+                actions.push(HostAction::CreateUser {
+                    name: user.clone(),
+                    group: group.clone(),
+                    home: self.data_dir.clone(),
+                });
+            }
+        }
+
+        // The configuration may hold tokens, so only the agent's group can read it.
+        if !self.skips("config-dir") {
+            dir_actions(
+                &mut actions,
+                &self.config_dir,
+                CONFIG_DIR_MODE,
+                "root",
+                group.as_str(),
+            )?;
+        }
+        if !self.skips("data-dir") {
+            dir_actions(
+                &mut actions,
+                &self.data_dir,
+                DATA_DIR_MODE,
+                user.as_str(),
+                group.as_str(),
+            )?;
+        }
+
+        Ok(actions)
+    }
+
+    /// Set up the host, logging every action taken.
+    pub fn apply(&self) -> anyhow::Result<()> {
+        let actions = self.actions()?;
+        if actions.is_empty() {
+            log::info!("host is already set up");
+            return Ok(());
+        }
+        for action in actions {
+            apply(&action)?;
+            log::info!("{}", action);
+        }
+        Ok(())
+    }
+}
+
+/// Add the actions needed for a directory with `mode`, owned by `user` and `group`.
+fn dir_actions(
+    actions: &mut Vec<HostAction>,
+    path: &Path,
+    mode: u32,
+    user: &str,
+    group: &str,
+) -> anyhow::Result<()> {
+    let metadata = match fs::metadata(path) {
+        Ok(value) => value,
+        Err(_) => {
+            actions.push(HostAction::CreateDir(path.to_path_buf()));
+            actions.push(HostAction::Chmod(path.to_path_buf(), mode));
+            actions.push(HostAction::Chown(
+                path.to_path_buf(),
+                user.to_string(),
+                group.to_string(),
+            ));
+            return Ok(());
+        }
+    };
+    if !metadata.is_dir() {
+        anyhow::bail!("{} exists and is not a directory", path.display());
+    }
+
+    if metadata.mode() & 0o7777 != mode {
+        actions.push(HostAction::Chmod(path.to_path_buf(), mode));
+    }
+    let uid = lookup_id("passwd", user)?;
+    let gid = lookup_id("group", group)?;
+    if uid != Some(metadata.uid()) || gid != Some(metadata.gid()) {
+        actions.push(HostAction::Chown(
+            path.to_path_buf(),
+            user.to_string(),
+            group.to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Apply an action.
+fn apply(action: &HostAction) -> anyhow::Result<()> {
+    match action {
+        HostAction::CreateGroup(name) => run("groupadd", &["--system", name.as_str()]),
+        HostAction::CreateUser { name, group, home } => run(
+            "useradd",
+            &[
+                "--system",
+                "--gid",
+                group.as_str(),
+                "--home-dir",
+                home.to_str()
+                    .ok_or_else(|| anyhow::anyhow!("bad home directory {}", home.display()))?,
+                "--no-create-home",
+                "--shell",
+                "/bin/false",
+                name.as_str(),
+            ],
+        ),
+        HostAction::CreateDir(path) => Ok(fs::create_dir_all(path)?),
+        HostAction::Chmod(path, mode) => Ok(fs::set_permissions(
+            path,
+            fs::Permissions::from_mode(*mode),
+        )?),
+        HostAction::Chown(path, user, group) => {
+            let uid = lookup_id("passwd", user)?
+                .ok_or_else(|| anyhow::anyhow!("no such user {}", user))?;
+            let gid = lookup_id("group", group)?
+                .ok_or_else(|| anyhow::anyhow!("no such group {}", group))?;
+            Ok(chown(path, Some(uid), Some(gid))?)
+        }
+    }
+}
+
+/// Look up the id of a user (`database` passwd) or group (`database` group).
+///
+/// Returns None if there is no such user or group.
+fn lookup_id(database: &str, name: &str) -> anyhow::Result<Option<u32>> {
+    let output = Command::new("getent").args([database, name]).output()?;
+    // getent exits with 2 when the key is not found.
+    if output.status.code() == Some(2) {
+        return Ok(None);
+    }
+    if !output.status.success() {
+        anyhow::bail!("getent {} {} exited with {}", database, name, output.status);
+    }
+
+    // The entries look like "nomad:x:998:998:...", the id is the third field.
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let id = stdout
+        .lines()
+        .next()
+        .and_then(|line| line.split(':').nth(2))
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| anyhow::anyhow!("unexpected getent output: {}", stdout))?;
+    Ok(Some(id))
+}
+
+/// Run a command, failing if it does not exit successfully.
+fn run(program: &str, args: &[&str]) -> anyhow::Result<()> {
+    log::debug!("running {} {}", program, args.join(" "));
+    let status = Command::new(program).args(args).status()?;
+    if !status.success() {
+        anyhow::bail!("{} {} exited with {}", program, args.join(" "), status);
+    }
+    Ok(())
+}
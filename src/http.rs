@@ -0,0 +1,393 @@
+//! Module for talking to the remote endpoints over http.
+//!
+//! Requests are retried with exponential backoff and jitter when they fail in a way that is
+//! likely to be transient: connection errors, timeouts, 5xx responses and 429 responses.
+//!
+//! Proxies are configured explicitly rather than left to reqwest: the proxy from the settings
+//! takes precedence over `HTTPS_PROXY`/`HTTP_PROXY`, and hosts in the no proxy list (or
+//! `NO_PROXY`) are always reached directly.
+
+use crate::common::USER_AGENT;
+
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use reqwest::blocking::Client;
+use reqwest::blocking::RequestBuilder;
+use reqwest::blocking::Response;
+use reqwest::Certificate;
+use reqwest::Identity;
+use reqwest::Proxy;
+use reqwest::StatusCode;
+use reqwest::Url;
+
+/// Default number of attempts for a request.
+pub const DEFAULT_ATTEMPTS: u32 = 4;
+/// Delay before the first retry.
+const BASE_DELAY: Duration = Duration::from_millis(500);
+/// Upper bound for the delay between two attempts.
+const MAX_DELAY: Duration = Duration::from_secs(30);
+/// Default timeout for establishing a connection, in seconds.
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+/// Default timeout for a single read, in seconds.
+pub const DEFAULT_READ_TIMEOUT: u64 = 30;
+
+/// When and how often to retry a request.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// total number of attempts, including the first one
+    attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    #[allow(missing_docs)]
+    fn default() -> Self {
+        Self::new(DEFAULT_ATTEMPTS)
+    }
+}
+
+impl RetryPolicy {
+    /// Create a new RetryPolicy making at most `attempts` attempts.
+    #[inline]
+    pub fn new(attempts: u32) -> Self {
+        Self {
+            attempts: attempts.max(1),
+        }
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Get the delay before retrying after the `attempt`th attempt failed.
+    ///
+    /// The delay doubles with every attempt and a random half of it is jitter, so that many hosts
+    /// failing at once do not retry in lockstep.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let delay = BASE_DELAY
+            .checked_mul(1 << attempt.saturating_sub(1).min(16))
+            .unwrap_or(MAX_DELAY)
+            .min(MAX_DELAY);
+        let half = delay.as_millis() as u64 / 2;
+        Duration::from_millis(half + fastrand::u64(0..=half))
+    }
+
+    /// Sleep before the next attempt and log why.
+    pub fn wait(&self, attempt: u32, what: &str, reason: &dyn std::fmt::Display) {
+        let delay = self.backoff(attempt);
+        log::warn!(
+            "{} failed (attempt {}/{}): {}, retrying in {:.1}s",
+            what,
+            attempt,
+            self.attempts,
+            reason,
+            delay.as_secs_f64()
+        );
+        thread::sleep(delay);
+    }
+}
+
+/// Whether a request error is likely to be transient.
+///
+/// Only failures to connect and timeouts are: TLS and certificate failures, bad urls and redirect
+/// policy errors fail the same way on every attempt.
+pub fn is_transient_error(e: &reqwest::Error) -> bool {
+    e.is_connect() || e.is_timeout()
+}
+
+/// Whether a response status is likely to be transient.
+pub fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Settings of the http client.
+#[derive(Clone, Debug)]
+pub struct HttpSettings {
+    retry: RetryPolicy,
+    /// proxy for all requests, overriding HTTPS_PROXY and HTTP_PROXY
+    proxy: Option<String>,
+    /// comma separated hosts to reach directly, overriding NO_PROXY
+    no_proxy: Option<String>,
+    /// PEM file with extra root certificates
+    ca_cert: Option<PathBuf>,
+    /// PEM file with the client certificate, and the key if client_key is not set
+    client_cert: Option<PathBuf>,
+    /// PEM file with the key of the client certificate
+    client_key: Option<PathBuf>,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+}
+
+impl Default for HttpSettings {
+    #[allow(missing_docs)]
+    fn default() -> Self {
+        Self::new(RetryPolicy::default())
+    }
+}
+
+impl HttpSettings {
+    /// Create new HttpSettings.
+    #[inline]
+    pub fn new(retry: RetryPolicy) -> Self {
+        Self {
+            retry,
+            proxy: None,
+            no_proxy: None,
+            ca_cert: None,
+            client_cert: None,
+            client_key: None,
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT),
+            read_timeout: Duration::from_secs(DEFAULT_READ_TIMEOUT),
+        }
+    }
+
+    /// Set the proxy and the hosts to reach directly.
+    #[inline]
+    pub fn with_proxy(mut self, proxy: Option<String>, no_proxy: Option<String>) -> Self {
+        self.proxy = proxy;
+        self.no_proxy = no_proxy;
+        self
+    }
+
+    /// Trust the root certificates in a PEM file, in addition to the built-in ones.
+    #[inline]
+    pub fn with_ca_cert(mut self, ca_cert: Option<PathBuf>) -> Self {
+        self.ca_cert = ca_cert;
+        self
+    }
+
+    /// Present a client certificate.
+    #[inline]
+    pub fn with_client_cert(mut self, cert: Option<PathBuf>, key: Option<PathBuf>) -> Self {
+        self.client_cert = cert;
+        self.client_key = key;
+        self
+    }
+
+    /// Set the connect and read timeouts.
+    #[inline]
+    pub fn with_timeouts(mut self, connect: Duration, read: Duration) -> Self {
+        self.connect_timeout = connect;
+        self.read_timeout = read;
+        self
+    }
+
+    /// Get the proxy for a url, if any.
+    fn proxy_for(&self, url: &Url) -> Option<Url> {
+        let host = url.host_str().unwrap_or_default();
+        let no_proxy = self
+            .no_proxy
+            .clone()
+            .or_else(|| env_var(&["NO_PROXY", "no_proxy"]))
+            .unwrap_or_default();
+        if bypasses_proxy(no_proxy.as_str(), host) {
+            return None;
+        }
+
+        let proxy = self.proxy.clone().or_else(|| match url.scheme() {
+            "https" => env_var(&["HTTPS_PROXY", "https_proxy"]),
+            "http" => env_var(&["HTTP_PROXY", "http_proxy"]),
+            _ => None,
+        })?;
+        match Url::parse(proxy.as_str()) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                log::warn!("ignoring bad proxy {}: {}", proxy, e);
+                None
+            }
+        }
+    }
+
+    /// Build a client from these settings.
+    fn client(&self) -> anyhow::Result<Client> {
+        if let Some(value) = &self.proxy {
+            Url::parse(value).map_err(|e| anyhow::anyhow!("bad proxy {}: {}", value, e))?;
+        }
+
+        let settings = self.clone();
+        let mut builder = Client::builder()
+            .user_agent(USER_AGENT)
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.read_timeout)
+            .no_proxy()
+            .proxy(Proxy::custom(move |url| settings.proxy_for(url)));
+
+        if let Some(path) = &self.ca_cert {
+            let pem = read_pem(path)?;
+            builder = builder
+                .add_root_certificate(Certificate::from_pem(pem.as_slice()).map_err(|e| {
+                    anyhow::anyhow!("bad ca certificate {}: {}", path.display(), e)
+                })?);
+            log::debug!("trusting the certificates in {}", path.display());
+        }
+
+        if let Some(path) = &self.client_cert {
+            let mut pem = read_pem(path)?;
+            if let Some(key) = &self.client_key {
+                pem.push(b'\n');
+                pem.extend(read_pem(key)?);
+            }
+            builder = builder.identity(Identity::from_pem(pem.as_slice()).map_err(|e| {
+                anyhow::anyhow!("bad client certificate {}: {}", path.display(), e)
+            })?);
+            log::debug!("presenting the client certificate {}", path.display());
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+/// Read a PEM file.
+fn read_pem(path: &Path) -> anyhow::Result<Vec<u8>> {
+    fs::read(path).map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))
+}
+
+/// Get the first set environment variable out of `names`.
+fn env_var(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+}
+
+/// Whether `host` matches a comma separated no proxy list.
+///
+/// Entries are `*`, ip addresses or domains, which also match their subdomains.
+fn bypasses_proxy(no_proxy: &str, host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let ip = host.parse::<IpAddr>().ok();
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            if entry == "*" {
+                return true;
+            }
+            if let (Some(ip), Ok(entry)) = (ip, entry.parse::<IpAddr>()) {
+                return ip == entry;
+            }
+            let entry = entry.trim_start_matches('.');
+            host == entry || host.ends_with(format!(".{}", entry).as_str())
+        })
+}
+
+/// An http client that retries transient failures.
+///
+/// The underlying client is built on first use, so that bad settings, e.g. an unreadable
+/// certificate file, are reported by the command that uses it.
+#[derive(Clone, Debug)]
+pub struct HttpClient {
+    settings: HttpSettings,
+    client: OnceLock<Client>,
+}
+
+impl Default for HttpClient {
+    #[allow(missing_docs)]
+    fn default() -> Self {
+        Self::new(&HttpSettings::default())
+    }
+}
+
+impl HttpClient {
+    /// Create a new HttpClient.
+    pub fn new(settings: &HttpSettings) -> Self {
+        Self {
+            settings: settings.clone(),
+            client: OnceLock::new(),
+        }
+    }
+
+    /// Get the underlying client, building it if needed.
+    ///
+    /// Fails if the certificate files in the settings cannot be read.
+    pub fn client(&self) -> anyhow::Result<&Client> {
+        if let Some(value) = self.client.get() {
+            return Ok(value);
+        }
+        let client = self.settings.client()?;
+        Ok(self.client.get_or_init(|| client))
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn retry(&self) -> &RetryPolicy {
+        &self.settings.retry
+    }
+
+    /// Send a request, retrying transient failures.
+    ///
+    /// `build` is called for every attempt. `what` describes the request in log messages. The
+    /// last response is returned even if its status is not a success, so callers still have to
+    /// check it.
+    pub fn send<F>(&self, what: &str, build: F) -> anyhow::Result<Response>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        let client = self.client()?;
+        let retry = &self.settings.retry;
+        let mut attempt = 1;
+        loop {
+            match build(client).send() {
+                Ok(res) if is_transient_status(res.status()) && attempt < retry.attempts => {
+                    retry.wait(attempt, what, &res.status());
+                }
+                Ok(res) => return Ok(res),
+                Err(e) if is_transient_error(&e) && attempt < retry.attempts => {
+                    retry.wait(attempt, what, &e);
+                }
+                Err(e) => return Err(e.into()),
+            }
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_transient_error;
+    use super::is_transient_status;
+
+    use std::net::TcpListener;
+
+    use reqwest::blocking::Client;
+    use reqwest::StatusCode;
+
+    #[test]
+    fn transient_statuses() {
+        assert!(is_transient_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(is_transient_status(StatusCode::BAD_GATEWAY));
+        assert!(is_transient_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_transient_status(StatusCode::NOT_FOUND));
+        assert!(!is_transient_status(StatusCode::FORBIDDEN));
+    }
+
+    #[test]
+    fn connection_refused_is_transient() {
+        // Bind and drop a listener to get a local port nothing listens on.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let e = Client::new()
+            .get(format!("http://127.0.0.1:{}/", port).as_str())
+            .send()
+            .unwrap_err();
+        assert!(is_transient_error(&e));
+    }
+
+    #[test]
+    fn bad_urls_are_not_transient() {
+        let e = Client::new().get("not a url").send().unwrap_err();
+        assert!(!is_transient_error(&e));
+    }
+}
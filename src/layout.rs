@@ -0,0 +1,239 @@
+//! Module for the versioned install layout.
+//!
+//! In this layout every release is unpacked into `<prefix>/nomad/<version>/nomad` and the nomad
+//! binary path becomes a symlink to the selected version.
+
+use crate::common::backup_path;
+use crate::common::compare_versions;
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+use std::path::PathBuf;
+
+use semver::Version;
+
+/// Default prefix for the versioned install layout.
+pub const DEFAULT_PREFIX: &str = "/opt";
+
+/// The versioned install layout.
+pub struct VersionedLayout {
+    root: PathBuf,
+}
+
+impl VersionedLayout {
+    /// Create a new versioned layout rooted at `<prefix>/nomad`.
+    pub fn new(prefix: &Path) -> Self {
+        Self {
+            root: prefix.join("nomad"),
+        }
+    }
+
+    /// Get the directory of a version.
+    pub fn version_dir(&self, version: &str) -> PathBuf {
+        self.root.join(version)
+    }
+
+    /// Get the path of the nomad binary of a version.
+    pub fn binary(&self, version: &str) -> PathBuf {
+        self.version_dir(version).join("nomad")
+    }
+
+    /// Get the installed versions, sorted from oldest to newest.
+    pub fn installed(&self) -> anyhow::Result<Vec<String>> {
+        if !self.root.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut versions = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let version = if let Some(value) = entry.file_name().to_str() {
+                value.to_string()
+            } else {
+                continue;
+            };
+            if self.binary(version.as_str()).is_file() {
+                versions.push(version);
+            }
+        }
+        versions.sort_by(|a, b| compare_versions(a, b));
+
+        Ok(versions)
+    }
+
+    /// Get the version the symlink at `link` points to, if it points inside this layout.
+    pub fn active(&self, link: &Path) -> anyhow::Result<Option<String>> {
+        let target = match fs::read_link(link) {
+            Ok(value) => value,
+            Err(_) => return Ok(None),
+        };
+        let version = target
+            .strip_prefix(&self.root)
+            .ok()
+            .and_then(|value| value.parent())
+            .and_then(|value| value.to_str())
+            .map(|value| value.to_string());
+        Ok(version)
+    }
+
+    /// Point the symlink at `link` to the binary of a version.
+    ///
+    /// The symlink is created next to `link` and renamed over it, so `link` never goes missing.
+    /// A nomad binary at `link` that was not installed in this layout is backed up to
+    /// `<link>.bak` first. Anything else at `link` is not replaced.
+    pub fn activate(&self, version: &str, link: &Path) -> anyhow::Result<()> {
+        check_version(version)?;
+        let bin = self.binary(version);
+        if !bin.is_file() {
+            anyhow::bail!(
+                "version {} is not installed in {}",
+                version,
+                self.root.display()
+            );
+        }
+
+        match fs::symlink_metadata(link) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                if self.active(link)?.is_none() {
+                    anyhow::bail!(
+                        "{} is a symlink to {}, outside of {}, not replacing it",
+                        link.display(),
+                        fs::read_link(link)?.display(),
+                        self.root.display()
+                    );
+                }
+            }
+            Ok(metadata) if metadata.is_file() => {
+                let backup = backup_path(link);
+                fs::copy(link, &backup)?;
+                log::warn!(
+                    "{} is not in the versioned layout, backed it up to {}",
+                    link.display(),
+                    backup.display()
+                );
+            }
+            Ok(_) => anyhow::bail!("{} is not a file, not replacing it", link.display()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let tmp = {
+            let mut tmp = link.to_path_buf().into_os_string();
+            tmp.push(".link");
+            PathBuf::from(tmp)
+        };
+        if fs::symlink_metadata(&tmp).is_ok() {
+            fs::remove_file(&tmp)?;
+        }
+        symlink(&bin, &tmp)?;
+        fs::rename(&tmp, link)?;
+
+        Ok(())
+    }
+}
+
+/// Make sure a version is a semantic version before it becomes a path in the layout.
+///
+/// Anything else, such as `../../usr`, could point outside of the layout.
+pub fn check_version(version: &str) -> anyhow::Result<()> {
+    Version::parse(version)
+        .map(|_| ())
+        .map_err(|e| anyhow::anyhow!("bad version {}: {}", version, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_version;
+    use super::VersionedLayout;
+
+    use crate::common::scratch_dir;
+
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    /// Create a layout in a fresh directory named after `name` with the versions installed.
+    fn layout(name: &str, versions: &[&str]) -> (PathBuf, VersionedLayout) {
+        let dir = scratch_dir(name);
+        let layout = VersionedLayout::new(&dir);
+        for version in versions {
+            fs::create_dir_all(layout.version_dir(version)).unwrap();
+            fs::write(layout.binary(version), version).unwrap();
+        }
+        (dir, layout)
+    }
+
+    #[test]
+    fn versions_are_semver() {
+        assert!(check_version("1.0.1").is_ok());
+        assert!(check_version("1.1.0-beta1").is_ok());
+        assert!(check_version("1.0.1+ent").is_ok());
+        assert!(check_version("../../usr").is_err());
+        assert!(check_version("1.0.1/../../..").is_err());
+        assert!(check_version("").is_err());
+    }
+
+    #[test]
+    fn activate_refuses_paths() {
+        let layout = VersionedLayout::new(Path::new("/opt"));
+        let e = layout
+            .activate("../../usr", Path::new("/usr/local/bin/nomad"))
+            .unwrap_err();
+        assert!(e.to_string().starts_with("bad version"));
+    }
+
+    #[test]
+    fn activate_switches_versions() {
+        let (dir, layout) = layout("layout-switch", &["1.0.1", "1.0.2"]);
+        let link = dir.join("nomad-bin");
+
+        layout.activate("1.0.1", &link).unwrap();
+        assert_eq!(layout.active(&link).unwrap().as_deref(), Some("1.0.1"));
+        layout.activate("1.0.2", &link).unwrap();
+        assert_eq!(layout.active(&link).unwrap().as_deref(), Some("1.0.2"));
+        assert_eq!(fs::read_to_string(&link).unwrap(), "1.0.2");
+        assert!(!dir.join("nomad-bin.bak").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn activate_backs_up_plain_binaries() {
+        let (dir, layout) = layout("layout-backup", &["1.0.2"]);
+        let link = dir.join("nomad-bin");
+        fs::write(&link, "plain").unwrap();
+
+        layout.activate("1.0.2", &link).unwrap();
+        assert_eq!(layout.active(&link).unwrap().as_deref(), Some("1.0.2"));
+        assert_eq!(
+            fs::read_to_string(dir.join("nomad-bin.bak")).unwrap(),
+            "plain"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn activate_keeps_foreign_files() {
+        let (dir, layout) = layout("layout-foreign", &["1.0.2"]);
+        let other = dir.join("other");
+        fs::write(&other, "other").unwrap();
+
+        let link = dir.join("nomad-bin");
+        symlink(&other, &link).unwrap();
+        let e = layout.activate("1.0.2", &link).unwrap_err();
+        assert!(e.to_string().ends_with("not replacing it"));
+        assert_eq!(fs::read_link(&link).unwrap(), other);
+
+        let link = dir.join("nomad-dir");
+        fs::create_dir(&link).unwrap();
+        let e = layout.activate("1.0.2", &link).unwrap_err();
+        assert!(e.to_string().ends_with("not replacing it"));
+        assert!(link.is_dir());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,13 +1,26 @@
 #[macro_use]
 extern crate rust_embed;
 
+mod agent_config;
 mod artifacts;
+mod cache;
 mod checkpoint;
 #[macro_use]
 mod cmd;
 mod common;
+mod config;
+mod config_check;
+mod constraint;
+mod extract;
+mod host;
+mod http;
+mod layout;
+mod nomad;
 mod releases;
 mod security;
+mod state;
+mod systemd;
+mod tls;
 
 use cmd::*;
 
@@ -47,7 +60,19 @@ fn main() {
 
         register_subcommands!(app, commands: {
             InfoCmd,
-            InstallCmd
+            InstallCmd,
+            UninstallCmd,
+            UpgradeCmd,
+            UseCmd,
+            ListInstalledCmd,
+            VersionsCmd,
+            CacheCmd,
+            VerifyCmd,
+            StatusCmd,
+            ServiceCmd,
+            InitConfigCmd,
+            ConfigCmd,
+            TlsCmd
         });
 
         app
@@ -77,14 +102,34 @@ fn main() {
             out.finish(format_args!("{} {} {}", time, prefix, message));
         })
         .level(log_level)
-        .chain(std::io::stdout())
+        .chain(std::io::stderr())
         .apply()
         .unwrap();
 
+    let config = match config::Config::load() {
+        Ok(value) => value,
+        Err(e) => {
+            log::error!("failed to load the configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+
     log::trace!("nomadutil ready");
 
-    match_subcommands!(matches, commands: {
+    match_subcommands!(matches, config, commands: {
         InfoCmd,
-        InstallCmd
+        InstallCmd,
+        UninstallCmd,
+        UpgradeCmd,
+        UseCmd,
+        ListInstalledCmd,
+        VersionsCmd,
+        CacheCmd,
+        VerifyCmd,
+        StatusCmd,
+        ServiceCmd,
+        InitConfigCmd,
+        ConfigCmd,
+        TlsCmd
     });
 }
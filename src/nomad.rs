@@ -0,0 +1,136 @@
+//! Module for interacting with an installed Nomad.
+
+use crate::http::HttpClient;
+use crate::http::HttpSettings;
+use crate::http::RetryPolicy;
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use reqwest::blocking::Client;
+
+/// Default address of the local Nomad agent's HTTP API.
+pub const DEFAULT_NOMAD_ADDR: &str = "http://127.0.0.1:4646";
+
+/// Get the version of a nomad binary by running `nomad version`.
+///
+/// Returns None if the binary does not exist.
+pub fn installed_version(bin: &Path) -> anyhow::Result<Option<String>> {
+    if !bin.exists() {
+        return Ok(None);
+    }
+
+    let output = Command::new(bin).arg("version").output()?;
+    if !output.status.success() {
+        anyhow::bail!("{} version exited with {}", bin.display(), output.status);
+    }
+
+    // The first line looks like "Nomad v1.0.1 (c9c68aa55a7275f22d2338f2df53e67ebfcb9238)".
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .map(|value| value.trim_start_matches('v'))
+        .ok_or_else(|| {
+            anyhow::anyhow!("unexpected {} version output: {}", bin.display(), stdout)
+        })?;
+
+    Ok(Some(version.to_string()))
+}
+
+/// How long a single health check request may take.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Checks the health of a local Nomad agent.
+///
+/// Agents with TLS enabled are reached with the CA and client certificate from the settings,
+/// which default to `NOMAD_CACERT`, `NOMAD_CLIENT_CERT` and `NOMAD_CLIENT_KEY` like the nomad
+/// cli does.
+#[derive(Clone, Debug)]
+pub struct HealthProbe {
+    /// address of the agent's HTTP API
+    addr: String,
+    client: Client,
+}
+
+impl HealthProbe {
+    /// Create a new HealthProbe for the agent at `addr`.
+    ///
+    /// Fails if the certificate files cannot be read.
+    pub fn new(
+        addr: &str,
+        ca_cert: Option<PathBuf>,
+        client_cert: Option<PathBuf>,
+        client_key: Option<PathBuf>,
+    ) -> anyhow::Result<Self> {
+        // The agent is local, so never go through a proxy, and each attempt is bounded by the
+        // probe timeout instead of being retried by the client.
+        let settings = HttpSettings::new(RetryPolicy::new(1))
+            .with_proxy(None, Some("*".to_string()))
+            .with_ca_cert(ca_cert.or_else(|| env_path("NOMAD_CACERT")))
+            .with_client_cert(
+                client_cert.or_else(|| env_path("NOMAD_CLIENT_CERT")),
+                client_key.or_else(|| env_path("NOMAD_CLIENT_KEY")),
+            )
+            .with_timeouts(PROBE_TIMEOUT, PROBE_TIMEOUT);
+        Ok(Self {
+            addr: addr.trim_end_matches('/').to_string(),
+            client: HttpClient::new(&settings).client()?.clone(),
+        })
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn addr(&self) -> &str {
+        self.addr.as_str()
+    }
+
+    /// Check whether the agent reports itself as healthy, waiting at most `timeout`.
+    pub fn healthy(&self, timeout: Duration) -> bool {
+        let url = format!("{}/v1/agent/health", self.addr);
+        match self
+            .client
+            .get(url.as_str())
+            .timeout(timeout.min(PROBE_TIMEOUT))
+            .send()
+        {
+            Ok(res) => {
+                log::debug!("{}: {}", url, res.status());
+                res.status().is_success()
+            }
+            Err(e) => {
+                log::debug!("{}: {}", url, e);
+                false
+            }
+        }
+    }
+
+    /// Wait for the agent to report itself as healthy.
+    ///
+    /// Returns whether the agent became healthy before the timeout elapsed.
+    pub fn wait_healthy(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        loop {
+            let remaining = timeout.checked_sub(start.elapsed()).unwrap_or_default();
+            if self.healthy(remaining.max(Duration::from_secs(1))) {
+                return true;
+            }
+            if start.elapsed() >= timeout {
+                return false;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+}
+
+/// Get a path from an environment variable, None if it is not set or empty.
+fn env_path(name: &str) -> Option<PathBuf> {
+    std::env::var_os(name)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
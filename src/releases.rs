@@ -1,15 +1,24 @@
 //! Module for getting a release.
 
 use crate::artifacts::*;
+use crate::cache::Cache;
 use crate::cmd::InstallCmd;
+use crate::common::Endpoints;
+use crate::common::TempFile;
+use crate::constraint::VersionConstraint;
+use crate::extract::extract_nomad;
 use crate::security::*;
 
-use std::io::Cursor;
-use std::io::Read;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
 
-use bytes::Bytes;
+use reqwest::header::ACCEPT;
 
-use zip::ZipArchive;
+use semver::Version;
+
+use serde::Deserialize;
 
 /// Options for getting a release.
 pub struct ReleaseGetOpts {
@@ -17,12 +26,24 @@ pub struct ReleaseGetOpts {
     check_integrity: bool,
     /// check the signature of the shasums
     check_sig: bool,
+    /// directory of the artifact cache, if caching is enabled
+    cache_dir: Option<PathBuf>,
+    /// artifacts on disk to use instead of downloading them
+    local: Option<LocalArtifacts>,
+    /// the remote endpoints
+    endpoints: Endpoints,
+    /// armoured keyrings to trust in addition to the HashiCorp keys
+    keyrings: Vec<PathBuf>,
+    /// fingerprint of the key that must have signed the shasums
+    require_fingerprint: Option<String>,
+    /// where to stage the zip archive and the binary, e.g. next to the install target
+    staging_dir: Option<PathBuf>,
 }
 
 impl Default for ReleaseGetOpts {
     #[allow(missing_docs)]
     fn default() -> Self {
-        Self::new(true, true)
+        Self::new(true, true, None, Endpoints::default())
     }
 }
 
@@ -32,6 +53,12 @@ impl From<&InstallCmd> for ReleaseGetOpts {
         Self {
             check_integrity: src.check_integrity(),
             check_sig: src.check_sig(),
+            cache_dir: src.cache_dir().map(|value| value.to_path_buf()),
+            local: None,
+            endpoints: src.endpoints().clone(),
+            keyrings: src.keyrings().to_vec(),
+            require_fingerprint: src.require_fingerprint().map(|value| value.to_string()),
+            staging_dir: None,
         }
     }
 }
@@ -39,13 +66,53 @@ impl From<&InstallCmd> for ReleaseGetOpts {
 impl ReleaseGetOpts {
     /// Create a new ReleaseGetOpts.
     #[inline]
-    pub fn new(check_integrity: bool, check_sig: bool) -> Self {
+    pub fn new(
+        check_integrity: bool,
+        check_sig: bool,
+        cache_dir: Option<PathBuf>,
+        endpoints: Endpoints,
+    ) -> Self {
         Self {
             check_integrity,
             check_sig,
+            cache_dir,
+            local: None,
+            endpoints,
+            keyrings: Vec::new(),
+            require_fingerprint: None,
+            staging_dir: None,
         }
     }
 
+    /// Trust additional keyrings and require a signature by a specific key.
+    #[inline]
+    pub fn with_keys(
+        mut self,
+        keyrings: Vec<PathBuf>,
+        require_fingerprint: Option<String>,
+    ) -> Self {
+        self.keyrings = keyrings;
+        self.require_fingerprint = require_fingerprint;
+        self
+    }
+
+    /// Stage the zip archive and the unpacked binary in `dir`.
+    ///
+    /// This should be on the same disk as the install target: the system temp directory is often
+    /// a tmpfs, which would hold a whole release in memory.
+    #[inline]
+    pub fn with_staging_dir(mut self, dir: PathBuf) -> Self {
+        self.staging_dir = Some(dir);
+        self
+    }
+
+    /// Use artifacts on disk instead of downloading them.
+    #[inline]
+    pub fn with_local(mut self, local: LocalArtifacts) -> Self {
+        self.local = Some(local);
+        self
+    }
+
     #[allow(missing_docs, dead_code)]
     #[inline]
     pub fn check_integrity(&self) -> bool {
@@ -57,31 +124,144 @@ impl ReleaseGetOpts {
     pub fn check_sig(&self) -> bool {
         self.check_sig
     }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn cache_dir(&self) -> Option<&Path> {
+        self.cache_dir.as_deref()
+    }
+}
+
+/// A nomad binary unpacked from a release, and how it was obtained.
+pub struct NomadBinary {
+    /// the binary, removed when dropped
+    file: TempFile,
+    /// where the zip archive came from
+    source: String,
+    /// sha256 digest of the zip archive
+    zip_digest: Vec<u8>,
+    /// whether the zip archive was checked against the sha256sums
+    checked_sums: bool,
+    /// fingerprint of the key that signed the sha256sums, if the signature was checked
+    signed_by: Option<String>,
+}
+
+impl NomadBinary {
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn source(&self) -> &str {
+        self.source.as_str()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn zip_digest(&self) -> &[u8] {
+        self.zip_digest.as_slice()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn checked_sums(&self) -> bool {
+        self.checked_sums
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn signed_by(&self) -> Option<&str> {
+        self.signed_by.as_deref()
+    }
+}
+
+/// Get an artifact from the local artifacts or the cache, if possible, or download it.
+///
+/// Returns the artifact and whether it came from the cache. Downloads too large to hold in memory
+/// are staged in `dir`.
+fn fetch<T: RemoteArtifact<T> + LocalArtifact<T>>(
+    version: &str,
+    opts: &ReleaseGetOpts,
+    cache: Option<&Cache>,
+    dir: &Path,
+) -> anyhow::Result<(T, bool)> {
+    if let Some(local) = &opts.local {
+        return Ok((local.load::<T>()?, false));
+    }
+    if let Some(value) = cache.and_then(|cache| cache.load::<T>(version)) {
+        log::info!("using cached {}", T::file_name(version));
+        return Ok((value, true));
+    }
+
+    let artifact = T::get(
+        opts.endpoints.http(),
+        opts.endpoints.releases_url(),
+        version,
+        dir,
+    )?;
+    log::info!("downloaded {}", T::file_name(version));
+    Ok((artifact, false))
 }
 
 /// Get a Nomad release.
 ///
-/// This will return the nomad binary after it has been verifief for integrity and uncompressed.
-pub fn get(version: &str, opts: Option<ReleaseGetOpts>) -> anyhow::Result<Bytes> {
-    let opts: ReleaseGetOpts = if let Some(value) = opts {
-        value
+/// This will return the nomad binary after it has been verifief for integrity and uncompressed,
+/// along with where it came from and how it was verified.
+///
+/// If a cache directory is set, artifacts are taken from the cache when they pass verification
+/// and stored in it after downloading. The cache is not used when integrity checks are disabled,
+/// since there would be no way to tell whether a cached archive can be trusted.
+///
+/// If local artifacts are set, they go through the same verification but nothing is downloaded
+/// or cached.
+///
+/// The zip archive is streamed to disk, into the cache directory if caching is enabled and into
+/// the staging directory otherwise, so memory use does not depend on the size of the release. The
+/// binary is unpacked into the staging directory. Without a staging directory, the system temp
+/// directory is used.
+pub fn get(version: &str, opts: Option<ReleaseGetOpts>) -> anyhow::Result<NomadBinary> {
+    let opts: ReleaseGetOpts = opts.unwrap_or_default();
+
+    if opts.require_fingerprint.is_some() && !(opts.check_integrity && opts.check_sig) {
+        anyhow::bail!("a signature by a specific key is required, but signatures are not checked");
+    }
+
+    let cache: Option<Cache> = if !opts.check_integrity || opts.local.is_some() {
+        None
     } else {
-        ReleaseGetOpts::default()
+        opts.cache_dir.as_deref().map(Cache::new)
     };
+    let cache = cache.as_ref();
+    let staging_dir = opts.staging_dir.clone().unwrap_or_else(std::env::temp_dir);
+    let staging_dir = staging_dir.as_path();
 
+    let mut signed_by = None;
     let sums: Option<Sha256Sums> = if opts.check_integrity {
-        let sums = Sha256Sums::get(version)?;
-        log::info!("downloaded checksums for version {}", version);
+        let (sums, sums_cached) = fetch::<Sha256Sums>(version, &opts, cache, staging_dir)?;
 
         if !opts.check_sig {
             log::warn!("not checking the signature of the shasums");
         } else {
-            let sig = Sha256SumsSig::get(version)?;
-            log::info!("downloaded checksums signature for version {}", version);
+            let (sig, sig_cached) = fetch::<Sha256SumsSig>(version, &opts, cache, staging_dir)?;
+
+            let sig_checker = SigChecker::new(
+                opts.keyrings.as_slice(),
+                opts.require_fingerprint.as_deref(),
+            )?;
+            let fingerprint = sig_checker.check(sig.inner(), sums.inner())?;
+            log::info!("checksums signature ok, signed by {}", fingerprint);
+            signed_by = Some(fingerprint.to_string());
+
+            if let (Some(cache), false) = (cache, sig_cached) {
+                cache.store(version, &sig);
+            }
+        }
 
-            let sig_checker = SigChecker::new()?;
-            let _ = sig_checker.check(sig.inner(), sums.inner())?;
-            log::info!("checksums signature ok");
+        if let (Some(cache), false) = (cache, sums_cached) {
+            cache.store(version, &sums);
         }
 
         Some(sums)
@@ -89,42 +269,276 @@ pub fn get(version: &str, opts: Option<ReleaseGetOpts>) -> anyhow::Result<Bytes>
         None
     };
 
-    let buf = {
-        let zip = {
-            let zip = NomadZip::get(version)?;
-            log::info!("downloaded nomad zip archive for version {}", version);
+    let source = if opts.local.is_some() {
+        None
+    } else {
+        Some(NomadZip::url(opts.endpoints.releases_url(), version))
+    };
 
+    let bin = {
+        let zip = {
             if !opts.check_integrity {
-                log::warn!("not checking the integrity of the zip archive")
+                let (zip, _) = fetch::<NomadZip>(version, &opts, None, staging_dir)?;
+                log::warn!("not checking the integrity of the zip archive");
+                zip
             } else {
                 let sums_checker = SumsChecker::new(sums.unwrap().inner(), version)?;
-                let _ = sums_checker.check(zip.inner())?;
-                log::info!("zip archive ok");
-            }
 
-            zip
+                let cached = cache.and_then(|cache| {
+                    let zip = cache.load::<NomadZip>(version)?;
+                    match sums_checker.check(zip.digest()) {
+                        Ok(()) => Some(zip),
+                        Err(e) => {
+                            log::warn!("cached zip archive is not valid, downloading: {}", e);
+                            None
+                        }
+                    }
+                });
+
+                if let Some(zip) = cached {
+                    log::info!("using cached nomad zip archive for version {}", version);
+                    log::info!("zip archive ok");
+                    zip
+                } else {
+                    let zip = if let Some(cache) = cache {
+                        // Download straight into the cache so storing it is only a rename, and
+                        // so an interrupted download can be resumed by the next run.
+                        let dir = cache.dir(version);
+                        fs::create_dir_all(&dir)?;
+                        let zip = NomadZip::download(
+                            opts.endpoints.http(),
+                            opts.endpoints.releases_url(),
+                            version,
+                            &dir,
+                            true,
+                        )?;
+                        log::info!("downloaded {}", NomadZip::file_name(version));
+                        zip
+                    } else {
+                        let (zip, _) = fetch::<NomadZip>(version, &opts, None, staging_dir)?;
+                        zip
+                    };
+
+                    sums_checker.check(zip.digest())?;
+                    log::info!("zip archive ok");
+
+                    if let Some(cache) = cache {
+                        cache.store(version, &zip);
+                    }
+                    zip
+                }
+            }
         };
-        let mut zip = ZipArchive::new(Cursor::new(zip.inner()))?;
+        let source = source.unwrap_or_else(|| zip.path().display().to_string());
+        let zip_digest = zip.digest().to_vec();
+        let bin = extract_nomad(zip.path(), staging_dir)?;
 
-        if zip.is_empty() {
-            anyhow::bail!("empty archive");
-        }
-        if zip.len() != 1 {
-            log::warn!(
-                "zip archive: {} files: {:?}",
-                zip.len(),
-                zip.file_names().collect::<Vec<&str>>()
-            );
-            anyhow::bail!("malformed zip archive");
+        NomadBinary {
+            file: bin,
+            source,
+            zip_digest,
+            checked_sums: opts.check_integrity,
+            signed_by,
         }
-
-        let mut zip_file = zip.by_name("nomad")?;
-        let mut buf: Vec<u8> = Vec::with_capacity(zip_file.size() as usize);
-        let _ = zip_file.read_to_end(&mut buf)?;
-        buf
     };
 
     log::info!("unzipped the nomad artifact");
 
-    Ok(Bytes::from(buf))
+    Ok(bin)
+}
+
+/// The releases index of a product.
+#[derive(Clone, Debug, Deserialize)]
+struct ReleaseIndex {
+    versions: HashMap<String, Release>,
+}
+
+/// A release from the releases index.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Release {
+    version: String,
+    #[serde(default)]
+    builds: Vec<ReleaseBuild>,
+    #[serde(skip)]
+    semver: Option<Version>,
+}
+
+impl Release {
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn version(&self) -> &str {
+        self.version.as_str()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn builds(&self) -> &[ReleaseBuild] {
+        self.builds.as_slice()
+    }
+
+    /// Get the parsed version.
+    #[inline]
+    pub fn semver(&self) -> &Version {
+        self.semver
+            .as_ref()
+            .expect("releases are only handed out with a parsed version")
+    }
+
+    /// Whether this is a prerelease (beta, rc, ...).
+    #[inline]
+    pub fn is_prerelease(&self) -> bool {
+        !self.semver().pre.is_empty()
+    }
+
+    /// Whether this is an enterprise release.
+    #[inline]
+    pub fn is_enterprise(&self) -> bool {
+        self.semver().build.as_str().contains("ent")
+    }
+
+    /// Get the build for an os and arch.
+    pub fn build(&self, os: &str, arch: &str) -> Option<&ReleaseBuild> {
+        self.builds
+            .iter()
+            .find(|build| build.os == os && build.arch == arch)
+    }
+}
+
+/// A build of a release for an os and arch.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReleaseBuild {
+    os: String,
+    arch: String,
+    filename: String,
+    url: String,
+}
+
+impl ReleaseBuild {
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn os(&self) -> &str {
+        self.os.as_str()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn arch(&self) -> &str {
+        self.arch.as_str()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn filename(&self) -> &str {
+        self.filename.as_str()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn url(&self) -> &str {
+        self.url.as_str()
+    }
+}
+
+/// Options for listing releases.
+pub struct ReleaseListOpts {
+    /// include prereleases
+    prereleases: bool,
+    /// include enterprise releases
+    enterprise: bool,
+    /// the remote endpoints
+    endpoints: Endpoints,
+}
+
+impl Default for ReleaseListOpts {
+    #[allow(missing_docs)]
+    fn default() -> Self {
+        Self::new(false, false, &Endpoints::default())
+    }
+}
+
+impl ReleaseListOpts {
+    /// Create a new ReleaseListOpts.
+    #[inline]
+    pub fn new(prereleases: bool, enterprise: bool, endpoints: &Endpoints) -> Self {
+        Self {
+            prereleases,
+            enterprise,
+            endpoints: endpoints.clone(),
+        }
+    }
+}
+
+/// List the Nomad releases from the releases index.
+///
+/// Releases are sorted from oldest to newest. Versions that are not valid semantic versions are
+/// skipped.
+pub fn list(opts: Option<ReleaseListOpts>) -> anyhow::Result<Vec<Release>> {
+    let opts: ReleaseListOpts = opts.unwrap_or_default();
+
+    let url = format!("{}/nomad/index.json", opts.endpoints.releases_url());
+    let index_res = opts
+        .endpoints
+        .http()
+        .send("getting the releases index", |client| {
+            client.get(url.as_str()).header(ACCEPT, "application/json")
+        })?;
+    if !index_res.status().is_success() {
+        anyhow::bail!("failed to get the releases index: {}", index_res.status());
+    }
+    let index: ReleaseIndex = index_res.json()?;
+    log::debug!("releases index has {} versions", index.versions.len());
+
+    let mut releases: Vec<Release> = index
+        .versions
+        .into_values()
+        .filter_map(
+            |mut release| match Version::parse(release.version.as_str()) {
+                Ok(value) => {
+                    release.semver = Some(value);
+                    Some(release)
+                }
+                Err(e) => {
+                    log::debug!("skipping version {}: {}", release.version, e);
+                    None
+                }
+            },
+        )
+        .filter(|release| opts.prereleases || !release.is_prerelease())
+        .filter(|release| opts.enterprise || !release.is_enterprise())
+        .collect();
+    releases.sort_by(|a, b| a.semver().cmp(b.semver()));
+
+    Ok(releases)
+}
+
+/// Get the newest release with a build for this platform from the releases index.
+pub fn latest(endpoints: &Endpoints) -> anyhow::Result<Release> {
+    let release = list(Some(ReleaseListOpts::new(false, false, endpoints)))?
+        .into_iter()
+        .rev()
+        .find(|release| release.build("linux", crate::ARCH).is_some());
+
+    release.ok_or_else(|| anyhow::anyhow!("no releases found in {}", endpoints.releases_url()))
+}
+
+/// Resolve a version constraint to the newest matching release from the releases index.
+///
+/// Only releases with a build for this platform are considered.
+pub fn resolve(endpoints: &Endpoints, constraint: &VersionConstraint) -> anyhow::Result<Release> {
+    let release = list(Some(ReleaseListOpts::new(false, false, endpoints)))?
+        .into_iter()
+        .rev()
+        .filter(|release| release.build("linux", crate::ARCH).is_some())
+        .find(|release| constraint.matches(release.semver()));
+
+    if let Some(value) = release {
+        log::info!(
+            "version constraint {} resolved to {}",
+            constraint,
+            value.version()
+        );
+        Ok(value)
+    } else {
+        anyhow::bail!("no release matches version constraint {}", constraint);
+    }
 }
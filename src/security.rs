@@ -1,23 +1,89 @@
 //! Security-related module for checking sha25ssums and signature.
 
-use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
 use std::io::Cursor;
+use std::path::PathBuf;
+
+use chrono::DateTime;
+use chrono::TimeZone;
+use chrono::Utc;
 
 use gpgrv::Keyring;
+use gpgrv::Signature;
 
-use sha2::Digest;
-use sha2::Sha256;
+use sha1::Digest;
+use sha1::Sha1;
 
 /// Container for embedded assets.
 ///
-/// Only used to embed the HashiCorp GPG key in the binary.
+/// Only used to embed the HashiCorp GPG keys in the binary.
 #[derive(RustEmbed)]
 #[folder = "assets/"]
 struct Assets;
 
+/// A release signing key known to nomadutil.
+struct KnownKey {
+    /// fingerprint of the primary key
+    fingerprint: &'static str,
+    /// name of the embedded armoured key
+    asset: Option<&'static str>,
+    /// signatures made before this time are rejected
+    valid_from: Option<&'static str>,
+    /// signatures made after this time are rejected
+    valid_until: Option<&'static str>,
+    /// signatures made by this key are always rejected
+    revoked: bool,
+}
+
+/// The HashiCorp release signing keys, current and historical.
+///
+/// When HashiCorp rotates its key, the new key is added here with its armoured key in `assets/`,
+/// and the validity window of the old key is closed. A window only limits a key that was retired
+/// in good order: the signer sets the creation time of a signature, so whoever holds a
+/// compromised key can backdate signatures into any window. Keys that are known to be
+/// compromised are therefore marked as revoked; their key material is not needed to reject their
+/// signatures.
+const HASHICORP_KEYS: &[KnownKey] = &[
+    KnownKey {
+        fingerprint: "91A6E7F85D05C65630BEF18951852D87348FFC4C",
+        asset: Some("security@hashicorp.com.key"),
+        valid_from: None,
+        valid_until: None,
+        // Exposed in HCSEC-2021-12. The releases it signed were re-signed with the current key.
+        revoked: true,
+    },
+    KnownKey {
+        fingerprint: "C874011F0AB405110D02105534365D9472D7468F",
+        asset: Some("security@hashicorp.com-72D7468F.key"),
+        valid_from: None,
+        valid_until: None,
+        revoked: false,
+    },
+];
+
+/// A key that signatures are checked against.
+struct TrustedKey {
+    fingerprint: String,
+    keyring: Keyring,
+    valid_from: Option<DateTime<Utc>>,
+    valid_until: Option<DateTime<Utc>>,
+}
+
+/// A key whose signatures are rejected.
+struct RevokedKey {
+    fingerprint: String,
+    /// ids of the key and its subkeys, if known
+    key_ids: HashSet<u64>,
+}
+
 /// Container for the components required to check signatures.
 pub struct SigChecker {
-    keyring: Keyring,
+    keys: Vec<TrustedKey>,
+    revoked: Vec<RevokedKey>,
+    /// fingerprint of the key that must have made the signature
+    require_fingerprint: Option<String>,
 }
 
 /// Container for the components required to check a zip archive's checksum.
@@ -27,39 +93,465 @@ pub struct SumsChecker {
 
 impl SigChecker {
     /// Create a new signature checker.
-    pub fn new() -> anyhow::Result<Self> {
-        let keyring = {
-            let mut keyring = Keyring::new();
+    ///
+    /// The checker trusts the embedded HashiCorp keys and the keys in the armoured `keyrings`. If
+    /// `require_fingerprint` is set, only signatures made by that key are accepted.
+    pub fn new(keyrings: &[PathBuf], require_fingerprint: Option<&str>) -> anyhow::Result<Self> {
+        let mut keys = Vec::new();
+        let mut revoked = Vec::new();
 
-            let key: Cow<'static, [u8]> =
-                if let Some(value) = Assets::get("security@hashicorp.com.key") {
-                    value
-                } else {
-                    anyhow::bail!("failed to load the embedded gpg key");
-                };
-            let _ = keyring.append_keys_from_armoured(key.to_vec().as_slice());
+        for known in HASHICORP_KEYS {
+            let keyring = match known
+                .asset
+                .and_then(|asset| Assets::get(asset).map(|key| (asset, key)))
+            {
+                Some((asset, key)) => Some(embedded_keyring(asset, &key, known.fingerprint)?),
+                // The key id is enough to reject the signatures of a revoked key.
+                None if known.revoked => None,
+                None => anyhow::bail!(
+                    "the gpg key {} is not embedded in this build",
+                    known.fingerprint
+                ),
+            };
 
-            keyring
-        };
+            if known.revoked {
+                let mut key_ids: HashSet<u64> = keyring
+                    .map(|value| value.key_ids().into_iter().copied().collect())
+                    .unwrap_or_default();
+                key_ids.insert(key_id(known.fingerprint)?);
+                revoked.push(RevokedKey {
+                    fingerprint: known.fingerprint.to_string(),
+                    key_ids,
+                });
+            } else if let Some(keyring) = keyring {
+                keys.push(TrustedKey {
+                    fingerprint: known.fingerprint.to_string(),
+                    keyring,
+                    valid_from: known.valid_from.map(parse_time).transpose()?,
+                    valid_until: known.valid_until.map(parse_time).transpose()?,
+                });
+            }
+        }
+
+        for path in keyrings {
+            let armoured = fs::read(path)
+                .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+            let loaded = load_keys(armoured.as_slice())
+                .map_err(|e| anyhow::anyhow!("bad keyring {}: {}", path.display(), e))?;
+            for (fingerprint, keyring) in loaded {
+                if revoked.iter().any(|key| key.fingerprint == fingerprint) {
+                    log::warn!(
+                        "not trusting revoked key {} from {}",
+                        fingerprint,
+                        path.display()
+                    );
+                    continue;
+                }
+                log::debug!("trusting key {} from {}", fingerprint, path.display());
+                keys.push(TrustedKey {
+                    fingerprint,
+                    keyring,
+                    valid_from: None,
+                    valid_until: None,
+                });
+            }
+        }
+
+        // With a required key, the signature is only checked against that key, so a signature
+        // by another trusted key next to it does not get in the way.
+        let require_fingerprint = require_fingerprint.map(normalize_fingerprint).transpose()?;
+        if let Some(value) = &require_fingerprint {
+            keys.retain(|key| &key.fingerprint == value);
+            if keys.is_empty() {
+                anyhow::bail!(
+                    "a signature by {} is required, but that key is not trusted",
+                    value
+                );
+            }
+        }
 
-        Ok(Self { keyring })
+        Ok(Self {
+            keys,
+            revoked,
+            require_fingerprint,
+        })
     }
 
     /// Check sums against a signature.
-    pub fn check(&self, sig: &[u8], sums: &str) -> anyhow::Result<()> {
-        let sums = Cursor::new(sums.as_bytes());
-        gpgrv::verify_detached(sig, sums, &self.keyring)?;
+    ///
+    /// Returns the fingerprint of the key that made the signature.
+    pub fn check(&self, sig: &[u8], sums: &str) -> anyhow::Result<&str> {
+        let signatures = gpgrv::read_doc(Cursor::new(sig), io::sink())?.signatures;
+
+        // A signature by a revoked key does not count, but it does not taint a signature by a
+        // trusted key next to it, e.g. when a release was re-signed after a key was revoked.
+        let revoked = signatures
+            .iter()
+            .filter_map(|signature| signature.issuer)
+            .find_map(|issuer| {
+                self.revoked
+                    .iter()
+                    .find(|key| key.key_ids.contains(&u64::from_be_bytes(issuer)))
+            });
+        if let Some(key) = revoked {
+            log::warn!("ignoring the signature by revoked key {}", key.fingerprint);
+        }
+
+        for key in &self.keys {
+            let sums = Cursor::new(sums.as_bytes());
+            if let Err(e) = gpgrv::verify_detached(Cursor::new(sig), sums, &key.keyring) {
+                log::debug!("not signed by {}: {}", key.fingerprint, e);
+                continue;
+            }
+
+            let key_ids = key.keyring.key_ids();
+            let created = signatures
+                .iter()
+                .filter(|signature| {
+                    signature
+                        .issuer
+                        .is_some_and(|value| key_ids.contains(&u64::from_be_bytes(value)))
+                })
+                .find_map(creation_time);
+            key.check_window(created)?;
+
+            return Ok(key.fingerprint.as_str());
+        }
+
+        if let Some(key) = revoked {
+            anyhow::bail!("signed by revoked key {}", key.fingerprint);
+        }
+
+        let issuers: Vec<String> = signatures
+            .iter()
+            .filter_map(|signature| signature.issuer)
+            .map(hex::encode_upper)
+            .collect();
+        if let Some(value) = &self.require_fingerprint {
+            anyhow::bail!(
+                "not signed by the required key {}, signature issuers: {:?}",
+                value,
+                issuers
+            );
+        }
+        anyhow::bail!(
+            "not signed by a trusted key, signature issuers: {:?}",
+            issuers
+        );
+    }
+}
 
+impl TrustedKey {
+    /// Check that a signature made at `created` falls in the validity window of this key.
+    fn check_window(&self, created: Option<DateTime<Utc>>) -> anyhow::Result<()> {
+        if self.valid_from.is_none() && self.valid_until.is_none() {
+            return Ok(());
+        }
+        let created = if let Some(value) = created {
+            value
+        } else {
+            anyhow::bail!(
+                "signature by {} has no creation time, cannot check it against the validity of the key",
+                self.fingerprint
+            );
+        };
+
+        if let Some(value) = self.valid_from.filter(|value| created < *value) {
+            anyhow::bail!(
+                "signature by {} was made at {}, before the key became valid at {}",
+                self.fingerprint,
+                created,
+                value
+            );
+        }
+        if let Some(value) = self.valid_until.filter(|value| created > *value) {
+            anyhow::bail!(
+                "signature by {} was made at {}, after the key stopped being valid at {}",
+                self.fingerprint,
+                created,
+                value
+            );
+        }
         Ok(())
     }
 }
 
+/// Load an embedded armoured key, checking that it has the expected fingerprint.
+fn embedded_keyring(asset: &str, armoured: &[u8], fingerprint: &str) -> anyhow::Result<Keyring> {
+    load_keys(armoured)
+        .map_err(|e| anyhow::anyhow!("bad embedded gpg key {}: {}", asset, e))?
+        .into_iter()
+        .find(|(value, _)| value == fingerprint)
+        .map(|(_, keyring)| keyring)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "embedded gpg key {} does not have fingerprint {}",
+                asset,
+                fingerprint
+            )
+        })
+}
+
+/// Load the keys from armoured public key blocks.
+///
+/// Every primary key gets its own keyring holding it and its subkeys, so it is known which key
+/// verified a signature. Returns the fingerprints of the primary keys with their keyrings.
+///
+/// Fails if the keys cannot be parsed or there are no keys.
+fn load_keys(armoured: &[u8]) -> anyhow::Result<Vec<(String, Keyring)>> {
+    let data = unarmour(armoured)?;
+
+    let mut keys = Vec::new();
+    for (fingerprint, packets) in split_keys(data.as_slice())? {
+        let mut keyring = Keyring::new();
+        if keyring.append_keys_from(packets)? == 0 {
+            anyhow::bail!("no usable keys in {}", fingerprint);
+        }
+        keys.push((fingerprint, keyring));
+    }
+    if keys.is_empty() {
+        anyhow::bail!("no keys found");
+    }
+
+    Ok(keys)
+}
+
+/// Decode armoured public key blocks.
+///
+/// https://tools.ietf.org/html/rfc4880#section-6.2
+fn unarmour(armoured: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let armoured = std::str::from_utf8(armoured)?;
+
+    let mut data = Vec::new();
+    let mut blocks = 0;
+    let mut lines = armoured.lines().map(|line| line.trim());
+    while let Some(line) = lines.next() {
+        if line != "-----BEGIN PGP PUBLIC KEY BLOCK-----" {
+            continue;
+        }
+        // Armour headers end with an empty line.
+        for line in lines.by_ref() {
+            if line.is_empty() {
+                break;
+            }
+        }
+        let mut encoded = String::new();
+        let mut checksum = None;
+        for line in lines.by_ref() {
+            if let Some(value) = line.strip_prefix('=') {
+                checksum = Some(value);
+                continue;
+            }
+            if line.starts_with("-----") {
+                break;
+            }
+            encoded.push_str(line);
+        }
+        let decoded = base64::decode(encoded.as_str())?;
+        if let Some(value) = checksum {
+            let expected = base64::decode(value)
+                .map_err(|e| anyhow::anyhow!("bad armour checksum {}: {}", value, e))?;
+            let actual = crc24(decoded.as_slice()).to_be_bytes();
+            if expected.as_slice() != &actual[1..] {
+                anyhow::bail!(
+                    "armour checksum mismatch: expected {}, got {}",
+                    hex::encode_upper(expected),
+                    hex::encode_upper(&actual[1..])
+                );
+            }
+        }
+        data.extend(decoded);
+        blocks += 1;
+    }
+    if blocks == 0 {
+        anyhow::bail!("no armoured public key block found");
+    }
+
+    Ok(data)
+}
+
+/// Compute the CRC-24 checksum of armoured data.
+///
+/// https://tools.ietf.org/html/rfc4880#section-6.1
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0x00b7_04ce;
+    for byte in data {
+        crc ^= u32::from(*byte) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x0100_0000 != 0 {
+                crc ^= 0x0186_4cfb;
+            }
+        }
+    }
+    crc & 0x00ff_ffff
+}
+
+/// Split public key packets into the packets of each primary key.
+///
+/// Returns the fingerprint of every primary key with its packets.
+///
+/// https://tools.ietf.org/html/rfc4880#section-4.2
+fn split_keys(mut data: &[u8]) -> anyhow::Result<Vec<(String, &[u8])>> {
+    let all = data;
+    let mut keys: Vec<(String, usize)> = Vec::new();
+    while !data.is_empty() {
+        let offset = all.len() - data.len();
+        let (tag, header, len) = packet_header(data)?;
+        let body = data
+            .get(header..header + len)
+            .ok_or_else(|| anyhow::anyhow!("truncated packet at {}", offset))?;
+
+        if tag == 6 {
+            if body.first() != Some(&4) {
+                anyhow::bail!("not supported: version {:?} public keys", body.first());
+            }
+            let mut hasher = Sha1::new();
+            hasher.update([0x99]);
+            hasher.update((len as u16).to_be_bytes());
+            hasher.update(body);
+            keys.push((hex::encode_upper(hasher.finalize()), offset));
+        } else if keys.is_empty() {
+            anyhow::bail!("expected a public key packet, found tag {}", tag);
+        }
+
+        data = &data[header + len..];
+    }
+
+    let ends = keys
+        .iter()
+        .skip(1)
+        .map(|(_, start)| *start)
+        .chain(std::iter::once(all.len()))
+        .collect::<Vec<usize>>();
+    Ok(keys
+        .into_iter()
+        .zip(ends)
+        .map(|((fingerprint, start), end)| (fingerprint, &all[start..end]))
+        .collect())
+}
+
+/// Parse a packet header.
+///
+/// Returns the tag, the length of the header and the length of the body.
+fn packet_header(data: &[u8]) -> anyhow::Result<(u8, usize, usize)> {
+    let byte = |index: usize| {
+        data.get(index)
+            .map(|value| *value as usize)
+            .ok_or_else(|| anyhow::anyhow!("truncated packet header"))
+    };
+
+    let first = byte(0)? as u8;
+    if first & 0x80 == 0 {
+        anyhow::bail!("invalid packet tag {:#x}", first);
+    }
+    if first & 0x40 == 0 {
+        // old format
+        let tag = (first >> 2) & 0x0f;
+        Ok(match first & 0x03 {
+            0 => (tag, 2, byte(1)?),
+            1 => (tag, 3, (byte(1)? << 8) | byte(2)?),
+            2 => (
+                tag,
+                5,
+                (byte(1)? << 24) | (byte(2)? << 16) | (byte(3)? << 8) | byte(4)?,
+            ),
+            _ => (tag, 1, data.len() - 1),
+        })
+    } else {
+        // new format
+        let tag = first & 0x3f;
+        Ok(match byte(1)? {
+            len @ 0..=191 => (tag, 2, len),
+            len @ 192..=223 => (tag, 3, ((len - 192) << 8) + byte(2)? + 192),
+            255 => (
+                tag,
+                6,
+                (byte(2)? << 24) | (byte(3)? << 16) | (byte(4)? << 8) | byte(5)?,
+            ),
+            _ => anyhow::bail!("not supported: partial body lengths in keys"),
+        })
+    }
+}
+
+/// Normalize a fingerprint given by the user, e.g. `91A6 E7F8 ...` or `0x91a6e7f8...`.
+fn normalize_fingerprint(value: &str) -> anyhow::Result<String> {
+    let normalized: String = value
+        .trim_start_matches("0x")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase();
+    if normalized.len() != 40 || !normalized.chars().all(|c| c.is_ascii_hexdigit()) {
+        anyhow::bail!("bad fingerprint {}, expected 40 hex digits", value);
+    }
+    Ok(normalized)
+}
+
+/// Get the key id of a fingerprint, i.e. its last 8 bytes.
+fn key_id(fingerprint: &str) -> anyhow::Result<u64> {
+    let fingerprint = hex::decode(fingerprint.replace(' ', ""))?;
+    if fingerprint.len() != 20 {
+        anyhow::bail!("bad fingerprint length {}", fingerprint.len());
+    }
+    let mut id = [0u8; 8];
+    id.copy_from_slice(&fingerprint[12..]);
+    Ok(u64::from_be_bytes(id))
+}
+
+/// Parse a time in RFC 3339 format.
+fn parse_time(value: &str) -> anyhow::Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(value)?.with_timezone(&Utc))
+}
+
+/// Get the creation time of a signature.
+///
+/// https://tools.ietf.org/html/rfc4880#section-5.2
+fn creation_time(signature: &Signature) -> Option<DateTime<Utc>> {
+    let data = signature.authenticated_data.as_slice();
+    let seconds = if data.len() == 5 {
+        // v3: signature type and creation time
+        Some(u32::from_be_bytes([data[1], data[2], data[3], data[4]]))
+    } else if data.first() == Some(&4) {
+        // v4: the creation time is a hashed subpacket
+        let mut subpackets = data.get(6..)?;
+        let mut seconds = None;
+        while !subpackets.is_empty() {
+            let (header, len) = match subpackets[0] {
+                value @ 0..=191 => (1, usize::from(value)),
+                value @ 192..=254 => (
+                    2,
+                    ((usize::from(value) - 192) << 8) + usize::from(*subpackets.get(1)?) + 192,
+                ),
+                255 => (
+                    5,
+                    u32::from_be_bytes([
+                        *subpackets.get(1)?,
+                        *subpackets.get(2)?,
+                        *subpackets.get(3)?,
+                        *subpackets.get(4)?,
+                    ]) as usize,
+                ),
+            };
+            let body = subpackets.get(header..header + len)?;
+            if body.len() == 5 && body[0] & 0x7f == 2 {
+                seconds = Some(u32::from_be_bytes([body[1], body[2], body[3], body[4]]));
+            }
+            subpackets = &subpackets[header + len..];
+        }
+        seconds
+    } else {
+        None
+    }?;
+    Some(Utc.timestamp(i64::from(seconds), 0))
+}
+
 impl SumsChecker {
     /// Create a new SumsChecker from a SHA256SUMS file.
     pub fn new(sums_raw: &str, version: &str) -> anyhow::Result<Self> {
         let mut sums_opt: Option<Vec<u8>> = None;
         for line in sums_raw.split('\n') {
-            if line.len() == 0 {
+            if line.is_empty() {
                 log::debug!("empty line, skipping");
                 continue;
             }
@@ -115,16 +607,183 @@ impl SumsChecker {
         Ok(Self { sums })
     }
 
-    /// Check whether a file's digest matches the one provided.
-    pub fn check(&self, src: &[u8]) -> anyhow::Result<()> {
-        let sums = Sha256::digest(src);
-        if sums.as_slice() != self.sums {
+    /// Check whether a file's sha256 digest matches the one provided.
+    pub fn check(&self, digest: &[u8]) -> anyhow::Result<()> {
+        if digest != self.sums.as_slice() {
             anyhow::bail!(
                 "artifact digest {} does not match provided digest {}",
-                hex::encode(sums.as_slice()),
+                hex::encode(digest),
                 hex::encode(self.sums.as_slice())
             );
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::crc24;
+    use super::load_keys;
+    use super::packet_header;
+    use super::split_keys;
+    use super::unarmour;
+    use super::SigChecker;
+    use super::HASHICORP_KEYS;
+
+    use std::path::PathBuf;
+
+    /// Two test keys, exported together in a single armoured block.
+    const TEST_KEYS: &str = include_str!("../tests/data/test-keys.asc");
+    const ONE: &str = "774F6641A6175393C0B9222BC492A33B3836F0A5";
+    const TWO: &str = "EA18CA1046914B0882F74D11FB5351782C057F22";
+    /// The pre-2021 HashiCorp key.
+    const HASHICORP_KEY: &str = include_str!("../assets/security@hashicorp.com.key");
+    const HASHICORP: &str = "91A6E7F85D05C65630BEF18951852D87348FFC4C";
+
+    const SUMS: &str = include_str!("../tests/data/sums.txt");
+    /// A detached signature of the sums by the first test key.
+    const SIG_ONE: &[u8] = include_bytes!("../tests/data/sums.one.sig");
+    /// A detached signature of the sums by both test keys.
+    const SIG_BOTH: &[u8] = include_bytes!("../tests/data/sums.both.sig");
+
+    fn test_keyring() -> Vec<PathBuf> {
+        vec![PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/test-keys.asc")]
+    }
+
+    fn fingerprints(armoured: &str) -> Vec<String> {
+        load_keys(armoured.as_bytes())
+            .unwrap()
+            .into_iter()
+            .map(|(fingerprint, _)| fingerprint)
+            .collect()
+    }
+
+    #[test]
+    fn crc24_of_empty_data_is_the_initial_value() {
+        assert_eq!(crc24(&[]), 0x00b7_04ce);
+    }
+
+    #[test]
+    fn keys_in_one_block() {
+        assert_eq!(fingerprints(TEST_KEYS), vec![ONE, TWO]);
+    }
+
+    #[test]
+    fn keys_in_several_blocks() {
+        let armoured = format!("{}\n{}", HASHICORP_KEY, TEST_KEYS);
+        assert_eq!(fingerprints(armoured.as_str()), vec![HASHICORP, ONE, TWO]);
+    }
+
+    #[test]
+    fn bad_armour_checksum() {
+        let line = TEST_KEYS
+            .lines()
+            .find(|line| line.starts_with('='))
+            .unwrap();
+        let tampered = TEST_KEYS.replace(line, "=AAAA");
+        let e = unarmour(tampered.as_bytes()).unwrap_err();
+        assert!(e.to_string().starts_with("armour checksum mismatch"));
+    }
+
+    #[test]
+    fn bad_armour() {
+        assert!(unarmour(b"").is_err());
+        assert!(unarmour(b"not a key").is_err());
+        assert!(unarmour(
+            b"-----BEGIN PGP PUBLIC KEY BLOCK-----\n\n!!!!\n-----END PGP PUBLIC KEY BLOCK-----\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn new_format_headers() {
+        // one, two and five octet lengths
+        assert_eq!(packet_header(&[0xc6, 0x05]).unwrap(), (6, 2, 5));
+        assert_eq!(packet_header(&[0xc6, 0xc0, 0x00]).unwrap(), (6, 3, 192));
+        assert_eq!(packet_header(&[0xc6, 0xc5, 0xfb]).unwrap(), (6, 3, 1723));
+        assert_eq!(
+            packet_header(&[0xc6, 0xff, 0x00, 0x01, 0x00, 0x00]).unwrap(),
+            (6, 6, 65536)
+        );
+        assert_eq!(packet_header(&[0xcd, 0x10]).unwrap(), (13, 2, 16));
+    }
+
+    #[test]
+    fn old_format_headers() {
+        // one, two and four octet lengths, and an indeterminate length
+        assert_eq!(packet_header(&[0x98, 0x05]).unwrap(), (6, 2, 5));
+        assert_eq!(packet_header(&[0x99, 0x01, 0x0d]).unwrap(), (6, 3, 269));
+        assert_eq!(
+            packet_header(&[0x9a, 0x00, 0x01, 0x00, 0x00]).unwrap(),
+            (6, 5, 65536)
+        );
+        assert_eq!(packet_header(&[0x9b, 0x01, 0x02]).unwrap(), (6, 1, 2));
+        assert_eq!(packet_header(&[0xb4, 0x10]).unwrap(), (13, 2, 16));
+    }
+
+    #[test]
+    fn malformed_headers() {
+        assert!(packet_header(&[]).is_err());
+        // no packet tag bit
+        assert!(packet_header(&[0x06, 0x05]).is_err());
+        // truncated lengths
+        assert!(packet_header(&[0xc6]).is_err());
+        assert!(packet_header(&[0xc6, 0xc0]).is_err());
+        assert!(packet_header(&[0xc6, 0xff, 0x00]).is_err());
+        assert!(packet_header(&[0x99, 0x01]).is_err());
+        // partial body length
+        assert!(packet_header(&[0xc6, 0xe0]).is_err());
+    }
+
+    #[test]
+    fn malformed_packets() {
+        // truncated body
+        assert!(split_keys(&[0xc6, 0x05, 0x04, 0x00]).is_err());
+        // user id before any public key
+        assert!(split_keys(&[0xcd, 0x01, 0x41]).is_err());
+        // version 3 public key
+        assert!(split_keys(&[0xc6, 0x01, 0x03]).is_err());
+    }
+
+    #[test]
+    fn signature_by_a_keyring_key() {
+        let checker = SigChecker::new(test_keyring().as_slice(), None).unwrap();
+        assert_eq!(checker.check(SIG_ONE, SUMS).unwrap(), ONE);
+    }
+
+    #[test]
+    fn required_key_among_several_signatures() {
+        let checker = SigChecker::new(test_keyring().as_slice(), Some(TWO)).unwrap();
+        assert_eq!(checker.check(SIG_BOTH, SUMS).unwrap(), TWO);
+    }
+
+    #[test]
+    fn required_key_did_not_sign() {
+        let checker = SigChecker::new(test_keyring().as_slice(), Some(TWO)).unwrap();
+        let e = checker.check(SIG_ONE, SUMS).unwrap_err();
+        assert!(e.to_string().starts_with("not signed by the required key"));
+    }
+
+    #[test]
+    fn required_key_is_not_trusted() {
+        assert!(SigChecker::new(&[], Some(ONE)).is_err());
+    }
+
+    #[test]
+    fn embedded_keys_are_trusted() {
+        let checker = SigChecker::new(&[], None).unwrap();
+        assert!(!checker.keys.is_empty());
+        for key in &checker.keys {
+            assert!(HASHICORP_KEYS
+                .iter()
+                .any(|known| known.fingerprint == key.fingerprint && !known.revoked));
+        }
+    }
+
+    #[test]
+    fn tampered_sums() {
+        let checker = SigChecker::new(test_keyring().as_slice(), None).unwrap();
+        let sums = SUMS.replace("0123", "3210");
+        assert!(checker.check(SIG_ONE, sums.as_str()).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+//! Module for the install manifest.
+//!
+//! The manifest records every nomad installed by nomadutil: its version, where the release came
+//! from, the digests of the zip archive and the binary, how the release was verified and the
+//! files that were written. It is rewritten atomically by every command that changes an
+//! installation and is the source of truth for the commands that inspect one.
+
+use crate::artifacts::sha256_file;
+use crate::common::install_file;
+use crate::releases::NomadBinary;
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use chrono::DateTime;
+use chrono::Utc;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Default path of the install manifest.
+pub const DEFAULT_STATE_FILE: &str = "/var/lib/nomadutil/state.json";
+
+/// The install manifest.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct State {
+    #[serde(default)]
+    installs: Vec<Install>,
+}
+
+impl State {
+    /// Load the manifest from `path`.
+    ///
+    /// A missing manifest is an empty one.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            log::debug!("install manifest {} not found", path.display());
+            return Ok(Self::default());
+        }
+        let contents = fs::read(path)
+            .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+        serde_json::from_slice(contents.as_slice())
+            .map_err(|e| anyhow::anyhow!("bad install manifest {}: {}", path.display(), e))
+    }
+
+    /// Atomically write the manifest to `path`.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut contents = serde_json::to_vec_pretty(self)?;
+        contents.push(b'\n');
+        install_file(path, contents.as_slice(), 0o644, None)?;
+        log::debug!("install manifest {} updated", path.display());
+        Ok(())
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn installs(&self) -> &[Install] {
+        self.installs.as_slice()
+    }
+
+    /// Get the installation whose nomad binary is at `binary`.
+    pub fn get(&self, binary: &Path) -> Option<&Install> {
+        self.installs
+            .iter()
+            .find(|install| install.binary == binary)
+    }
+
+    /// Record an installation, replacing the one with the same nomad binary path.
+    pub fn record(&mut self, install: Install) {
+        self.remove(&install.binary);
+        self.installs.push(install);
+    }
+
+    /// Forget the installation whose nomad binary is at `binary`.
+    pub fn remove(&mut self, binary: &Path) -> Option<Install> {
+        let index = self
+            .installs
+            .iter()
+            .position(|install| install.binary == binary)?;
+        Some(self.installs.remove(index))
+    }
+}
+
+/// An installation of nomad.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Install {
+    /// where the nomad binary is run from, a symlink in the versioned install layout
+    binary: PathBuf,
+    version: String,
+    installed_at: DateTime<Utc>,
+    /// where the zip archive came from, None if unknown
+    source: Option<String>,
+    /// hex sha256 digest of the zip archive, None if unknown
+    zip_sha256: Option<String>,
+    /// hex sha256 digest of the nomad binary
+    binary_sha256: String,
+    /// whether the zip archive was checked against the sha256sums
+    checked_sums: bool,
+    /// fingerprint of the key that signed the sha256sums, if the signature was checked
+    signed_by: Option<String>,
+    /// whether the versioned install layout is used
+    versioned: bool,
+    /// the systemd service file
+    service_file: Option<PathBuf>,
+    /// every file written for this installation
+    files: Vec<PathBuf>,
+}
+
+impl Install {
+    /// Describe an installation of `release` as `version`, run from `binary`.
+    pub fn new(
+        binary: PathBuf,
+        version: &str,
+        release: &NomadBinary,
+        versioned: bool,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            binary,
+            version: version.to_string(),
+            installed_at: Utc::now(),
+            source: Some(release.source().to_string()),
+            zip_sha256: Some(hex::encode(release.zip_digest())),
+            binary_sha256: hex::encode(sha256_file(release.path())?),
+            checked_sums: release.checked_sums(),
+            signed_by: release.signed_by().map(|value| value.to_string()),
+            versioned,
+            service_file: None,
+            files: Vec::new(),
+        })
+    }
+
+    /// Set the systemd service file.
+    #[inline]
+    pub fn with_service_file(mut self, service_file: PathBuf) -> Self {
+        self.service_file = Some(service_file);
+        self
+    }
+
+    /// Add files written for this installation, skipping the ones already recorded.
+    pub fn with_files<I: IntoIterator<Item = PathBuf>>(mut self, files: I) -> Self {
+        for file in files {
+            if !self.files.contains(&file) {
+                self.files.push(file);
+            }
+        }
+        self
+    }
+
+    /// Switch to another version already installed in the versioned install layout.
+    ///
+    /// How that version was obtained is not known, so the release details are cleared. The
+    /// installation uses the versioned install layout from now on, even if it did not before.
+    pub fn switch(&mut self, version: &str, binary: &Path) -> anyhow::Result<()> {
+        self.version = version.to_string();
+        self.installed_at = Utc::now();
+        self.source = None;
+        self.zip_sha256 = None;
+        self.binary_sha256 = hex::encode(sha256_file(binary)?);
+        self.checked_sums = false;
+        self.signed_by = None;
+        self.versioned = true;
+        if !self.files.iter().any(|file| file == binary) {
+            self.files.push(binary.to_path_buf());
+        }
+        Ok(())
+    }
+
+    /// Check whether the binary at `path` is the one that was installed.
+    pub fn binary_matches(&self, path: &Path) -> anyhow::Result<bool> {
+        Ok(hex::encode(sha256_file(path)?) == self.binary_sha256)
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn binary(&self) -> &Path {
+        self.binary.as_path()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn version(&self) -> &str {
+        self.version.as_str()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn installed_at(&self) -> &DateTime<Utc> {
+        &self.installed_at
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn zip_sha256(&self) -> Option<&str> {
+        self.zip_sha256.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn binary_sha256(&self) -> &str {
+        self.binary_sha256.as_str()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn checked_sums(&self) -> bool {
+        self.checked_sums
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn signed_by(&self) -> Option<&str> {
+        self.signed_by.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn versioned(&self) -> bool {
+        self.versioned
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn service_file(&self) -> Option<&Path> {
+        self.service_file.as_deref()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn files(&self) -> &[PathBuf] {
+        self.files.as_slice()
+    }
+}
@@ -0,0 +1,525 @@
+//! Module for interacting with systemd.
+//!
+//! Units are managed through the Systemd trait, so commands do not depend on how systemd is
+//! reached and tests can fake it. Systemctl implements it with the `systemctl` and `journalctl`
+//! commands.
+
+#[cfg(test)]
+use std::cell::RefCell;
+#[cfg(test)]
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Number of journal lines shown when a unit fails.
+pub const JOURNAL_LINES: usize = 20;
+/// How long a started unit has to stay up before it is considered started.
+const STARTUP_GRACE: Duration = Duration::from_secs(3);
+
+/// The state of a unit.
+#[derive(Clone, Debug)]
+pub struct UnitState {
+    /// e.g. active, inactive or failed
+    active: String,
+    /// e.g. running, dead or failed
+    sub: String,
+    /// e.g. enabled or disabled
+    unit_file: String,
+}
+
+impl UnitState {
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn active(&self) -> &str {
+        self.active.as_str()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn sub(&self) -> &str {
+        self.sub.as_str()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn unit_file(&self) -> &str {
+        self.unit_file.as_str()
+    }
+
+    /// Whether the unit is running.
+    #[inline]
+    pub fn is_active(&self) -> bool {
+        self.active == "active"
+    }
+
+    /// Whether the unit has failed, or is waiting to be restarted after failing.
+    #[inline]
+    pub fn is_failed(&self) -> bool {
+        self.active == "failed" || self.sub == "auto-restart"
+    }
+
+    /// Whether the unit starts at boot.
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.unit_file == "enabled"
+    }
+}
+
+impl fmt::Display for UnitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}), {}", self.active, self.sub, self.unit_file)
+    }
+}
+
+/// Operations on systemd units.
+pub trait Systemd {
+    /// Reload the unit files.
+    fn daemon_reload(&self) -> anyhow::Result<()>;
+    /// Run a unit action such as start, stop, restart, enable or disable.
+    fn unit_action(&self, action: &str, unit: &str) -> anyhow::Result<()>;
+    /// Get the state of a unit.
+    fn state(&self, unit: &str) -> anyhow::Result<UnitState>;
+    /// Get the last `lines` journal lines of a unit.
+    fn journal(&self, unit: &str, lines: usize) -> anyhow::Result<Vec<String>>;
+
+    /// How long a started unit has to stay up before it is considered started.
+    fn startup_grace(&self) -> Duration {
+        STARTUP_GRACE
+    }
+
+    /// Start a unit, or restart it if it is running, and check that it stays up.
+    ///
+    /// If the unit fails, the error includes its last journal lines.
+    fn start_checked(&self, unit: &str) -> anyhow::Result<UnitState> {
+        let action = if self.state(unit)?.is_active() {
+            "restart"
+        } else {
+            "start"
+        };
+        if let Err(e) = self.unit_action(action, unit) {
+            return Err(self.with_journal(unit, e));
+        }
+
+        let grace = self.startup_grace();
+        let start = Instant::now();
+        loop {
+            let state = self.state(unit)?;
+            if state.is_failed() || (!state.is_active() && state.active != "activating") {
+                let e = anyhow::anyhow!("{} did not stay up: {}", unit, state);
+                return Err(self.with_journal(unit, e));
+            }
+            if start.elapsed() >= grace && state.is_active() {
+                return Ok(state);
+            }
+            if start.elapsed() >= grace * 10 {
+                anyhow::bail!("{} is still {}", unit, state);
+            }
+            thread::sleep(grace / 6);
+        }
+    }
+
+    /// Add the last journal lines of a unit to an error.
+    fn with_journal(&self, unit: &str, e: anyhow::Error) -> anyhow::Error {
+        match self.journal(unit, JOURNAL_LINES) {
+            Ok(lines) if !lines.is_empty() => {
+                anyhow::anyhow!(
+                    "{}\nlast journal lines of {}:\n{}",
+                    e,
+                    unit,
+                    lines.join("\n")
+                )
+            }
+            Ok(_) => e,
+            Err(journal_e) => {
+                log::debug!("failed to read the journal of {}: {}", unit, journal_e);
+                e
+            }
+        }
+    }
+}
+
+/// Systemd reached through the `systemctl` and `journalctl` commands.
+pub struct Systemctl;
+
+impl Systemd for Systemctl {
+    fn daemon_reload(&self) -> anyhow::Result<()> {
+        systemctl(&["daemon-reload"])
+    }
+
+    fn unit_action(&self, action: &str, unit: &str) -> anyhow::Result<()> {
+        systemctl(&[action, unit])
+    }
+
+    fn state(&self, unit: &str) -> anyhow::Result<UnitState> {
+        log::debug!("running systemctl show {}", unit);
+        let output = Command::new("systemctl")
+            .args([
+                "show",
+                "--property=ActiveState,SubState,UnitFileState",
+                unit,
+            ])
+            .stderr(Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            anyhow::bail!("systemctl show {} exited with {}", unit, output.status);
+        }
+
+        let mut state = UnitState {
+            active: String::new(),
+            sub: String::new(),
+            unit_file: String::new(),
+        };
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            match line.split_once('=') {
+                Some(("ActiveState", value)) => state.active = value.to_string(),
+                Some(("SubState", value)) => state.sub = value.to_string(),
+                Some(("UnitFileState", value)) => state.unit_file = value.to_string(),
+                _ => {}
+            }
+        }
+        Ok(state)
+    }
+
+    fn journal(&self, unit: &str, lines: usize) -> anyhow::Result<Vec<String>> {
+        let lines = lines.to_string();
+        log::debug!("running journalctl -u {} -n {}", unit, lines);
+        let output = Command::new("journalctl")
+            .args(["--unit", unit, "--lines", lines.as_str(), "--no-pager"])
+            .stderr(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            anyhow::bail!("journalctl exited with {}", output.status);
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.to_string())
+            .collect())
+    }
+}
+
+/// Systemd faked for tests.
+///
+/// Units go through a scripted list of states, the last of which sticks, and every action is
+/// recorded.
+#[cfg(test)]
+pub struct FakeSystemd {
+    /// states returned by `state`, in order
+    states: RefCell<VecDeque<UnitState>>,
+    /// actions run so far, as `<action> <unit>`
+    actions: RefCell<Vec<String>>,
+    /// actions that fail, as `<action> <unit>`
+    failing: Vec<String>,
+    journal: Vec<String>,
+}
+
+#[cfg(test)]
+impl FakeSystemd {
+    /// Create a FakeSystemd going through `states`, given as (active, sub) pairs.
+    pub fn new(states: &[(&str, &str)]) -> Self {
+        Self {
+            states: RefCell::new(
+                states
+                    .iter()
+                    .map(|(active, sub)| UnitState {
+                        active: active.to_string(),
+                        sub: sub.to_string(),
+                        unit_file: "enabled".to_string(),
+                    })
+                    .collect(),
+            ),
+            actions: RefCell::new(Vec::new()),
+            failing: Vec::new(),
+            journal: Vec::new(),
+        }
+    }
+
+    /// Make an action, such as `restart nomad.service`, fail.
+    pub fn failing(mut self, action: &str) -> Self {
+        self.failing.push(action.to_string());
+        self
+    }
+
+    /// Set the journal lines of every unit.
+    pub fn with_journal_lines(mut self, lines: &[&str]) -> Self {
+        self.journal = lines.iter().map(|line| line.to_string()).collect();
+        self
+    }
+
+    /// Get the actions run so far.
+    pub fn actions(&self) -> Vec<String> {
+        self.actions.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+impl Systemd for FakeSystemd {
+    fn daemon_reload(&self) -> anyhow::Result<()> {
+        self.actions.borrow_mut().push("daemon-reload".to_string());
+        Ok(())
+    }
+
+    fn unit_action(&self, action: &str, unit: &str) -> anyhow::Result<()> {
+        let action = format!("{} {}", action, unit);
+        self.actions.borrow_mut().push(action.clone());
+        if self.failing.contains(&action) {
+            anyhow::bail!("systemctl {} exited with exit status: 1", action);
+        }
+        Ok(())
+    }
+
+    fn state(&self, _unit: &str) -> anyhow::Result<UnitState> {
+        let mut states = self.states.borrow_mut();
+        let state = states
+            .front()
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("no unit state scripted"))?;
+        if states.len() > 1 {
+            states.pop_front();
+        }
+        Ok(state)
+    }
+
+    fn journal(&self, _unit: &str, lines: usize) -> anyhow::Result<Vec<String>> {
+        Ok(self.journal.iter().take(lines).cloned().collect())
+    }
+
+    fn startup_grace(&self) -> Duration {
+        Duration::from_millis(60)
+    }
+}
+
+/// Get the unit name of a service file.
+pub fn unit_name(service_file: &Path) -> anyhow::Result<&str> {
+    service_file
+        .file_name()
+        .and_then(|value| value.to_str())
+        .ok_or_else(|| anyhow::anyhow!("bad service file path {}", service_file.display()))
+}
+
+/// Run systemctl with the given arguments, failing if it does not exit successfully.
+fn systemctl(args: &[&str]) -> anyhow::Result<()> {
+    log::debug!("running systemctl {}", args.join(" "));
+    let status = Command::new("systemctl").args(args).status()?;
+    if !status.success() {
+        anyhow::bail!("systemctl {} exited with {}", args.join(" "), status);
+    }
+    Ok(())
+}
+
+/// The built-in template of the nomad service file.
+pub const DEFAULT_SERVICE_TEMPLATE: &str = include_str!("../assets/nomad.service");
+
+/// Variables available to service file templates.
+#[derive(Clone, Debug)]
+pub struct UnitVars {
+    /// path of the nomad binary
+    binary: Option<PathBuf>,
+    /// the nomad configuration directory
+    config_dir: PathBuf,
+    /// the nomad data directory
+    data_dir: PathBuf,
+    user: Option<String>,
+    group: Option<String>,
+    /// file with environment variables for the agent, e.g. tokens
+    environment_file: Option<PathBuf>,
+    /// extra arguments for `nomad agent`
+    agent_args: String,
+    limit_nofile: String,
+    limit_nproc: String,
+}
+
+impl UnitVars {
+    /// Create new UnitVars.
+    pub fn new(config_dir: PathBuf, data_dir: PathBuf) -> Self {
+        Self {
+            binary: None,
+            config_dir,
+            data_dir,
+            user: None,
+            group: None,
+            environment_file: None,
+            agent_args: String::new(),
+            limit_nofile: "infinity".to_string(),
+            limit_nproc: "infinity".to_string(),
+        }
+    }
+
+    /// Set the path of the nomad binary.
+    #[inline]
+    pub fn with_binary(mut self, binary: PathBuf) -> Self {
+        self.binary = Some(binary);
+        self
+    }
+
+    /// Set the user and group the agent runs as.
+    #[inline]
+    pub fn with_user(mut self, user: Option<String>, group: Option<String>) -> Self {
+        self.user = user;
+        self.group = group;
+        self
+    }
+
+    /// Set the environment file of the agent.
+    #[inline]
+    pub fn with_environment_file(mut self, environment_file: Option<PathBuf>) -> Self {
+        self.environment_file = environment_file;
+        self
+    }
+
+    /// Set extra arguments for `nomad agent`.
+    #[inline]
+    pub fn with_agent_args(mut self, agent_args: Option<String>) -> Self {
+        self.agent_args = agent_args.unwrap_or_default();
+        self
+    }
+
+    /// Set the LimitNOFILE and LimitNPROC of the agent, keeping the defaults for None.
+    #[inline]
+    pub fn with_limits(mut self, nofile: Option<String>, nproc: Option<String>) -> Self {
+        if let Some(value) = nofile {
+            self.limit_nofile = value;
+        }
+        if let Some(value) = nproc {
+            self.limit_nproc = value;
+        }
+        self
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn config_dir(&self) -> &Path {
+        self.config_dir.as_path()
+    }
+
+    /// Get the value of a variable, None if it is not set.
+    fn get(&self, name: &str) -> anyhow::Result<Option<String>> {
+        let path = |value: &Path| value.display().to_string();
+        Ok(match name {
+            "binary" => self.binary.as_deref().map(path),
+            "config_dir" => Some(path(&self.config_dir)),
+            "data_dir" => Some(path(&self.data_dir)),
+            "user" => self.user.clone(),
+            "group" => self.group.clone(),
+            "environment_file" => self.environment_file.as_deref().map(path),
+            "agent_args" => Some(self.agent_args.clone()),
+            "limit_nofile" => Some(self.limit_nofile.clone()),
+            "limit_nproc" => Some(self.limit_nproc.clone()),
+            _ => anyhow::bail!("unknown variable {}", name),
+        })
+    }
+}
+
+/// Render a service file template.
+///
+/// `{{ name }}` is replaced by the value of the variable `name`. Lines using a variable that is
+/// not set, such as `User={{ user }}` when no user is given, are left out, and trailing
+/// whitespace is trimmed.
+pub fn render_unit(template: &str, vars: &UnitVars) -> anyhow::Result<String> {
+    let mut out = String::with_capacity(template.len());
+    'lines: for (index, line) in template.lines().enumerate() {
+        let mut rendered = String::with_capacity(line.len());
+        let mut rest = line;
+        while let Some(start) = rest.find("{{") {
+            rendered.push_str(&rest[..start]);
+            let end = rest[start..].find("}}").ok_or_else(|| {
+                anyhow::anyhow!("line {}: unterminated {{{{ in the template", index + 1)
+            })? + start;
+            let name = rest[start + 2..end].trim();
+            match vars
+                .get(name)
+                .map_err(|e| anyhow::anyhow!("line {}: {}", index + 1, e))?
+            {
+                Some(value) => rendered.push_str(value.as_str()),
+                None => continue 'lines,
+            }
+            rest = &rest[end + 2..];
+        }
+        rendered.push_str(rest);
+        out.push_str(rendered.trim_end());
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FakeSystemd;
+    use super::Systemd;
+
+    const UNIT: &str = "nomad.service";
+
+    #[test]
+    fn start_checked_starts_an_inactive_unit() {
+        let systemd = FakeSystemd::new(&[("inactive", "dead"), ("active", "running")]);
+        let state = systemd.start_checked(UNIT).unwrap();
+        assert!(state.is_active());
+        assert_eq!(systemd.actions(), vec!["start nomad.service"]);
+    }
+
+    #[test]
+    fn start_checked_restarts_an_active_unit() {
+        let systemd = FakeSystemd::new(&[("active", "running")]);
+        systemd.start_checked(UNIT).unwrap();
+        assert_eq!(systemd.actions(), vec!["restart nomad.service"]);
+    }
+
+    #[test]
+    fn start_checked_waits_while_activating() {
+        let systemd = FakeSystemd::new(&[
+            ("inactive", "dead"),
+            ("activating", "start"),
+            ("activating", "start"),
+            ("active", "running"),
+        ]);
+        assert!(systemd.start_checked(UNIT).unwrap().is_active());
+    }
+
+    #[test]
+    fn start_checked_fails_with_the_journal() {
+        let systemd = FakeSystemd::new(&[("inactive", "dead"), ("failed", "failed")])
+            .with_journal_lines(&["nomad: bad config"]);
+        let e = systemd.start_checked(UNIT).unwrap_err().to_string();
+        assert!(e.starts_with("nomad.service did not stay up: failed (failed)"));
+        assert!(e.ends_with("last journal lines of nomad.service:\nnomad: bad config"));
+    }
+
+    #[test]
+    fn start_checked_fails_when_the_unit_restarts() {
+        let systemd = FakeSystemd::new(&[
+            ("inactive", "dead"),
+            ("active", "running"),
+            ("activating", "auto-restart"),
+        ]);
+        assert!(systemd.start_checked(UNIT).is_err());
+    }
+
+    #[test]
+    fn start_checked_gives_up_on_a_unit_stuck_activating() {
+        let systemd = FakeSystemd::new(&[("inactive", "dead"), ("activating", "start")]);
+        let e = systemd.start_checked(UNIT).unwrap_err().to_string();
+        assert_eq!(e, "nomad.service is still activating (start), enabled");
+    }
+
+    #[test]
+    fn start_checked_surfaces_failed_actions_with_the_journal() {
+        let systemd = FakeSystemd::new(&[("inactive", "dead")])
+            .failing("start nomad.service")
+            .with_journal_lines(&["nomad: permission denied"]);
+        let e = systemd.start_checked(UNIT).unwrap_err().to_string();
+        assert!(e.starts_with("systemctl start nomad.service exited"));
+        assert!(e.ends_with("nomad: permission denied"));
+    }
+
+    #[test]
+    fn with_journal_keeps_errors_without_journal_lines() {
+        let systemd = FakeSystemd::new(&[]);
+        let e = systemd.with_journal(UNIT, anyhow::anyhow!("failed"));
+        assert_eq!(e.to_string(), "failed");
+    }
+}
@@ -0,0 +1,328 @@
+//! Module for generating the TLS certificates of a nomad cluster.
+//!
+//! Certificates and file names follow `nomad tls`: an ECDSA CA and agent certificates whose
+//! SANs include the `<role>.<region>.nomad` names the agents verify each other against.
+
+use std::net::IpAddr;
+
+use chrono::Datelike;
+use chrono::Duration;
+use chrono::Utc;
+
+use rcgen::date_time_ymd;
+use rcgen::BasicConstraints;
+use rcgen::Certificate;
+use rcgen::CertificateParams;
+use rcgen::DistinguishedName;
+use rcgen::DnType;
+use rcgen::ExtendedKeyUsagePurpose;
+use rcgen::IsCa;
+use rcgen::KeyPair;
+use rcgen::KeyUsagePurpose;
+use rcgen::SanType;
+use rcgen::PKCS_ECDSA_P256_SHA256;
+
+/// File name of the CA certificate.
+pub const CA_FILE: &str = "nomad-agent-ca.pem";
+/// File name of the CA key.
+pub const CA_KEY_FILE: &str = "nomad-agent-ca-key.pem";
+/// Default validity of the CA, in days.
+pub const DEFAULT_CA_DAYS: u32 = 1825;
+/// Default validity of the agent and cli certificates, in days.
+pub const DEFAULT_CERT_DAYS: u32 = 365;
+
+/// What a certificate is for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CertRole {
+    Server,
+    Client,
+    /// the nomad cli and other api clients
+    Cli,
+}
+
+impl CertRole {
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Server => "server",
+            Self::Client => "client",
+            Self::Cli => "cli",
+        }
+    }
+
+    /// Get the file names of the certificate and the key for `region`.
+    pub fn file_names(self, region: &str) -> (String, String) {
+        (
+            format!("{}-{}-nomad.pem", region, self.name()),
+            format!("{}-{}-nomad-key.pem", region, self.name()),
+        )
+    }
+}
+
+/// A certificate and its key, PEM encoded.
+#[derive(Clone, Debug)]
+pub struct PemPair {
+    cert: String,
+    key: String,
+}
+
+impl PemPair {
+    /// Create a new PemPair.
+    pub fn new(cert: String, key: String) -> Self {
+        Self { cert, key }
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn cert(&self) -> &str {
+        self.cert.as_str()
+    }
+
+    #[allow(missing_docs, dead_code)]
+    #[inline]
+    pub fn key(&self) -> &str {
+        self.key.as_str()
+    }
+}
+
+/// Create a CA valid for `days` days.
+pub fn create_ca(days: u32) -> anyhow::Result<PemPair> {
+    let mut params = base_params(days);
+    let mut name = DistinguishedName::new();
+    name.push(
+        DnType::CommonName,
+        format!("Nomad Agent CA {}", fastrand::u64(..)),
+    );
+    params.distinguished_name = name;
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![
+        KeyUsagePurpose::DigitalSignature,
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+    ];
+
+    let cert = Certificate::from_params(params)?;
+    Ok(PemPair::new(
+        cert.serialize_pem()?,
+        cert.serialize_private_key_pem(),
+    ))
+}
+
+/// Create a certificate for `role` in `region`, signed by `ca` and valid for `days` days.
+///
+/// The SANs are `<role>.<region>.nomad` and `localhost`, `127.0.0.1` for agents, and the extra
+/// `dns_names` and `ip_addresses`.
+pub fn create_cert(
+    ca: &PemPair,
+    role: CertRole,
+    region: &str,
+    dns_names: &[String],
+    ip_addresses: &[IpAddr],
+    days: u32,
+) -> anyhow::Result<PemPair> {
+    let ca_key = KeyPair::from_pem(ca.key()).map_err(|e| anyhow::anyhow!("bad CA key: {}", e))?;
+    let ca_params = CertificateParams::from_ca_cert_pem(ca.cert(), ca_key)
+        .map_err(|e| anyhow::anyhow!("bad CA certificate: {}", e))?;
+    let ca = Certificate::from_params(ca_params)?;
+
+    let common_name = format!("{}.{}.nomad", role.name(), region);
+    let mut params = base_params(days);
+    let mut name = DistinguishedName::new();
+    name.push(DnType::CommonName, common_name.as_str());
+    params.distinguished_name = name;
+
+    params.subject_alt_names = vec![
+        SanType::DnsName(common_name),
+        SanType::DnsName("localhost".to_string()),
+    ];
+    if role != CertRole::Cli {
+        params
+            .subject_alt_names
+            .push(SanType::IpAddress(IpAddr::from([127, 0, 0, 1])));
+    }
+    params.subject_alt_names.extend(
+        dns_names
+            .iter()
+            .map(|value| SanType::DnsName(value.to_string())),
+    );
+    params
+        .subject_alt_names
+        .extend(ip_addresses.iter().map(|value| SanType::IpAddress(*value)));
+
+    params.key_usages = vec![
+        KeyUsagePurpose::DigitalSignature,
+        KeyUsagePurpose::KeyEncipherment,
+    ];
+    // Agents serve the api and rpc and connect to each other, the cli only connects.
+    params.extended_key_usages = match role {
+        CertRole::Cli => vec![ExtendedKeyUsagePurpose::ClientAuth],
+        _ => vec![
+            ExtendedKeyUsagePurpose::ServerAuth,
+            ExtendedKeyUsagePurpose::ClientAuth,
+        ],
+    };
+
+    let cert = Certificate::from_params(params)?;
+    Ok(PemPair::new(
+        cert.serialize_pem_with_signer(&ca)?,
+        cert.serialize_private_key_pem(),
+    ))
+}
+
+/// Get the parameters shared by every certificate: an ECDSA P-256 key, a random serial number
+/// and a validity from today for `days` days.
+fn base_params(days: u32) -> CertificateParams {
+    let now = Utc::now();
+    let until = now + Duration::days(i64::from(days));
+
+    let mut params = CertificateParams::default();
+    params.alg = &PKCS_ECDSA_P256_SHA256;
+    params.serial_number = Some(fastrand::u64(..).into());
+    params.not_before = date_time_ymd(now.year(), now.month() as u8, now.day() as u8);
+    params.not_after = date_time_ymd(until.year(), until.month() as u8, until.day() as u8);
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::create_ca;
+    use super::create_cert;
+    use super::CertRole;
+    use super::PemPair;
+
+    use std::convert::TryInto;
+    use std::net::IpAddr;
+
+    use x509_parser::extensions::GeneralName;
+    use x509_parser::pem::parse_x509_pem;
+    use x509_parser::pem::Pem;
+
+    fn pem(value: &str) -> Pem {
+        parse_x509_pem(value.as_bytes()).unwrap().1
+    }
+
+    /// Get the DNS names and the IP addresses of a certificate.
+    fn sans(cert: &str) -> (Vec<String>, Vec<IpAddr>) {
+        let pem = pem(cert);
+        let cert = pem.parse_x509().unwrap();
+        let mut dns_names = Vec::new();
+        let mut ip_addresses = Vec::new();
+        for name in &cert
+            .subject_alternative_name()
+            .unwrap()
+            .unwrap()
+            .value
+            .general_names
+        {
+            match name {
+                GeneralName::DNSName(value) => dns_names.push(value.to_string()),
+                GeneralName::IPAddress(value) => {
+                    let octets: [u8; 4] = (*value).try_into().unwrap();
+                    ip_addresses.push(IpAddr::from(octets));
+                }
+                _ => panic!("unexpected SAN {:?}", name),
+            }
+        }
+        (dns_names, ip_addresses)
+    }
+
+    /// Get the server auth and client auth EKUs of a certificate.
+    fn ekus(cert: &str) -> (bool, bool) {
+        let pem = pem(cert);
+        let cert = pem.parse_x509().unwrap();
+        let eku = cert.extended_key_usage().unwrap().unwrap().value;
+        (eku.server_auth, eku.client_auth)
+    }
+
+    fn cert(ca: &PemPair, role: CertRole) -> PemPair {
+        create_cert(ca, role, "eu", &[], &[], 30).unwrap()
+    }
+
+    #[test]
+    fn ca_is_a_ca() {
+        let ca = create_ca(30).unwrap();
+        let pem = pem(ca.cert());
+        let cert = pem.parse_x509().unwrap();
+        assert!(cert.is_ca());
+        cert.verify_signature(None).unwrap();
+        assert!(ca.key().contains("PRIVATE KEY"));
+    }
+
+    #[test]
+    fn certs_verify_against_the_ca() {
+        let ca = create_ca(30).unwrap();
+        let ca_pem = pem(ca.cert());
+        let ca_cert = ca_pem.parse_x509().unwrap();
+
+        for role in &[CertRole::Server, CertRole::Client, CertRole::Cli] {
+            let leaf = cert(&ca, *role);
+            let pem = pem(leaf.cert());
+            let cert = pem.parse_x509().unwrap();
+            assert!(!cert.is_ca());
+            assert_eq!(cert.issuer(), ca_cert.subject());
+            cert.verify_signature(Some(ca_cert.public_key())).unwrap();
+        }
+
+        let other = create_ca(30).unwrap();
+        let other_pem = pem(other.cert());
+        let leaf = cert(&ca, CertRole::Server);
+        let pem = pem(leaf.cert());
+        assert!(pem
+            .parse_x509()
+            .unwrap()
+            .verify_signature(Some(other_pem.parse_x509().unwrap().public_key()))
+            .is_err());
+    }
+
+    #[test]
+    fn agent_sans() {
+        let ca = create_ca(30).unwrap();
+        let localhost = IpAddr::from([127, 0, 0, 1]);
+
+        let (dns_names, ip_addresses) = sans(cert(&ca, CertRole::Server).cert());
+        assert_eq!(dns_names, ["server.eu.nomad", "localhost"]);
+        assert_eq!(ip_addresses, [localhost]);
+
+        let (dns_names, ip_addresses) = sans(cert(&ca, CertRole::Client).cert());
+        assert_eq!(dns_names, ["client.eu.nomad", "localhost"]);
+        assert_eq!(ip_addresses, [localhost]);
+    }
+
+    #[test]
+    fn additional_sans() {
+        let ca = create_ca(30).unwrap();
+        let extra = IpAddr::from([10, 0, 0, 1]);
+        let leaf = create_cert(
+            &ca,
+            CertRole::Server,
+            "global",
+            &["nomad.example.com".to_string()],
+            &[extra],
+            30,
+        )
+        .unwrap();
+        let (dns_names, ip_addresses) = sans(leaf.cert());
+        assert_eq!(
+            dns_names,
+            ["server.global.nomad", "localhost", "nomad.example.com"]
+        );
+        assert_eq!(ip_addresses, [IpAddr::from([127, 0, 0, 1]), extra]);
+    }
+
+    #[test]
+    fn cli_sans() {
+        let ca = create_ca(30).unwrap();
+        let (dns_names, ip_addresses) = sans(cert(&ca, CertRole::Cli).cert());
+        assert_eq!(dns_names, ["cli.eu.nomad", "localhost"]);
+        assert!(ip_addresses.is_empty());
+    }
+
+    #[test]
+    fn extended_key_usages() {
+        let ca = create_ca(30).unwrap();
+        assert_eq!(ekus(cert(&ca, CertRole::Server).cert()), (true, true));
+        assert_eq!(ekus(cert(&ca, CertRole::Client).cert()), (true, true));
+        assert_eq!(ekus(cert(&ca, CertRole::Cli).cert()), (false, true));
+    }
+}
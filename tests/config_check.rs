@@ -0,0 +1,90 @@
+//! Tests for the exit status of `nomadutil config check`.
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+
+/// Get a fresh scratch directory for a test.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("nomadutil-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Run `nomadutil config check` against the configuration directory `dir`.
+fn config_check(dir: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_nomadutil"))
+        .args(["config", "check", "--config-dir"])
+        .arg(dir)
+        .env("XDG_CONFIG_HOME", dir)
+        .output()
+        .unwrap()
+}
+
+/// Check the configuration file `name` with `contents`.
+///
+/// Returns whether the check succeeded and what it printed to stdout.
+fn check(test: &str, name: &str, contents: &str) -> (bool, String) {
+    let dir = scratch_dir(test);
+    let config_dir = dir.join("nomad.d");
+    fs::create_dir(&config_dir).unwrap();
+    fs::write(config_dir.join(name), contents).unwrap();
+
+    let output = config_check(&config_dir);
+    let stdout = String::from_utf8(output.stdout)
+        .unwrap()
+        .replace(&format!("{}/", config_dir.display()), "");
+
+    fs::remove_dir_all(&dir).unwrap();
+    (output.status.success(), stdout)
+}
+
+#[test]
+fn valid_hcl() {
+    let hcl = "data_dir = \"/opt/nomad\"\n\nserver {\n  enabled = true\n}\n";
+    let (success, stdout) = check("config-check-valid-hcl", "nomad.hcl", hcl);
+    assert!(success);
+    assert_eq!(
+        stdout,
+        "nomad.hcl:3:1: warning: server without bootstrap_expect, it will not bootstrap a cluster\n"
+    );
+}
+
+#[test]
+fn invalid_hcl() {
+    let (success, stdout) = check(
+        "config-check-invalid-hcl",
+        "nomad.hcl",
+        "data_dir = \"nomad\"\n",
+    );
+    assert!(!success);
+    assert_eq!(
+        stdout,
+        "nomad.hcl:1:1: error: data_dir \"nomad\" is not an absolute path\n"
+    );
+}
+
+#[test]
+fn valid_json() {
+    let (success, stdout) = check(
+        "config-check-valid-json",
+        "nomad.json",
+        r#"{"data_dir": "/opt/nomad"}"#,
+    );
+    assert!(success);
+    assert_eq!(stdout, "");
+}
+
+#[test]
+fn invalid_json() {
+    let (success, stdout) = check(
+        "config-check-invalid-json",
+        "nomad.json",
+        "{\"data_dir\": \"/opt/nomad\",}",
+    );
+    assert!(!success);
+    assert_eq!(stdout, "nomad.json:1:27: error: trailing comma\n");
+}
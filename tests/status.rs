@@ -0,0 +1,76 @@
+//! Tests for the output of `nomadutil status`.
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+
+/// A manifest with a single plain installation.
+const MANIFEST: &str = r#"{
+  "installs": [
+    {
+      "binary": "/usr/local/bin/nomad",
+      "version": "1.0.1",
+      "installed_at": "2021-01-01T00:00:00Z",
+      "source": "https://releases.hashicorp.com/nomad/1.0.1/nomad_1.0.1_linux_amd64.zip",
+      "zip_sha256": null,
+      "binary_sha256": "00",
+      "checked_sums": true,
+      "signed_by": null,
+      "versioned": false,
+      "service_file": "/etc/systemd/system/nomad.service",
+      "files": ["/usr/local/bin/nomad", "/etc/systemd/system/nomad.service"]
+    }
+  ]
+}
+"#;
+
+/// Get a fresh scratch directory for a test.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("nomadutil-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Run `nomadutil status --json` against the manifest at `state_file`.
+fn status_json(dir: &Path, state_file: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_nomadutil"))
+        .args(["status", "--json"])
+        .env("XDG_CONFIG_HOME", dir)
+        .env("NOMADUTIL_STATE_FILE", state_file)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn status_json_is_only_json() {
+    let dir = scratch_dir("status-json");
+    let state_file = dir.join("state.json");
+    fs::write(&state_file, MANIFEST).unwrap();
+
+    let output = status_json(&dir, &state_file);
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let value: serde_json::Value = serde_json::from_str(stdout.as_str()).unwrap();
+    assert_eq!(value["installs"][0]["version"], "1.0.1");
+    assert_eq!(value["installs"][0]["versioned"], false);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn status_json_without_manifest() {
+    let dir = scratch_dir("status-json-empty");
+    let state_file = dir.join("missing.json");
+
+    let output = status_json(&dir, &state_file);
+    assert!(output.status.success());
+
+    let value: serde_json::Value = serde_json::from_slice(output.stdout.as_slice()).unwrap();
+    assert_eq!(value["installs"], serde_json::json!([]));
+
+    fs::remove_dir_all(&dir).unwrap();
+}